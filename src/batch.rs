@@ -0,0 +1,543 @@
+use crate::{
+    config::Backup,
+    finder::{self, FileMatch, Finder, ReplaceReport, SearchParams},
+    search,
+};
+use anyhow::{Result, anyhow, bail};
+use crossbeam::channel::bounded;
+use crossterm::style::Stylize as _;
+use similar::TextDiff;
+use std::{io::Write, path::Path};
+use tracing::debug;
+
+// Search to completion, calling f with each file that matched
+fn for_each_match(
+    finder: &Finder,
+    params: SearchParams,
+    mut f: impl FnMut(FileMatch) -> Result<()>,
+) -> Result<()> {
+    let (tx, rx) = bounded(0);
+    let searcher = finder.clone();
+    let handle = std::thread::spawn(move || search::search(searcher, params, tx));
+
+    for finding in rx {
+        f(finding)?;
+    }
+
+    handle
+        .join()
+        .map_err(|_| anyhow!("Search thread panicked"))?
+}
+
+// Search to completion, calling f with the path, original text, replaced text, and number of
+// replacements of each match
+fn for_each_replacement(
+    finder: Finder,
+    params: SearchParams,
+    replacement: &str,
+    mut f: impl FnMut(&Path, &str, &str, usize) -> Result<()>,
+) -> Result<()> {
+    for_each_match(&finder, params, |finding| {
+        let path = &finding.path;
+        debug!("Replacing in {path:?}");
+        let Some(text) = finder::read_text(path)? else {
+            return Ok(());
+        };
+        let (replaced, count) = finder.replace_counted(path, &text, replacement)?;
+        f(path, &text, &replaced, count)
+    })
+}
+
+// Search and replace without user interaction, returns the files changed.
+// If more than max_files files match, nothing is replaced and an error is returned.
+// Files are backed up first as configured by backup, and left alone if their backup exists.
+pub fn replace(
+    finder: Finder,
+    mut params: SearchParams,
+    replacement: &str,
+    max_files: Option<usize>,
+    backup: &Backup,
+) -> Result<ReplaceReport> {
+    if let Some(max) = max_files {
+        let mut paths = vec![];
+        // with its own stats, so the files it finds aren't counted twice
+        let counting = SearchParams {
+            stats: Default::default(),
+            ..params.clone()
+        };
+        for_each_match(&finder.clone().first_match_only(), counting, |finding| {
+            paths.push(finding.path);
+            Ok(())
+        })?;
+        if paths.len() > max {
+            bail!(
+                "{} files match, more than confirm_threshold ({max}). Pass --force to replace them anyway",
+                paths.len()
+            );
+        }
+        // only the files found above, so the count holds
+        params.files = Some(paths);
+    }
+    let mut report = ReplaceReport::default();
+    let can_stream = finder.can_stream();
+    for_each_match(&finder, params, |finding| {
+        let path = finding.path;
+        debug!("Replacing in {path:?}");
+        if can_stream && !finder::is_hardlinked(&path)? {
+            if let Some((tmp, count)) = finder.replace_streaming(&path, replacement)?
+                && finder::backup(&path, backup)?
+            {
+                finder::persist(tmp, &path)?;
+                report.files.push((path, count));
+            }
+            return Ok(());
+        }
+        let Some(text) = finder::read_text(&path)? else {
+            return Ok(());
+        };
+        let (replaced, count) = finder.replace_counted(&path, &text, replacement)?;
+        if replaced != text && finder::backup(&path, backup)? {
+            std::fs::write(&path, replaced)?;
+            report.files.push((path, count));
+        }
+        Ok(())
+    })?;
+    Ok(report)
+}
+
+// Write a line of a unified diff, colored like `git diff` if color is set
+fn write_diff_line(out: &mut impl Write, line: &str, color: bool) -> std::io::Result<()> {
+    if !color {
+        return write!(out, "{line}");
+    }
+    // keep the newline outside the escape codes
+    let (text, newline) = match line.strip_suffix('\n') {
+        Some(text) => (text, "\n"),
+        None => (line, ""),
+    };
+    let styled = if text.starts_with("---") || text.starts_with("+++") {
+        text.bold()
+    } else if text.starts_with("@@") {
+        text.cyan()
+    } else if text.starts_with('-') {
+        text.red()
+    } else if text.starts_with('+') {
+        text.green()
+    } else {
+        return write!(out, "{line}");
+    };
+    write!(out, "{styled}{newline}")
+}
+
+// Like replace, but writes a unified diff to out instead of modifying files
+pub fn diff(
+    finder: Finder,
+    params: SearchParams,
+    replacement: &str,
+    out: &mut impl Write,
+    color: bool,
+) -> Result<usize> {
+    let mut changed = 0;
+    for_each_replacement(finder, params, replacement, |path, text, replaced, _| {
+        if replaced == text {
+            debug!("No change in {path:?}");
+            return Ok(());
+        }
+        let path = path.to_string_lossy();
+        let diff = TextDiff::from_lines(text, replaced)
+            .unified_diff()
+            .header(&path, &path)
+            .to_string();
+        for line in diff.split_inclusive('\n') {
+            write_diff_line(out, line, color)?;
+        }
+        changed += 1;
+        Ok(())
+    })?;
+    Ok(changed)
+}
+
+// Write the path of each file that matched, returns the number of files
+pub fn files_with_matches(
+    finder: Finder,
+    params: SearchParams,
+    out: &mut impl Write,
+    color: bool,
+) -> Result<usize> {
+    let mut matched = 0;
+    for_each_match(&finder.first_match_only(), params, |finding| {
+        let path = finding.path.display().to_string();
+        if color {
+            writeln!(out, "{}", path.magenta())?;
+        } else {
+            writeln!(out, "{path}")?;
+        }
+        matched += 1;
+        Ok(())
+    })?;
+    Ok(matched)
+}
+
+// Write one line of JSON per file that matched, returns the number of files
+pub fn json(finder: Finder, params: SearchParams, out: &mut impl Write) -> Result<usize> {
+    let mut matched = 0;
+    for_each_match(&finder, params, |finding| {
+        serde_json::to_writer(&mut *out, &finding)?;
+        writeln!(out)?;
+        matched += 1;
+        Ok(())
+    })?;
+    Ok(matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::finder::RegexParams;
+    use pretty_assertions::assert_eq;
+
+    fn params(path: &Path) -> SearchParams {
+        SearchParams {
+            paths: vec![path.into()],
+            types: ignore::types::TypesBuilder::new()
+                .add_defaults()
+                .build()
+                .unwrap(),
+            threads: 1,
+            ..Default::default()
+        }
+    }
+
+    fn finder(pattern: &str) -> Finder {
+        Finder::new(
+            pattern,
+            &RegexParams {
+                ignore_case: false,
+                multi_line: false,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "foo bar\nbar foo\n").unwrap();
+        std::fs::write(tmp.path().join("b.txt"), "bar\n").unwrap();
+        std::fs::write(tmp.path().join("c.txt"), "FOO\n").unwrap();
+
+        let report = replace(
+            finder("foo"),
+            params(tmp.path()),
+            "baz",
+            None,
+            &Backup::Enabled(false),
+        )
+        .unwrap();
+        assert_eq!(report.files, [(tmp.path().join("a.txt"), 2)]);
+        assert_eq!(
+            report.to_string(),
+            format!(
+                "{}: 2 replacements\n2 replacements in 1 file",
+                tmp.path().join("a.txt").display()
+            )
+        );
+
+        let read = |name| std::fs::read_to_string(tmp.path().join(name)).unwrap();
+        assert_eq!(read("a.txt"), "baz bar\nbar baz\n");
+        assert_eq!(read("b.txt"), "bar\n");
+        assert_eq!(read("c.txt"), "FOO\n");
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace_backup() {
+        let multi_line = Finder::new(
+            "foo",
+            &RegexParams {
+                multi_line: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        // whether or not the replacement streams
+        for finder in [finder("foo"), multi_line] {
+            let tmp = tempfile::tempdir().unwrap();
+            std::fs::write(tmp.path().join("a.txt"), "foo\n").unwrap();
+            std::fs::write(tmp.path().join("b.txt"), "foo\n").unwrap();
+            // an existing backup is never overwritten, and its file is left alone
+            std::fs::write(tmp.path().join("b.txt.orig"), "existing").unwrap();
+
+            let backup = Backup::Suffix(".orig".into());
+            let report = replace(finder, params(tmp.path()), "bar", None, &backup).unwrap();
+            assert_eq!(report.files, [(tmp.path().join("a.txt"), 1)]);
+
+            let read = |name| std::fs::read_to_string(tmp.path().join(name)).unwrap();
+            assert_eq!(read("a.txt"), "bar\n");
+            assert_eq!(read("a.txt.orig"), "foo\n");
+            assert_eq!(read("b.txt"), "foo\n");
+            assert_eq!(read("b.txt.orig"), "existing");
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_replace_links() {
+        let multi_line = Finder::new(
+            "foo",
+            &RegexParams {
+                multi_line: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        // whether or not the replacement streams
+        for finder in [finder("foo"), multi_line] {
+            let tmp = tempfile::tempdir().unwrap();
+            let path = |name| tmp.path().join(name);
+            std::fs::write(path("real.txt"), "foo\n").unwrap();
+            std::os::unix::fs::symlink("real.txt", path("symlink.txt")).unwrap();
+            std::fs::write(path("other.txt"), "foo\n").unwrap();
+            std::fs::hard_link(path("other.txt"), path("hardlink.txt")).unwrap();
+
+            let params = SearchParams {
+                files: Some(vec![path("symlink.txt"), path("hardlink.txt")]),
+                ..params(tmp.path())
+            };
+            replace(finder, params, "bar", None, &Backup::Enabled(false)).unwrap();
+
+            // the links are kept, and the files they link to replaced
+            let meta = std::fs::symlink_metadata(path("symlink.txt")).unwrap();
+            assert!(meta.file_type().is_symlink());
+            let read = |name| std::fs::read_to_string(path(name)).unwrap();
+            assert_eq!(read("real.txt"), "bar\n");
+            assert_eq!(read("hardlink.txt"), "bar\n");
+            assert_eq!(read("other.txt"), "bar\n");
+        }
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace_max_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            std::fs::write(tmp.path().join(name), "foo\n").unwrap();
+        }
+        let read = |name| std::fs::read_to_string(tmp.path().join(name)).unwrap();
+
+        let err = replace(
+            finder("foo"),
+            params(tmp.path()),
+            "bar",
+            Some(2),
+            &Backup::Enabled(false),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3 files match, more than confirm_threshold (2). Pass --force to replace them anyway"
+        );
+        assert_eq!(read("a.txt"), "foo\n", "nothing is replaced");
+
+        let params = params(tmp.path());
+        let stats = params.stats.clone();
+        let report = replace(
+            finder("foo"),
+            params,
+            "bar",
+            Some(3),
+            &Backup::Enabled(false),
+        )
+        .unwrap();
+        assert_eq!(report.files.len(), 3);
+        assert_eq!(read("a.txt"), "bar\n");
+        let summary = stats.summary();
+        assert_eq!(
+            (
+                summary.matches,
+                summary.files_matched,
+                summary.files_searched
+            ),
+            (3, 3, 3),
+            "files are counted once"
+        );
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace_trailing_newline() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "bar\nfoo\n").unwrap();
+        std::fs::write(tmp.path().join("b.txt"), "bar\nfoo").unwrap();
+        std::fs::write(tmp.path().join("c.py"), "bar\nfoo(1)\n").unwrap();
+        std::fs::write(tmp.path().join("d.py"), "bar\nfoo(1)").unwrap();
+
+        let report = replace(
+            finder("foo$"),
+            params(tmp.path()),
+            "baz\n",
+            None,
+            &Backup::Enabled(false),
+        )
+        .unwrap();
+        assert_eq!(report.files.len(), 2);
+        let report = replace(
+            finder("foo($A)"),
+            params(tmp.path()),
+            "baz($A)",
+            None,
+            &Backup::Enabled(false),
+        )
+        .unwrap();
+        assert_eq!(
+            report.files,
+            [(tmp.path().join("c.py"), 1), (tmp.path().join("d.py"), 1)]
+        );
+
+        let read = |name| std::fs::read_to_string(tmp.path().join(name)).unwrap();
+        assert_eq!(read("a.txt"), "bar\nbaz\n\n");
+        assert_eq!(read("b.txt"), "bar\nbaz");
+        assert_eq!(read("c.py"), "bar\nbaz(1)\n");
+        assert_eq!(read("d.py"), "bar\nbaz(1)");
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace_non_utf8() {
+        let tmp = tempfile::tempdir().unwrap();
+        // the matched line is valid UTF-8, but the rest of the file is latin-1
+        std::fs::write(tmp.path().join("a.txt"), b"foo\ncaf\xe9\n").unwrap();
+        std::fs::write(tmp.path().join("b.txt"), "foo\n").unwrap();
+
+        let report = replace(
+            finder("foo"),
+            params(tmp.path()),
+            "baz",
+            None,
+            &Backup::Enabled(false),
+        )
+        .unwrap();
+        assert_eq!(report.files, [(tmp.path().join("b.txt"), 1)]);
+
+        let read = |name| std::fs::read(tmp.path().join(name)).unwrap();
+        assert_eq!(read("a.txt"), b"foo\ncaf\xe9\n");
+        assert_eq!(read("b.txt"), b"baz\n");
+        assert!(logs_contain("not valid UTF-8"));
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_diff() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "foo\nbar\n").unwrap();
+        std::fs::write(tmp.path().join("b.txt"), "bar\nfoo").unwrap();
+        // replacement produces identical content, so is skipped
+        std::fs::write(tmp.path().join("c.txt"), "baz\n").unwrap();
+
+        let mut out = vec![];
+        let changed = diff(
+            finder("foo|baz"),
+            params(tmp.path()),
+            "baz",
+            &mut out,
+            false,
+        )
+        .unwrap();
+        assert_eq!(changed, 2);
+
+        let tmp_str = tmp.path().to_str().unwrap();
+        let out = String::from_utf8(out).unwrap().replace(tmp_str, "<TMP>");
+        assert_eq!(
+            out,
+            "\
+--- <TMP>/a.txt
++++ <TMP>/a.txt
+@@ -1,2 +1,2 @@
+-foo
++baz
+ bar
+--- <TMP>/b.txt
++++ <TMP>/b.txt
+@@ -1,2 +1,2 @@
+ bar
+-foo
+\\ No newline at end of file
++baz
+\\ No newline at end of file
+"
+        );
+
+        // nothing was written
+        let read = |name| std::fs::read_to_string(tmp.path().join(name)).unwrap();
+        assert_eq!(read("a.txt"), "foo\nbar\n");
+        assert_eq!(read("b.txt"), "bar\nfoo");
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_diff_color() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "foo\nbar\n").unwrap();
+
+        let mut out = vec![];
+        diff(finder("foo"), params(tmp.path()), "baz", &mut out, true).unwrap();
+
+        let tmp_str = tmp.path().to_str().unwrap();
+        let out = String::from_utf8(out).unwrap().replace(tmp_str, "<TMP>");
+        assert_eq!(
+            out,
+            "\
+\x1b[1m--- <TMP>/a.txt\x1b[0m
+\x1b[1m+++ <TMP>/a.txt\x1b[0m
+\x1b[38;5;14m@@ -1,2 +1,2 @@\x1b[39m
+\x1b[38;5;9m-foo\x1b[39m
+\x1b[38;5;10m+baz\x1b[39m
+ bar
+"
+        );
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_files_with_matches() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "foo\nfoo\n").unwrap();
+        std::fs::write(tmp.path().join("b.txt"), "bar\n").unwrap();
+        std::fs::write(tmp.path().join("c.txt"), "bar foo\n").unwrap();
+
+        let mut out = vec![];
+        let matched =
+            files_with_matches(finder("foo"), params(tmp.path()), &mut out, false).unwrap();
+        assert_eq!(matched, 2);
+
+        let tmp_str = tmp.path().to_str().unwrap();
+        let out = String::from_utf8(out).unwrap().replace(tmp_str, "<TMP>");
+        assert_eq!(out, "<TMP>/a.txt\n<TMP>/c.txt\n");
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_json() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "foo bar foo\nbar\n").unwrap();
+        std::fs::write(tmp.path().join("b.txt"), "bar\n").unwrap();
+
+        let mut out = vec![];
+        let matched = json(finder("foo"), params(tmp.path()), &mut out).unwrap();
+        assert_eq!(matched, 1);
+
+        let tmp_str = tmp.path().to_str().unwrap();
+        let out = String::from_utf8(out).unwrap().replace(tmp_str, "<TMP>");
+        assert_eq!(
+            out,
+            r#"{"type":"match","path":"<TMP>/a.txt","lines":[{"number":1,"text":"foo bar foo\n","offset":0,"ranges":[{"start":0,"end":3},{"start":8,"end":11}]}]}
+"#
+        );
+
+        // nothing was written
+        let read = |name| std::fs::read_to_string(tmp.path().join(name)).unwrap();
+        assert_eq!(read("a.txt"), "foo bar foo\nbar\n");
+    }
+}
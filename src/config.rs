@@ -1,31 +1,265 @@
-use std::{collections::HashMap, fmt::Display, str::FromStr};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use anyhow::{Context, bail};
+use anyhow::{Context, anyhow, bail};
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ignore::types::{Types, TypesBuilder};
 use ratatui::style::{Color, Modifier, Style};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
+#[serde(try_from = "String")]
 pub enum Action {
     Noop,
     Exit,
     Confirm,
+    ReplaceFile,
+    SkipFile,
+    CancelSearch,
     ToggleSearchReplace,
     ToggleIgnoreCase,
     ToggleMultiLine,
+    ToggleWholeWord,
+    ToggleFixedString,
+    ToggleSearchMode,
+    ToggleInvertMatch,
+    ToggleRespectIgnore,
+    ToggleHidden,
     CursorLeft,
     CursorRight,
+    CursorWordLeft,
+    CursorWordRight,
     CursorHome,
     CursorEnd,
     DeleteChar,
     DeleteCharBackward,
+    TransposeChar,
     DeleteWord,
     DeleteToEndOfLine,
     DeleteLine,
+    Yank,
+    InputUndo,
+    InputRedo,
+    InsertNewline,
     ScrollDown,
     ScrollUp,
     ScrollTop,
+    ScrollBottom,
+    PageDown,
+    PageUp,
+    ScrollLeft,
+    ScrollRight,
+    ToggleWrap,
+    ToggleDiffView,
+    CycleSort,
+    FilterResults,
+    NextMatch,
+    PrevMatch,
+    ToggleMatch,
+    OpenInEditor,
+    YankPath,
+    YankMatch,
+    HistoryPrev,
+    HistoryNext,
+    Paste,
+}
+
+impl Action {
+    pub const ALL: &[Action] = &[
+        Action::Noop,
+        Action::Exit,
+        Action::Confirm,
+        Action::ReplaceFile,
+        Action::SkipFile,
+        Action::CancelSearch,
+        Action::ToggleSearchReplace,
+        Action::ToggleIgnoreCase,
+        Action::ToggleMultiLine,
+        Action::ToggleWholeWord,
+        Action::ToggleFixedString,
+        Action::ToggleSearchMode,
+        Action::ToggleInvertMatch,
+        Action::ToggleRespectIgnore,
+        Action::ToggleHidden,
+        Action::CursorLeft,
+        Action::CursorRight,
+        Action::CursorWordLeft,
+        Action::CursorWordRight,
+        Action::CursorHome,
+        Action::CursorEnd,
+        Action::DeleteChar,
+        Action::DeleteCharBackward,
+        Action::TransposeChar,
+        Action::DeleteWord,
+        Action::DeleteToEndOfLine,
+        Action::DeleteLine,
+        Action::Yank,
+        Action::InputUndo,
+        Action::InputRedo,
+        Action::InsertNewline,
+        Action::ScrollDown,
+        Action::ScrollUp,
+        Action::ScrollTop,
+        Action::ScrollBottom,
+        Action::PageDown,
+        Action::PageUp,
+        Action::ScrollLeft,
+        Action::ScrollRight,
+        Action::ToggleWrap,
+        Action::ToggleDiffView,
+        Action::CycleSort,
+        Action::FilterResults,
+        Action::NextMatch,
+        Action::PrevMatch,
+        Action::ToggleMatch,
+        Action::OpenInEditor,
+        Action::YankPath,
+        Action::YankMatch,
+        Action::HistoryPrev,
+        Action::HistoryNext,
+        Action::Paste,
+    ];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::Noop => "Do nothing, used to unbind a default key",
+            Action::Exit => "Exit without performing any replacement",
+            Action::Confirm => "Prompt to exit and perform replacements",
+            Action::ReplaceFile => "Perform replacements in the selected file only",
+            Action::SkipFile => "Remove the selected file from the results",
+            Action::CancelSearch => "Stop searching, keeping the results found so far",
+            Action::ToggleSearchReplace => "Switch focus between the search and replace inputs",
+            Action::ToggleIgnoreCase => "Toggle ignore case flag",
+            Action::ToggleMultiLine => "Toggle matches spanning multiple lines",
+            Action::ToggleWholeWord => "Toggle only matching whole words",
+            Action::ToggleFixedString => "Toggle matching the pattern literally",
+            Action::ToggleSearchMode => "Cycle between auto, regex, and ast-grep patterns",
+            Action::ToggleInvertMatch => "Toggle showing lines that don't match",
+            Action::ToggleRespectIgnore => "Toggle searching ignored and hidden files",
+            Action::ToggleHidden => "Toggle searching hidden files",
+            Action::CursorLeft => "Move cursor left one character",
+            Action::CursorRight => "Move cursor right one character",
+            Action::CursorWordLeft => "Move cursor to the start of the previous word",
+            Action::CursorWordRight => "Move cursor to the end of the next word",
+            Action::CursorHome => "Move cursor to beginning of line",
+            Action::CursorEnd => "Move cursor to end of line",
+            Action::DeleteChar => "Delete character at cursor position",
+            Action::DeleteCharBackward => "Delete character before cursor (backspace)",
+            Action::TransposeChar => "Swap the characters before and at the cursor",
+            Action::DeleteWord => "Delete word before cursor",
+            Action::DeleteToEndOfLine => "Delete from cursor to end of line",
+            Action::DeleteLine => "Delete entire line",
+            Action::Yank => "Insert text removed by the last word or line deletion",
+            Action::InputUndo => "Undo the last edit to the input",
+            Action::InputRedo => "Redo the last edit undone in the input",
+            Action::InsertNewline => "Insert a newline, e.g. for a multi-line replacement",
+            Action::ScrollDown => "Scroll results down one file",
+            Action::ScrollUp => "Scroll results up one file",
+            Action::ScrollTop => "Scroll to the first result",
+            Action::ScrollBottom => "Scroll to the last result",
+            Action::PageDown => "Scroll results down a page",
+            Action::PageUp => "Scroll results up a page",
+            Action::ScrollLeft => "Scroll results left",
+            Action::ScrollRight => "Scroll results right",
+            Action::ToggleWrap => "Toggle wrapping long result lines",
+            Action::ToggleDiffView => "Toggle showing original text beside its replacement",
+            Action::CycleSort => "Sort results by path, modified time, or match count",
+            Action::FilterResults => "Focus the filter to narrow the current results",
+            Action::NextMatch => "Select the next match",
+            Action::PrevMatch => "Select the previous match",
+            Action::ToggleMatch => "Accept or reject replacing the selected match",
+            Action::OpenInEditor => "Open the selected match in $EDITOR",
+            Action::YankPath => "Copy the path of the selected match to the clipboard",
+            Action::YankMatch => "Copy the selected match to the clipboard",
+            Action::HistoryPrev => "Recall the previous pattern from history",
+            Action::HistoryNext => "Recall the next pattern from history",
+            Action::Paste => "Insert text from the clipboard",
+        }
+    }
+}
+
+impl TryFrom<String> for Action {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let names = Action::ALL.iter().map(|a| (a.to_string(), *a));
+        if let Some((_, action)) = names.clone().find(|(name, _)| *name == s) {
+            return Ok(action);
+        }
+        let names: Vec<_> = names.map(|(name, _)| name).collect();
+        match closest(&s, names.iter().map(String::as_str)) {
+            Some(name) => bail!("Unknown action '{s}', did you mean '{name}'?"),
+            None => bail!("Unknown action '{s}', see `lasr --list-actions`"),
+        }
+    }
+}
+
+// The name used in the config, e.g. "scroll_top"
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match toml::Value::try_from(self) {
+            Ok(toml::Value::String(s)) => f.write_str(&s),
+            _ => Err(std::fmt::Error),
+        }
+    }
+}
+
+// Levenshtein distance, counting the single-character edits to turn a into b
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let sub = prev[j] + usize::from(ca != *cb);
+            cur.push(sub.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+// The candidate most like name, if any are close enough to be a likely typo
+fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let max = (name.chars().count() / 3).max(2);
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|&(d, _)| d <= max)
+        .min_by_key(|&(d, _)| d)
+        .map(|(_, c)| c)
+}
+
+#[test]
+fn test_closest() {
+    assert_eq!(edit_distance("scrol_top", "scroll_top"), 1);
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+    assert_eq!(edit_distance("", "abc"), 3);
+    assert_eq!(
+        closest("scrol_tpo", ["scroll_top", "scroll_up"]),
+        Some("scroll_top")
+    );
+    assert_eq!(closest("escape", ["esc", "enter"]), None);
+    assert_eq!(closest("entr", ["esc", "enter"]), Some("enter"));
+}
+
+#[test]
+fn test_action_names() {
+    let readme = include_str!("../README.md");
+    for action in Action::ALL {
+        let name = action.to_string();
+        let parsed: Action = toml::Value::String(name.clone()).try_into().unwrap();
+        assert_eq!(&parsed, action);
+        assert!(
+            readme.contains(&format!("| `{name}` ")),
+            "{name} is not in the README"
+        );
+    }
 }
 
 #[derive(Debug, PartialOrd, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
@@ -56,6 +290,24 @@ impl From<KeyEvent> for Key {
     }
 }
 
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("backspace", KeyCode::Backspace),
+    ("enter", KeyCode::Enter),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+    ("tab", KeyCode::Tab),
+    ("backtab", KeyCode::BackTab),
+    ("delete", KeyCode::Delete),
+    ("insert", KeyCode::Insert),
+    ("esc", KeyCode::Esc),
+];
+
 impl TryFrom<String> for Key {
     type Error = anyhow::Error;
 
@@ -77,35 +329,22 @@ impl TryFrom<String> for Key {
             }
             None => (KeyModifiers::empty(), s.as_str()),
         };
-        let code = match code {
-            "backspace" => KeyCode::Backspace,
-            "enter" => KeyCode::Enter,
-            "left" => KeyCode::Left,
-            "right" => KeyCode::Right,
-            "up" => KeyCode::Up,
-            "down" => KeyCode::Down,
-            "home" => KeyCode::Home,
-            "end" => KeyCode::End,
-            "pageup" => KeyCode::PageUp,
-            "pagedown" => KeyCode::PageDown,
-            "tab" => KeyCode::Tab,
-            "backtab" => KeyCode::BackTab,
-            "delete" => KeyCode::Delete,
-            "insert" => KeyCode::Insert,
-            "esc" => KeyCode::Esc,
-            _ => {
-                if code.len() == 1 && code.is_ascii() {
-                    KeyCode::Char(code.chars().next().unwrap())
-                } else if let Some(f) = s.strip_prefix("f") {
-                    // Fn-key
-                    let f: u8 = f
-                        .parse()
-                        .with_context(|| format!("'{code}' is not a valid F-key"))?;
-                    KeyCode::F(f)
-                } else {
-                    bail!("Unknown key code '{code}'");
-                }
+        let code = match KEY_NAMES.iter().find(|(name, _)| *name == code) {
+            Some(&(_, code)) => code,
+            None if code.len() == 1 && code.is_ascii() => {
+                KeyCode::Char(code.chars().next().unwrap())
             }
+            // Fn-key
+            None => match code.strip_prefix("f").map(str::parse) {
+                Some(Ok(f)) => KeyCode::F(f),
+                Some(Err(_)) if code[1..].chars().all(|c| c.is_ascii_digit()) => {
+                    bail!("'{code}' is not a valid F-key")
+                }
+                _ => match closest(code, KEY_NAMES.iter().map(|(name, _)| *name)) {
+                    Some(name) => bail!("Unknown key code '{code}', did you mean '{name}'?"),
+                    None => bail!("Unknown key code '{code}'"),
+                },
+            },
         };
 
         Ok(Key { code, modifiers })
@@ -177,15 +416,114 @@ impl Display for Key {
     }
 }
 
+// One or more keys pressed in order, e.g. "g g" or "c-x,c-k"
+#[derive(Debug, PartialOrd, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+#[serde(into = "String")]
+pub struct KeySeq(Vec<Key>);
+
+impl KeySeq {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn starts_with(&self, prefix: &KeySeq) -> bool {
+        self.0.starts_with(&prefix.0)
+    }
+}
+
+impl From<Key> for KeySeq {
+    fn from(value: Key) -> Self {
+        Self(vec![value])
+    }
+}
+
+impl From<KeyEvent> for KeySeq {
+    fn from(value: KeyEvent) -> Self {
+        Key::from(value).into()
+    }
+}
+
+impl From<&[KeyEvent]> for KeySeq {
+    fn from(value: &[KeyEvent]) -> Self {
+        Self(value.iter().map(|&k| k.into()).collect())
+    }
+}
+
+impl TryFrom<String> for KeySeq {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        // a lone key may itself be a space or comma, e.g. "a-,"
+        if let Ok(key) = Key::try_from(s.clone()) {
+            return Ok(key.into());
+        }
+        s.split([' ', ','])
+            .filter(|k| !k.is_empty())
+            .map(|k| Key::try_from(k.to_string()))
+            .collect::<Result<_, _>>()
+            .map(Self)
+            .map_err(|e| anyhow!("Invalid key '{s}': {e}"))
+    }
+}
+
+#[test]
+fn test_key_seq_from_string() {
+    let seq = |s: &str| KeySeq::try_from(s.to_string()).unwrap();
+    let g = Key::char('g', KeyModifiers::empty());
+    assert_eq!(seq("g"), KeySeq(vec![g]));
+    assert_eq!(seq("g g"), KeySeq(vec![g, g]));
+    assert_eq!(
+        seq("c-x,c-k"),
+        KeySeq(vec![
+            Key::char('x', KeyModifiers::CONTROL),
+            Key::char('k', KeyModifiers::CONTROL)
+        ])
+    );
+    assert_eq!(seq("a-,"), KeySeq(vec![Key::char(',', KeyModifiers::ALT)]));
+    assert_eq!(
+        seq(" "),
+        KeySeq(vec![Key::char(' ', KeyModifiers::empty())])
+    );
+    assert_eq!(String::from(seq("g,  g")), "g g");
+    assert!(KeySeq::try_from("g x-a".to_string()).is_err());
+}
+
+impl From<KeySeq> for String {
+    fn from(val: KeySeq) -> Self {
+        val.0
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl Display for KeySeq {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&String::from(self.clone()))
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
-pub struct KeyMap(HashMap<Key, Action>);
+pub struct KeyMap(HashMap<KeySeq, Action>);
 
+// Either the name of a preset like `theme = "monochrome"`, or a table of styles.
+// A table may start from a preset with `preset = "name"` and override some of its styles.
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
-#[serde(default)]
+#[serde(try_from = "toml::Value")]
 pub struct Theme {
     pub base: Style,
     pub find: Style,
+    pub capture: Style, // meta-variables within an ast-grep match, on top of find
     pub replace: Style,
+    pub selected: Style,
+    pub error: Style,
+    pub syntax: Option<String>, // name of a syntect theme, defaults to terminal ANSI colors
 }
 
 impl Default for Theme {
@@ -200,22 +538,327 @@ impl Default for Theme {
                 add_modifier: Modifier::CROSSED_OUT,
                 ..Default::default()
             },
+            capture: Style {
+                add_modifier: Modifier::UNDERLINED,
+                ..Default::default()
+            },
             replace: Style {
                 fg: Some(Color::Green),
                 add_modifier: Modifier::BOLD,
                 ..Default::default()
             },
+            selected: Style {
+                add_modifier: Modifier::REVERSED,
+                ..Default::default()
+            },
+            error: Style {
+                fg: Some(Color::Red),
+                ..Default::default()
+            },
+            syntax: None,
+        }
+    }
+}
+
+const THEME_PRESETS: &[&str] = &["default", "high-contrast", "monochrome", "solarized"];
+
+impl Theme {
+    pub fn preset(name: &str) -> Option<Theme> {
+        let style = |fg, add_modifier| Style {
+            fg,
+            add_modifier,
+            ..Default::default()
+        };
+        match name {
+            "default" => Some(Theme::default()),
+            "high-contrast" => Some(Theme {
+                base: Style {
+                    fg: Some(Color::White),
+                    bg: Some(Color::Black),
+                    ..Default::default()
+                },
+                find: Style {
+                    bg: Some(Color::LightRed),
+                    ..style(Some(Color::Black), Modifier::CROSSED_OUT)
+                },
+                capture: style(None, Modifier::UNDERLINED),
+                replace: Style {
+                    bg: Some(Color::LightGreen),
+                    ..style(Some(Color::Black), Modifier::BOLD)
+                },
+                selected: style(None, Modifier::REVERSED | Modifier::BOLD),
+                error: style(Some(Color::LightRed), Modifier::BOLD),
+                syntax: None,
+            }),
+            "monochrome" => Some(Theme {
+                base: style(Some(Color::Reset), Modifier::empty()),
+                find: style(None, Modifier::CROSSED_OUT | Modifier::DIM),
+                capture: style(None, Modifier::BOLD),
+                replace: style(None, Modifier::BOLD | Modifier::UNDERLINED),
+                selected: style(None, Modifier::REVERSED),
+                error: style(None, Modifier::BOLD),
+                syntax: None,
+            }),
+            "solarized" => Some(Theme {
+                base: style(Some(Color::Rgb(0x83, 0x94, 0x96)), Modifier::empty()),
+                find: style(Some(Color::Rgb(0xdc, 0x32, 0x2f)), Modifier::CROSSED_OUT),
+                capture: style(Some(Color::Rgb(0xcb, 0x4b, 0x16)), Modifier::UNDERLINED),
+                replace: style(Some(Color::Rgb(0x85, 0x99, 0x00)), Modifier::BOLD),
+                selected: style(None, Modifier::REVERSED),
+                error: style(Some(Color::Rgb(0xdc, 0x32, 0x2f)), Modifier::empty()),
+                syntax: Some("Solarized (dark)".into()),
+            }),
+            _ => None,
+        }
+    }
+}
+
+// The styles given in a theme table, each overriding the preset (or default) theme
+#[derive(Deserialize)]
+struct ThemeOverrides {
+    preset: Option<String>,
+    base: Option<Style>,
+    find: Option<Style>,
+    capture: Option<Style>,
+    replace: Option<Style>,
+    selected: Option<Style>,
+    error: Option<Style>,
+    syntax: Option<String>,
+}
+
+impl TryFrom<toml::Value> for Theme {
+    type Error = anyhow::Error;
+
+    fn try_from(value: toml::Value) -> Result<Self, Self::Error> {
+        let preset = |name: &str| {
+            Theme::preset(name).with_context(|| {
+                format!("Unknown theme '{name}', expected one of {THEME_PRESETS:?}")
+            })
+        };
+        let overrides: ThemeOverrides = match value {
+            toml::Value::String(name) => return preset(&name),
+            value @ toml::Value::Table(_) => value.try_into()?,
+            v => bail!("Invalid theme: {v}"),
+        };
+        let theme = match overrides.preset {
+            Some(name) => preset(&name)?,
+            None => Theme::default(),
+        };
+        Ok(Theme {
+            base: overrides.base.unwrap_or(theme.base),
+            find: overrides.find.unwrap_or(theme.find),
+            capture: overrides.capture.unwrap_or(theme.capture),
+            replace: overrides.replace.unwrap_or(theme.replace),
+            selected: overrides.selected.unwrap_or(theme.selected),
+            error: overrides.error.unwrap_or(theme.error),
+            syntax: overrides.syntax.or(theme.syntax),
+        })
+    }
+}
+
+// Either `backup = true` to use the default suffix, or a custom suffix like `backup = ".orig"`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Backup {
+    Enabled(bool),
+    Suffix(String),
+}
+
+impl Backup {
+    pub fn suffix(&self) -> Option<&str> {
+        match self {
+            Backup::Enabled(true) => Some(".bak"),
+            Backup::Enabled(false) => None,
+            Backup::Suffix(s) => Some(s),
+        }
+    }
+}
+
+// How files are ordered in the results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sort {
+    #[default]
+    Path,
+    Modified, // most recently modified first
+    Matches,  // most matches first
+}
+
+impl Sort {
+    pub fn next(self) -> Self {
+        match self {
+            Sort::Path => Sort::Modified,
+            Sort::Modified => Sort::Matches,
+            Sort::Matches => Sort::Path,
         }
     }
 }
 
+impl Display for Sort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Sort::Path => "path",
+            Sort::Modified => "modified time",
+            Sort::Matches => "match count",
+        })
+    }
+}
+
+// How to search files that look binary, i.e. contain a NUL byte
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Binary {
+    #[default]
+    Quit, // stop searching a file at the first NUL byte
+    Convert, // treat NUL bytes as line endings
+    Text,    // search the file as if it were text
+}
+
+impl FromStr for Binary {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "quit" => Binary::Quit,
+            "convert" => Binary::Convert,
+            "text" => Binary::Text,
+            _ => bail!("Unknown binary mode '{s}', expected quit, convert, or text"),
+        })
+    }
+}
+
+// Overrides for files of one type, from a `[filetypes.<name>]` section
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct FileTypeConfig {
+    pub ignore_case: Option<bool>,
+    pub whole_word: Option<bool>,
+    pub fixed_string: Option<bool>,
+    pub ast: Option<bool>, // false to treat patterns like `$X` as a regex rather than ast-grep
+    pub syntax_theme: Option<String>,
+}
+
+// A FileTypeConfig along with the files it applies to
+#[derive(Debug, Clone)]
+pub struct FileType {
+    pub name: String,
+    pub config: FileTypeConfig,
+    types: Types,
+}
+
+impl FileType {
+    // name is either an extension like ".md", or a type from `lasr --type-list` like "rust"
+    pub fn new(name: &str, config: FileTypeConfig) -> anyhow::Result<Self> {
+        let mut builder = TypesBuilder::new();
+        builder.add_defaults();
+        Self::with_types(name, config, builder)
+    }
+
+    // Like new, but type names are looked up in the given builder
+    fn with_types(
+        name: &str,
+        config: FileTypeConfig,
+        mut builder: TypesBuilder,
+    ) -> anyhow::Result<Self> {
+        if name.starts_with('.') {
+            builder = TypesBuilder::new();
+            builder.add("ext", &format!("*{name}"))?;
+            builder.select("ext");
+        } else {
+            builder.select(name);
+        }
+        let types = builder
+            .build()
+            .with_context(|| format!("Invalid filetype '{name}'"))?;
+        Ok(Self {
+            name: name.into(),
+            config,
+            types,
+        })
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        self.types.matched(path, false).is_whitelist()
+    }
+}
+
+// A size in bytes, given as a number or a string with a K, M, or G suffix, e.g. "10M"
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "toml::Value")]
+#[serde(into = "u64")]
+pub struct FileSize(pub u64);
+
+impl FromStr for FileSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (num, mult) = match s.char_indices().last() {
+            Some((i, 'k' | 'K')) => (&s[..i], 1 << 10),
+            Some((i, 'm' | 'M')) => (&s[..i], 1 << 20),
+            Some((i, 'g' | 'G')) => (&s[..i], 1 << 30),
+            _ => (s, 1),
+        };
+        let num: u64 = num
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid file size: {s}"))?;
+        num.checked_mul(mult)
+            .map(FileSize)
+            .with_context(|| format!("File size too large: {s}"))
+    }
+}
+
+impl TryFrom<toml::Value> for FileSize {
+    type Error = anyhow::Error;
+
+    fn try_from(value: toml::Value) -> Result<Self, Self::Error> {
+        match value {
+            toml::Value::Integer(i) => Ok(FileSize(
+                i.try_into()
+                    .with_context(|| format!("Invalid file size: {i}"))?,
+            )),
+            toml::Value::String(s) => s.parse(),
+            v => bail!("Invalid file size: {v}"),
+        }
+    }
+}
+
+impl From<FileSize> for u64 {
+    fn from(val: FileSize) -> Self {
+        val.0
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 #[serde(default)]
 pub struct Config {
     pub theme: Theme,
-    pub keys: HashMap<Key, Action>,
+    pub keys: HashMap<KeySeq, Action>,
     pub auto_pairs: bool,
     pub threads: usize,
+    pub backup: Backup,
+    pub history_size: usize,
+    pub debounce: u64, // milliseconds to wait after typing a pattern before searching
+    pub max_depth: Option<usize>,
+    pub ignore_files: Vec<String>, // names of extra ignore files to respect in each directory
+    pub git_root: Option<PathBuf>, // walk from here, so its ignore rules apply whatever is searched
+    pub max_filesize: Option<FileSize>,
+    pub max_matches_per_file: Option<usize>, // stop collecting a file's matches after this many
+    pub binary: Binary,                      // how to search files containing a NUL byte
+    pub confirm_threshold: usize, // replacing in more files than this needs extra confirmation
+    pub highlight: bool,
+    pub wrap: bool,
+    pub column: bool, // show the column of the first match beside each line number
+    pub tab_width: usize, // columns between tab stops in results, 0 to leave tabs as-is
+    pub diff_view: bool, // show the original and replaced text side by side
+    pub sort: Sort,
+    pub syntax_dir: Option<PathBuf>, // extra .sublime-syntax files for highlighting
+    pub theme_dir: Option<PathBuf>,  // extra .tmTheme files for highlighting
+    pub watch_config: bool,          // reload the theme and keys when the config file changes
+    pub stats: bool,                 // show statistics when a search completes
+    pub filetypes: BTreeMap<String, FileTypeConfig>,
+    pub types: BTreeMap<String, Vec<String>>, // extra file types for -t, name to globs
 }
 
 impl Default for Config {
@@ -224,15 +867,26 @@ impl Default for Config {
             theme: Theme::default(),
             keys: [
                 ("enter", Action::Confirm),
+                ("a-enter", Action::ReplaceFile),
+                ("a-k", Action::SkipFile),
+                ("c-x", Action::CancelSearch),
                 ("esc", Action::Exit),
                 ("c-c", Action::Exit),
                 ("tab", Action::ToggleSearchReplace),
                 ("c-s", Action::ToggleIgnoreCase),
                 ("c-l", Action::ToggleMultiLine),
+                ("a-w", Action::ToggleWholeWord),
+                ("a-x", Action::ToggleFixedString),
+                ("a-m", Action::ToggleSearchMode),
+                ("a-v", Action::ToggleInvertMatch),
+                ("a-i", Action::ToggleRespectIgnore),
+                ("a-h", Action::ToggleHidden),
                 ("left", Action::CursorLeft),
                 ("c-b", Action::CursorLeft),
                 ("right", Action::CursorRight),
                 ("c-f", Action::CursorRight),
+                ("a-b", Action::CursorWordLeft),
+                ("a-f", Action::CursorWordRight),
                 ("home", Action::CursorHome),
                 ("c-a", Action::CursorHome),
                 ("end", Action::CursorEnd),
@@ -240,26 +894,126 @@ impl Default for Config {
                 ("backspace", Action::DeleteCharBackward),
                 ("c-h", Action::DeleteCharBackward),
                 ("c-d", Action::DeleteChar),
+                ("c-t", Action::TransposeChar),
                 ("c-w", Action::DeleteWord),
                 ("c-k", Action::DeleteToEndOfLine),
                 ("c-u", Action::DeleteLine),
+                ("c-y", Action::Yank),
+                ("c-z", Action::InputUndo),
+                ("c-r", Action::InputRedo),
+                ("c-j", Action::InsertNewline),
                 ("c-n", Action::ScrollDown),
                 ("c-p", Action::ScrollUp),
                 ("c-g", Action::ScrollTop),
+                ("a-g", Action::ScrollBottom),
+                ("pagedown", Action::PageDown),
+                ("pageup", Action::PageUp),
+                ("a-left", Action::ScrollLeft),
+                ("a-right", Action::ScrollRight),
+                ("a-z", Action::ToggleWrap),
+                ("a-d", Action::ToggleDiffView),
+                ("a-s", Action::CycleSort),
+                ("a-/", Action::FilterResults),
+                ("a-n", Action::NextMatch),
+                ("a-p", Action::PrevMatch),
+                ("a-r", Action::ToggleMatch),
+                ("c-o", Action::OpenInEditor),
+                ("a-y", Action::YankPath),
+                ("a-c", Action::YankMatch),
+                ("up", Action::HistoryPrev),
+                ("down", Action::HistoryNext),
+                ("c-v", Action::Paste),
             ]
             .map(|(k, v)| (k.to_string().try_into().unwrap(), v))
             .into(),
             auto_pairs: true,
             threads: 0,
+            backup: Backup::Enabled(false),
+            history_size: 100,
+            debounce: 150,
+            max_depth: None,
+            ignore_files: vec![".lasrignore".into()],
+            git_root: None,
+            max_filesize: None,
+            max_matches_per_file: None,
+            binary: Binary::Quit,
+            confirm_threshold: 100,
+            highlight: true,
+            wrap: false,
+            column: false,
+            tab_width: 4,
+            diff_view: false,
+            sort: Sort::Path,
+            syntax_dir: None,
+            theme_dir: None,
+            watch_config: false,
+            stats: false,
+            filetypes: BTreeMap::new(),
+            types: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    // Read the config at path, using the defaults if it doesn't exist
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(s) => s
+                .parse()
+                .with_context(|| format!("Invalid config {path:?}")),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(err).with_context(|| format!("Failed to read {path:?}")),
+        }
+    }
+
+    // The filetypes section, in the order they should be checked against a path.
+    // Extensions come before type names, so e.g. ".h" can override "c".
+    pub fn filetypes(&self) -> anyhow::Result<Vec<FileType>> {
+        let (exts, names): (Vec<_>, Vec<_>) = self
+            .filetypes
+            .iter()
+            .partition(|(name, _)| name.starts_with('.'));
+        exts.into_iter()
+            .chain(names)
+            .map(|(name, config)| FileType::with_types(name, config.clone(), self.types()?))
+            .collect()
+    }
+
+    // The built-in file types plus those from the types section.
+    // A type with the same name as a built-in one adds to its globs.
+    pub fn types(&self) -> anyhow::Result<TypesBuilder> {
+        let mut builder = TypesBuilder::new();
+        for (name, globs) in &self.types {
+            for glob in globs {
+                builder
+                    .add(name, glob)
+                    .with_context(|| format!("Invalid type '{name}'"))?;
+            }
         }
+        builder.add_defaults();
+        Ok(builder)
     }
 }
 
+// Condense a toml error to one line, with the location and text it occurred at
+fn toml_error(src: &str, err: toml::de::Error) -> anyhow::Error {
+    let message = err.message().trim();
+    let Some(span) = err.span() else {
+        return anyhow!("{message}");
+    };
+    let before = &src[..span.start];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    let column = before[line_start..].chars().count() + 1;
+    let text = src[line_start..].lines().next().unwrap_or_default().trim();
+    anyhow!("{message} at line {line}, column {column}: {text}")
+}
+
 impl FromStr for Config {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut c: Config = toml::from_str(s)?;
+        let mut c: Config = toml::from_str(s).map_err(|e| toml_error(s, e))?;
         let base = Self::default();
         // merge in any keys that the user didn't override
         for (k, v) in base.keys {
@@ -296,7 +1050,8 @@ mod tests {
             Key {
                 code: KeyCode::Char('x'),
                 modifiers: KeyModifiers::CONTROL,
-            },
+            }
+            .into(),
             Action::Exit,
         );
 
@@ -314,15 +1069,241 @@ mod tests {
                         add_modifier: Modifier::BOLD,
                         ..Default::default()
                     },
+                    capture: Style {
+                        add_modifier: Modifier::UNDERLINED,
+                        ..Default::default()
+                    },
                     replace: Style {
                         fg: Some(Color::Green),
                         add_modifier: Modifier::BOLD,
                         ..Default::default()
                     },
+                    selected: Style {
+                        add_modifier: Modifier::REVERSED,
+                        ..Default::default()
+                    },
+                    error: Style {
+                        fg: Some(Color::Red),
+                        ..Default::default()
+                    },
+                    syntax: None,
                 },
                 auto_pairs: false,
                 threads: 0,
+                backup: Backup::Enabled(false),
+                history_size: 100,
+                debounce: 150,
+                max_depth: None,
+                ignore_files: vec![".lasrignore".into()],
+                git_root: None,
+                max_filesize: None,
+                max_matches_per_file: None,
+                binary: Binary::Quit,
+                confirm_threshold: 100,
+                highlight: true,
+                wrap: false,
+                column: false,
+                tab_width: 4,
+                diff_view: false,
+                sort: Sort::Path,
+                syntax_dir: None,
+                theme_dir: None,
+                watch_config: false,
+                stats: false,
+                filetypes: BTreeMap::new(),
+                types: BTreeMap::new(),
             }
         )
     }
+
+    #[test]
+    fn test_config_types() {
+        let c: Config = toml::toml! {
+            [types]
+            web = ["*.html", "*.css"]
+            rust = ["*.ron"]
+
+            [filetypes.web]
+            fixed_string = true
+        }
+        .to_string()
+        .parse()
+        .unwrap();
+
+        let mut types = c.types().unwrap();
+        types.select("web");
+        let types = types.build().unwrap();
+        assert!(types.matched("index.html", false).is_whitelist());
+        assert!(types.matched("style.css", false).is_whitelist());
+        assert!(types.matched("main.rs", false).is_ignore());
+
+        // extends the built-in type
+        let mut types = c.types().unwrap();
+        types.select("rust");
+        let types = types.build().unwrap();
+        assert!(types.matched("main.rs", false).is_whitelist());
+        assert!(types.matched("data.ron", false).is_whitelist());
+
+        let filetypes = c.filetypes().unwrap();
+        assert!(filetypes[0].matches(Path::new("index.html")));
+
+        let c: Config = "types = { \"a-b\" = [\"*.x\"] }".parse().unwrap();
+        let Err(err) = c.types() else {
+            panic!("expected an invalid type name");
+        };
+        assert_eq!(err.to_string(), "Invalid type 'a-b'");
+    }
+
+    #[test]
+    fn test_config_filetypes() {
+        let c: Config = toml::toml! {
+            [filetypes.rust]
+            ignore_case = true
+
+            [filetypes.".rs"]
+            whole_word = true
+
+            [filetypes.".md"]
+            fixed_string = true
+        }
+        .to_string()
+        .parse()
+        .unwrap();
+
+        let filetypes = c.filetypes().unwrap();
+        let names: Vec<_> = filetypes.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, [".md", ".rs", "rust"]);
+        let find = |path| {
+            filetypes
+                .iter()
+                .find(|f| f.matches(Path::new(path)))
+                .map(|f| f.name.as_str())
+        };
+        assert_eq!(find("src/main.rs"), Some(".rs"));
+        assert_eq!(find("README.md"), Some(".md"));
+        assert_eq!(find("notes.txt"), None);
+        assert_eq!(
+            filetypes[0].config,
+            FileTypeConfig {
+                fixed_string: Some(true),
+                ..Default::default()
+            }
+        );
+
+        let c: Config = "[filetypes.nope]".parse().unwrap();
+        assert!(c.filetypes().is_err());
+    }
+
+    #[test]
+    fn test_config_sort() {
+        let c: Config = "sort = \"matches\"".parse().unwrap();
+        assert_eq!(c.sort, Sort::Matches);
+        assert_eq!(c.sort.next(), Sort::Path);
+        assert!("sort = \"size\"".parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn test_config_theme_preset() {
+        let c: Config = "theme = \"monochrome\"".parse().unwrap();
+        assert_eq!(c.theme, Theme::preset("monochrome").unwrap());
+
+        let c: Config = toml::toml! {
+            [theme]
+            preset = "solarized"
+            find.fg = "blue"
+        }
+        .to_string()
+        .parse()
+        .unwrap();
+        let solarized = Theme::preset("solarized").unwrap();
+        assert_eq!(
+            c.theme,
+            Theme {
+                find: Style {
+                    fg: Some(Color::Blue),
+                    ..Default::default()
+                },
+                ..solarized
+            }
+        );
+
+        let err = "theme = \"nope\"".parse::<Config>().unwrap_err();
+        assert!(err.to_string().contains("Unknown theme 'nope'"), "{err}");
+        for name in THEME_PRESETS {
+            assert!(Theme::preset(name).is_some(), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let err = |s: &str| s.parse::<Config>().unwrap_err().to_string();
+        assert_eq!(
+            err("[keys]\nc-x = \"scrol_top\"\n"),
+            "Unknown action 'scrol_top', did you mean 'scroll_top'? \
+             at line 2, column 7: c-x = \"scrol_top\""
+        );
+        assert_eq!(
+            err("[keys]\nc-x = \"frobnicate\""),
+            "Unknown action 'frobnicate', see `lasr --list-actions` \
+             at line 2, column 7: c-x = \"frobnicate\""
+        );
+        assert_eq!(
+            err("[keys]\nq-x = \"exit\""),
+            "Invalid key 'q-x': Unknown modifier 'q' at line 2, column 1: q-x = \"exit\""
+        );
+        assert_eq!(
+            err("[keys]\n\"g escp\" = \"exit\""),
+            "Invalid key 'g escp': Unknown key code 'escp', did you mean 'esc'? \
+             at line 2, column 1: \"g escp\" = \"exit\""
+        );
+        assert_eq!(
+            err("[keys]\nf99x = \"exit\""),
+            "Invalid key 'f99x': Unknown key code 'f99x' at line 2, column 1: f99x = \"exit\""
+        );
+        assert_eq!(
+            err("[keys]\nf999 = \"exit\""),
+            "Invalid key 'f999': 'f999' is not a valid F-key at line 2, column 1: f999 = \"exit\""
+        );
+    }
+
+    #[test]
+    fn test_config_max_filesize() {
+        let c: Config = "max_filesize = 1024".parse().unwrap();
+        assert_eq!(c.max_filesize, Some(FileSize(1024)));
+
+        let c: Config = "max_filesize = \"10M\"".parse().unwrap();
+        assert_eq!(c.max_filesize, Some(FileSize(10 * 1024 * 1024)));
+
+        assert_eq!("2k".parse::<FileSize>().unwrap(), FileSize(2048));
+        assert_eq!("1G".parse::<FileSize>().unwrap(), FileSize(1 << 30));
+        assert!("10X".parse::<FileSize>().is_err());
+        assert!("M".parse::<FileSize>().is_err());
+        assert!("max_filesize = -1".parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn test_config_binary() {
+        assert_eq!(Config::default().binary, Binary::Quit);
+        let c: Config = "binary = \"text\"".parse().unwrap();
+        assert_eq!(c.binary, Binary::Text);
+        assert!("binary = \"skip\"".parse::<Config>().is_err());
+
+        assert_eq!("convert".parse::<Binary>().unwrap(), Binary::Convert);
+        assert_eq!(
+            "skip".parse::<Binary>().unwrap_err().to_string(),
+            "Unknown binary mode 'skip', expected quit, convert, or text"
+        );
+    }
+
+    #[test]
+    fn test_config_backup() {
+        let c: Config = "backup = true".parse().unwrap();
+        assert_eq!(c.backup.suffix(), Some(".bak"));
+
+        let c: Config = "backup = false".parse().unwrap();
+        assert_eq!(c.backup.suffix(), None);
+
+        let c: Config = "backup = \".orig\"".parse().unwrap();
+        assert_eq!(c.backup.suffix(), Some(".orig"));
+    }
 }
@@ -1,50 +1,401 @@
+use crate::config::{Backup, Binary, FileType};
+use crate::search::Stats;
 use anyhow::{Context, Result, bail};
-use ast_grep_core::{Pattern, language::Language};
+use ast_grep_core::{
+    Doc, Matcher, Node, NodeMatch, Pattern,
+    language::Language,
+    meta_var::{MetaVarEnv, MetaVariable, Underlying},
+    replacer::{Content, Replacer as AstReplacer, TemplateFix},
+};
 use ast_grep_language::{LanguageExt, SupportLang};
 use grep::{
+    matcher::LineTerminator,
     regex::{RegexMatcher, RegexMatcherBuilder},
-    searcher::{BinaryDetection, Searcher, SearcherBuilder, sinks},
+    searcher::{BinaryDetection, Searcher, SearcherBuilder, Sink, SinkContext, SinkMatch},
 };
-use regex::{Regex, RegexBuilder};
+use regex::{Captures, Regex, RegexBuilder, Replacer};
+use serde::Serialize;
 use std::{
-    ops::Range,
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Display,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    ops::{Range, RangeInclusive},
     path::{Path, PathBuf},
-    sync::OnceLock,
+    str::FromStr,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
 };
-use tracing::trace;
+use tempfile::NamedTempFile;
+use tracing::{debug, trace, warn};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize)]
 pub struct LineMatch {
     pub number: u64,
     pub text: String,
 
-    // where we matched within the string
+    // byte offset of text within the file
+    pub offset: usize,
+
+    // where we matched within the string, empty for context lines
     pub ranges: Vec<Range<usize>>,
+
+    // where the meta-variables of an ast-grep pattern matched within the string, if any
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub captures: Vec<Range<usize>>,
 }
 
-#[derive(Debug, PartialEq)]
+// Serialized for --json, with a "type" field like ripgrep's output
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename = "match")]
 pub struct FileMatch {
     pub path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lang: Option<SupportLang>, // the language the file was parsed as for an ast-grep pattern
     pub lines: Vec<LineMatch>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool, // more matches were found than max_matches, and dropped
+}
+
+// The files changed by replacing all matches, with the number of matches replaced in each
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ReplaceReport {
+    pub files: Vec<(PathBuf, usize)>,
+}
+
+impl ReplaceReport {
+    pub fn replacements(&self) -> usize {
+        self.files.iter().map(|(_, n)| n).sum()
+    }
+
+    // The total replacements and files, like "3 replacements in 2 files"
+    pub fn summary(&self) -> String {
+        format!(
+            "{} in {}",
+            count(self.replacements(), "replacement", "replacements"),
+            count(self.files.len(), "file", "files")
+        )
+    }
+
+    // Each file changed, like "src/main.rs: 2 replacements"
+    pub fn file_lines(&self) -> impl Iterator<Item = String> {
+        self.files.iter().map(|(path, n)| {
+            format!(
+                "{}: {}",
+                path.display(),
+                count(*n, "replacement", "replacements")
+            )
+        })
+    }
+}
+
+// n followed by the singular or plural noun, like "1 file" or "2 files"
+pub(crate) fn count(n: usize, one: &str, many: &str) -> String {
+    format!("{n} {}", if n == 1 { one } else { many })
+}
+
+impl Display for ReplaceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in self.file_lines() {
+            writeln!(f, "{line}")?;
+        }
+        write!(f, "{}", self.summary())
+    }
+}
+
+// Read a file for replacement, or None if it is not valid UTF-8 (e.g. latin-1 or UTF-16)
+pub fn read_text(path: &Path) -> Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(err) if err.kind() == std::io::ErrorKind::InvalidData => {
+            warn!("Skipping {path:?}, not valid UTF-8");
+            Ok(None)
+        }
+        Err(err) => Err(err).with_context(|| format!("Reading {path:?}")),
+    }
+}
+
+// Copy path to a backup if enabled, returns false if the file should be left alone
+pub fn backup(path: &Path, backup: &Backup) -> Result<bool> {
+    let Some(suffix) = backup.suffix() else {
+        return Ok(true);
+    };
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(suffix);
+    let backup = PathBuf::from(backup);
+    debug!("Backing up {path:?} to {backup:?}");
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&backup);
+    match file {
+        Ok(mut file) => {
+            std::io::copy(&mut File::open(path)?, &mut file)
+                .with_context(|| format!("Failed to back up {path:?}"))?;
+            Ok(true)
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+            warn!("Backup {backup:?} already exists, skipping {path:?}");
+            Ok(false)
+        }
+        Err(err) => Err(err).with_context(|| format!("Failed to back up {path:?}")),
+    }
+}
+
+// Whether the file at path has other hard links, which renaming a new file over it would split off
+pub fn is_hardlinked(path: &Path) -> Result<bool> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt as _;
+        let meta = std::fs::metadata(path).with_context(|| format!("Reading {path:?}"))?;
+        Ok(meta.nlink() > 1)
+    }
+    #[cfg(not(unix))]
+    Ok(false)
+}
+
+// Move a file from Finder::replace_streaming over path, or over the file it links to
+pub fn persist(tmp: NamedTempFile, path: &Path) -> Result<()> {
+    let target = std::fs::canonicalize(path).with_context(|| format!("Resolving {path:?}"))?;
+    tmp.persist(&target)
+        .with_context(|| format!("Writing {target:?}"))?;
+    Ok(())
 }
 
+// The path results for SearchParams::stdin are reported under
+pub const STDIN_PATH: &str = "<stdin>";
+
 #[derive(Debug, Clone)]
 pub struct SearchParams {
     pub paths: Vec<PathBuf>,
     pub types: ignore::types::Types,
     pub threads: usize,
+    pub no_ignore: bool, // search files ignored by .gitignore/.ignore, and hidden files
+    pub hidden: bool,    // search hidden files, while still respecting ignore files
+    pub ignore_files: Vec<String>, // names of extra ignore files to respect, like .lasrignore
+    pub ignore_paths: Vec<PathBuf>, // ignore files that apply to every path searched
+    pub git_root: Option<PathBuf>, // treat as the repository root for ignore rules, see search
+    pub max_depth: Option<usize>,
+    pub max_filesize: Option<u64>, // skip files larger than this many bytes
+    pub files: Option<Vec<PathBuf>>, // search exactly these files, rather than walking paths
+    pub stdin: Option<Arc<String>>, // search this text as a single file at STDIN_PATH instead
+    pub stats: Arc<Stats>,         // counted while searching, shared by clones of the params
+    pub sorted: bool, // send results in path order, even when searching on multiple threads
 }
 
-#[derive(Debug, Clone)]
+impl Default for SearchParams {
+    fn default() -> Self {
+        Self {
+            paths: vec![".".into()],
+            types: ignore::types::Types::empty(),
+            threads: 0,
+            no_ignore: false,
+            hidden: false,
+            ignore_files: vec![],
+            ignore_paths: vec![],
+            git_root: None,
+            max_depth: None,
+            max_filesize: None,
+            files: None,
+            stdin: None,
+            stats: Arc::default(),
+            sorted: false,
+        }
+    }
+}
+
+// Whether the pattern is a regex or an ast-grep pattern
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SearchMode {
+    #[default]
+    Auto, // ast-grep if the pattern has a meta-variable like $X, otherwise regex
+    Regex,
+    Ast,
+}
+
+impl SearchMode {
+    pub fn next(self) -> Self {
+        match self {
+            SearchMode::Auto => SearchMode::Regex,
+            SearchMode::Regex => SearchMode::Ast,
+            SearchMode::Ast => SearchMode::Auto,
+        }
+    }
+}
+
+impl Display for SearchMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SearchMode::Auto => "auto",
+            SearchMode::Regex => "regex",
+            SearchMode::Ast => "ast",
+        })
+    }
+}
+
+impl FromStr for SearchMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "auto" => SearchMode::Auto,
+            "regex" => SearchMode::Regex,
+            "ast" => SearchMode::Ast,
+            _ => bail!("Unknown search mode '{s}', expected auto, regex, or ast"),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct RegexParams {
+    pub mode: SearchMode,
     pub ignore_case: bool,
     pub multi_line: bool,
+    pub whole_word: bool,
+    pub fixed_string: bool, // match the pattern literally rather than as a regex
+    pub invert_match: bool, // find lines that don't match the pattern
+    pub before_context: usize, // lines to show before each match
+    pub after_context: usize, // lines to show after each match
+    pub lines: Option<RangeInclusive<u64>>, // only show lines with these numbers
+    pub max_matches: Option<usize>, // stop collecting a file's matches after this many
+    pub binary: Binary,     // how to search files containing a NUL byte
+}
+
+// Parse a range of line numbers like "100:200". Either end may be omitted, e.g. "100:".
+pub fn parse_lines(s: &str) -> Result<RangeInclusive<u64>> {
+    let Some((start, end)) = s.split_once(':') else {
+        bail!("Expected a range of lines like 100:200, got '{s}'");
+    };
+    let parse = |n: &str, default| match n {
+        "" => Ok(default),
+        n => n
+            .parse::<u64>()
+            .with_context(|| format!("Invalid line number '{n}'")),
+    };
+    let (start, end) = (parse(start, 1)?, parse(end, u64::MAX)?);
+    if start == 0 || start > end {
+        bail!("Invalid range of lines '{s}', lines start at 1");
+    }
+    Ok(start..=end)
+}
+
+#[test]
+fn test_parse_lines() {
+    assert_eq!(parse_lines("100:200").unwrap(), 100..=200);
+    assert_eq!(parse_lines("5:5").unwrap(), 5..=5);
+    assert_eq!(parse_lines("100:").unwrap(), 100..=u64::MAX);
+    assert_eq!(parse_lines(":200").unwrap(), 1..=200);
+    let err = |s| parse_lines(s).unwrap_err().to_string();
+    assert_eq!(
+        err("100"),
+        "Expected a range of lines like 100:200, got '100'"
+    );
+    assert_eq!(err("a:2"), "Invalid line number 'a'");
+    assert_eq!(err("0:2"), "Invalid range of lines '0:2', lines start at 1");
+    assert_eq!(err("3:2"), "Invalid range of lines '3:2', lines start at 1");
+}
+
+#[test]
+fn test_find_lines() {
+    let tmp = tempfile::tempdir().unwrap();
+    let txt = tmp.path().join("a.txt");
+    std::fs::write(&txt, "foo\nbar\nfoo\nbar\nfoo\n").unwrap();
+    let find = |pattern, params: RegexParams| {
+        Finder::new(pattern, &params)
+            .unwrap()
+            .find(&txt)
+            .unwrap()
+            .into_iter()
+            .map(|l| (l.number, !l.ranges.is_empty()))
+            .collect::<Vec<_>>()
+    };
+
+    let params = RegexParams {
+        lines: Some(2..=4),
+        ..Default::default()
+    };
+    assert_eq!(find("foo", params.clone()), [(3, true)]);
+    assert_eq!(find("bar", params.clone()), [(2, true), (4, true)]);
+
+    // context is cut off at the range
+    let with_context = RegexParams {
+        before_context: 1,
+        after_context: 1,
+        ..params.clone()
+    };
+    assert_eq!(
+        find("foo", with_context),
+        [(2, false), (3, true), (4, false)]
+    );
+
+    // multi-line matches are found if any of their lines are in range
+    let multi_line = |lines| RegexParams {
+        multi_line: true,
+        lines: Some(lines),
+        ..Default::default()
+    };
+    assert_eq!(find(r"foo\nbar\nfoo", multi_line(3..=4)), [(1, true)]);
+    assert_eq!(find(r"bar\nfoo", multi_line(1..=1)), []);
+
+    // first_match_only finds the first match in range
+    let mut finder = Finder::new("foo", &params).unwrap().first_match_only();
+    assert_eq!(finder.find(&txt).unwrap()[0].number, 3);
+
+    let py = tmp.path().join("a.py");
+    std::fs::write(&py, "foo(1)\nfoo(2)\nfoo(3)\n").unwrap();
+    let mut finder = Finder::new(
+        "foo($A)",
+        &RegexParams {
+            lines: Some(2..=2),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let found = finder.find(&py).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].text, "foo(2)");
+}
+
+// Whether any line of text, starting at line number, is within lines
+fn in_lines(number: u64, text: &str, lines: &Option<RangeInclusive<u64>>) -> bool {
+    let Some(lines) = lines else {
+        return true;
+    };
+    let last = number + text.lines().count().saturating_sub(1) as u64;
+    number <= *lines.end() && last >= *lines.start()
 }
 
 #[derive(Debug, Clone)]
 pub enum Finder {
     Regex(Box<RegexFinder>),
     Ast(AstFinder),
+    Typed(Box<TypedFinder>),
+}
+
+// Uses a differently configured finder for files matching a filetype from the config
+#[derive(Debug, Clone)]
+pub struct TypedFinder {
+    default: Finder,
+    typed: Vec<(FileType, Finder)>, // checked in order, the first match wins
+}
+
+impl TypedFinder {
+    fn for_path(&self, path: &Path) -> &Finder {
+        self.typed
+            .iter()
+            .find(|(t, _)| t.matches(path))
+            .map_or(&self.default, |(_, f)| f)
+    }
+
+    fn for_path_mut(&mut self, path: &Path) -> &mut Finder {
+        self.typed
+            .iter_mut()
+            .find(|(t, _)| t.matches(path))
+            .map_or(&mut self.default, |(_, f)| f)
+    }
 }
 
 fn is_ast_pattern(pattern: &str) -> bool {
@@ -53,6 +404,37 @@ fn is_ast_pattern(pattern: &str) -> bool {
     re.is_match(pattern)
 }
 
+#[test]
+fn test_search_mode() {
+    let finder = |pattern, mode| {
+        let params = RegexParams {
+            mode,
+            ..Default::default()
+        };
+        Finder::new(pattern, &params).unwrap().is_ast()
+    };
+    assert!(finder("$X", SearchMode::Auto));
+    assert!(!finder("foo", SearchMode::Auto));
+    assert!(!finder("$X", SearchMode::Regex));
+    assert!(finder("foo", SearchMode::Ast));
+
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("a.sh");
+    std::fs::write(&path, "echo $VAR\n").unwrap();
+    let params = RegexParams {
+        mode: SearchMode::Regex,
+        ..Default::default()
+    };
+    let mut finder = Finder::new(r"\$VAR", &params).unwrap();
+    assert_eq!(finder.find(&path).unwrap().len(), 1);
+
+    assert_eq!("ast".parse::<SearchMode>().unwrap(), SearchMode::Ast);
+    assert_eq!(
+        "foo".parse::<SearchMode>().unwrap_err().to_string(),
+        "Unknown search mode 'foo', expected auto, regex, or ast"
+    );
+}
+
 #[test]
 fn test_is_ast_pattern() {
     assert!(is_ast_pattern("let $X ="));
@@ -64,36 +446,288 @@ fn test_is_ast_pattern() {
 }
 
 impl Finder {
-    pub fn new(pattern: &str, params: &RegexParams) -> Option<Self> {
-        if is_ast_pattern(pattern) {
-            return Some(Self::Ast(AstFinder::new(pattern)));
-        }
-        match RegexFinder::new(pattern, params) {
-            Ok(f) => Some(Self::Regex(Box::new(f))),
-            Err(e) => {
-                trace!("Not a valid regex pattern: {pattern}: {e}");
-                None
+    // Fails with the reason the pattern is invalid, which may just be that it's not finished yet
+    pub fn new(pattern: &str, params: &RegexParams) -> Result<Self> {
+        if pattern.is_empty() {
+            bail!("The pattern is empty");
+        }
+        let ast = match params.mode {
+            SearchMode::Auto => is_ast_pattern(pattern),
+            SearchMode::Regex => false,
+            SearchMode::Ast => true,
+        };
+        if !params.fixed_string && ast {
+            if params.invert_match {
+                bail!("Invert match doesn't apply to ast-grep patterns");
             }
+            return Ok(Self::Ast(AstFinder {
+                lines: params.lines.clone(),
+                max_matches: params.max_matches,
+                ..AstFinder::new(pattern)?
+            }));
         }
+        Ok(Self::Regex(Box::new(RegexFinder::new(pattern, params)?)))
+    }
+
+    // Like new, but with the settings of the given filetypes applied to matching files.
+    // Filetype settings take precedence over params, even if they were given on the command line.
+    pub fn with_filetypes(
+        pattern: &str,
+        params: &RegexParams,
+        filetypes: &[FileType],
+    ) -> Result<Self> {
+        let default = Self::new(pattern, params)?;
+        if filetypes.is_empty() {
+            return Ok(default);
+        }
+        let typed = filetypes
+            .iter()
+            .map(|t| {
+                let c = &t.config;
+                let params = RegexParams {
+                    ignore_case: c.ignore_case.unwrap_or(params.ignore_case),
+                    whole_word: c.whole_word.unwrap_or(params.whole_word),
+                    fixed_string: c.fixed_string.unwrap_or(params.fixed_string),
+                    ..params.clone()
+                };
+                let finder = if c.ast == Some(false) {
+                    Self::Regex(Box::new(RegexFinder::new(pattern, &params)?))
+                } else {
+                    Self::new(pattern, &params)?
+                };
+                Ok((t.clone(), finder))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self::Typed(Box::new(TypedFinder { default, typed })))
+    }
+
+    // Make find stop at the first match in each file, for when only the paths are needed
+    pub fn first_match_only(self) -> Self {
+        match self {
+            Finder::Regex(mut f) => {
+                f.first_only = true;
+                Finder::Regex(f)
+            }
+            Finder::Ast(f) => Finder::Ast(AstFinder {
+                first_only: true,
+                ..f
+            }),
+            Finder::Typed(f) => {
+                let TypedFinder { default, typed } = *f;
+                Finder::Typed(Box::new(TypedFinder {
+                    default: default.first_match_only(),
+                    typed: typed
+                        .into_iter()
+                        .map(|(t, f)| (t, f.first_match_only()))
+                        .collect(),
+                }))
+            }
+        }
+    }
+
+    // The language path is parsed as, if searched with an ast-grep pattern
+    pub fn language(&self, path: &Path) -> Option<SupportLang> {
+        match self {
+            Finder::Regex(_) => None,
+            Finder::Ast(_) => SupportLang::from_path(path),
+            Finder::Typed(f) => f.for_path(path).language(path),
+        }
+    }
+
+    // How many files an ast-grep pattern couldn't search, so far
+    pub fn ast_skipped(&self) -> AstSkipped {
+        match self {
+            Finder::Regex(_) => AstSkipped::default(),
+            Finder::Ast(f) => AstSkipped {
+                no_lang: f.skipped.no_lang.load(Ordering::Relaxed),
+                invalid_pattern: f.skipped.invalid_pattern.load(Ordering::Relaxed),
+            },
+            Finder::Typed(f) => f.typed.iter().map(|(_, f)| f.ast_skipped()).fold(
+                f.default.ast_skipped(),
+                |a, b| AstSkipped {
+                    no_lang: a.no_lang + b.no_lang,
+                    invalid_pattern: a.invalid_pattern + b.invalid_pattern,
+                },
+            ),
+        }
+    }
+
+    // Whether the pattern is an ast-grep pattern, at least for files without a filetype
+    pub fn is_ast(&self) -> bool {
+        match self {
+            Finder::Regex(_) => false,
+            Finder::Ast(_) => true,
+            Finder::Typed(f) => f.default.is_ast(),
+        }
+    }
+
+    // Drop matches past max_matches, returning whether there were any. Finders stop once they
+    // collect one match too many, so this knows the file had more.
+    pub fn truncate(&self, lines: &mut Vec<LineMatch>) -> bool {
+        let max = match self {
+            Finder::Regex(f) => f.max_matches,
+            Finder::Ast(f) => f.max_matches,
+            Finder::Typed(f) => return f.default.truncate(lines),
+        };
+        let Some(mut remaining) = max else {
+            return false;
+        };
+        for i in 0..lines.len() {
+            let line = &mut lines[i];
+            if line.ranges.len() <= remaining {
+                remaining -= line.ranges.len();
+                continue;
+            }
+            line.ranges.truncate(remaining);
+            // keep the line only if it still has a match
+            lines.truncate(if remaining > 0 { i + 1 } else { i });
+            return true;
+        }
+        false
     }
 
     pub fn find(&mut self, path: &Path) -> Result<Vec<LineMatch>> {
         match self {
             Finder::Regex(f) => f.find(path),
             Finder::Ast(f) => f.find(path),
+            Finder::Typed(f) => f.for_path_mut(path).find(path),
+        }
+    }
+
+    // Like find, but searches text rather than reading it from path
+    pub fn find_text(&mut self, path: &Path, text: &str) -> Result<Vec<LineMatch>> {
+        match self {
+            Finder::Regex(f) => f.find_text(text),
+            Finder::Ast(f) => f.find_text(path, text),
+            Finder::Typed(f) => f.for_path_mut(path).find_text(path, text),
         }
     }
 
+    // Replace every match in the full text of the file at path
     pub fn replace(&self, path: &Path, text: &str, replacement: &str) -> Result<String> {
+        Ok(self.replace_counted(path, text, replacement)?.0)
+    }
+
+    // Like replace, but also returns the number of matches replaced
+    pub fn replace_counted(
+        &self,
+        path: &Path,
+        text: &str,
+        replacement: &str,
+    ) -> Result<(String, usize)> {
+        let (replaced, count) = self.replace_match_counted(path, text, replacement)?;
+        Ok((match_trailing_newline(text, replaced), count))
+    }
+
+    // Like replace, but for a single match within the file, so a trailing newline is kept as-is
+    pub fn replace_match(&self, path: &Path, text: &str, replacement: &str) -> Result<String> {
+        Ok(self.replace_match_counted(path, text, replacement)?.0)
+    }
+
+    fn replace_match_counted(
+        &self,
+        path: &Path,
+        text: &str,
+        replacement: &str,
+    ) -> Result<(String, usize)> {
         match self {
-            Finder::Regex(f) => f.replace(text, replacement),
-            Finder::Ast(f) => f.replace(path, text, replacement),
+            Finder::Regex(f) => Ok(f.replace_counted(text, replacement)),
+            Finder::Ast(f) => f.replace_counted(path, text, replacement),
+            Finder::Typed(f) => f
+                .for_path(path)
+                .replace_match_counted(path, text, replacement),
         }
     }
+
+    // Like replace_match, but for the match at range within text, which may need the text around it
+    pub fn replace_at(
+        &self,
+        path: &Path,
+        text: &str,
+        range: Range<usize>,
+        replacement: &str,
+    ) -> Result<String> {
+        match self {
+            Finder::Regex(f) => f.replace_at(text, range, replacement),
+            Finder::Ast(f) => Ok(f.replace_counted(path, &text[range], replacement)?.0),
+            Finder::Typed(f) => f.for_path(path).replace_at(path, text, range, replacement),
+        }
+    }
+
+    // Whether replace_streaming can be used, which requires that matches never span lines
+    pub fn can_stream(&self) -> bool {
+        match self {
+            Finder::Regex(f) => !f.multi_line,
+            Finder::Ast(_) => false,
+            Finder::Typed(f) => {
+                f.default.can_stream() && f.typed.iter().all(|(_, f)| f.can_stream())
+            }
+        }
+    }
+
+    // Replace line by line into a temp file beside path, so large files needn't fit in memory.
+    // Returns the file to move over path with persist, and number of matches replaced, or None if
+    // nothing changed or the file was skipped for not being UTF-8.
+    // Moving a file over a hard link splits it off, so check is_hardlinked first.
+    pub fn replace_streaming(
+        &self,
+        path: &Path,
+        replacement: &str,
+    ) -> Result<Option<(NamedTempFile, usize)>> {
+        let finder = match self {
+            Finder::Regex(finder) => finder,
+            Finder::Ast(_) => bail!("AST replacements cannot be streamed"),
+            Finder::Typed(f) => return f.for_path(path).replace_streaming(path, replacement),
+        };
+        if finder.multi_line {
+            bail!("Multi-line replacements cannot be streamed");
+        }
+
+        // a symlink is kept, replacing the file it points to
+        let target = std::fs::canonicalize(path).with_context(|| format!("Resolving {path:?}"))?;
+        let dir = target.parent().unwrap_or(Path::new("/"));
+        let mut tmp = NamedTempFile::new_in(dir)
+            .with_context(|| format!("Creating temp file for {path:?}"))?;
+        let file = File::open(path).with_context(|| format!("Reading {path:?}"))?;
+        let mut reader = BufReader::new(file);
+        let mut writer = BufWriter::new(tmp.as_file_mut());
+
+        let mut changed = false;
+        let mut count = 0;
+        let mut buf = vec![];
+        while reader.read_until(b'\n', &mut buf)? > 0 {
+            let Ok(line) = std::str::from_utf8(&buf) else {
+                warn!("Skipping {path:?}, not valid UTF-8");
+                return Ok(None);
+            };
+            let (replaced, n) = finder.replace_counted(line, replacement);
+            let replaced = match_trailing_newline(line, replaced);
+            changed |= replaced != line;
+            count += n;
+            writer.write_all(replaced.as_bytes())?;
+            buf.clear();
+        }
+        writer.flush()?;
+        drop(writer);
+
+        if !changed {
+            trace!("No change in {path:?}");
+            return Ok(None);
+        }
+        tmp.as_file()
+            .set_permissions(std::fs::metadata(path)?.permissions())?;
+        Ok(Some((tmp, count)))
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct RegexFinder {
+    fixed_string: bool,
+    multi_line: bool,
+    first_only: bool, // stop searching a file after its first match
+    lines: Option<RangeInclusive<u64>>, // only find matches on these lines
+    max_matches: Option<usize>, // see Finder::truncate
+    skip_non_utf8: bool, // skip lines that aren't UTF-8, rather than the whole file
     regex: Regex,
     matcher: RegexMatcher,
     searcher: Searcher,
@@ -101,24 +735,60 @@ pub struct RegexFinder {
 
 impl RegexFinder {
     fn new(pattern: &str, params: &RegexParams) -> Result<Self> {
-        let regex = RegexBuilder::new(pattern)
+        let pattern = if params.fixed_string {
+            &regex::escape(pattern)
+        } else {
+            pattern
+        };
+
+        // The matcher handles word boundaries itself, but the regex used for highlighting and
+        // replacing needs them in the pattern
+        let regex_pattern = if params.whole_word {
+            format!(r"\b(?:{pattern})\b")
+        } else {
+            pattern.to_string()
+        };
+        // Replacement is applied to the whole file, so treat ^ and $ as line boundaries like the
+        // searcher does. CRLF mode keeps the \r of a \r\n ending from being matched and replaced.
+        let regex = RegexBuilder::new(&regex_pattern)
             .case_insensitive(params.ignore_case)
+            .multi_line(true)
+            .crlf(true)
             .build()
             .with_context(|| format!("Invalid regex: {pattern}"))?;
 
         let matcher = RegexMatcherBuilder::new()
             .case_smart(false)
             .case_insensitive(params.ignore_case)
-            .multi_line(params.multi_line)
+            // ^ and $ match at line boundaries, the searcher may match against many lines at once
+            .multi_line(true)
+            // a CRLF line terminator forbids \n in the pattern, which multi-line mode needs
+            .crlf(!params.multi_line)
+            .word(params.whole_word)
             .build(pattern)
             .with_context(|| format!("Failed to compile searcher with params: {params:?}"))?;
 
         let searcher = SearcherBuilder::new()
-            .binary_detection(BinaryDetection::quit(0))
+            .binary_detection(match params.binary {
+                Binary::Quit => BinaryDetection::quit(0),
+                Binary::Convert => BinaryDetection::convert(0),
+                Binary::Text => BinaryDetection::none(),
+            })
             .multi_line(params.multi_line)
+            .invert_match(params.invert_match)
+            .before_context(params.before_context)
+            .after_context(params.after_context)
+            .line_terminator(LineTerminator::crlf())
             .build();
 
         Ok(Self {
+            fixed_string: params.fixed_string,
+            multi_line: params.multi_line,
+            first_only: false,
+            lines: params.lines.clone(),
+            max_matches: params.max_matches,
+            // binary files are rarely valid UTF-8 throughout, but may have matches worth showing
+            skip_non_utf8: params.binary != Binary::Quit,
             regex,
             matcher,
             searcher,
@@ -126,93 +796,1167 @@ impl RegexFinder {
     }
 
     fn find(&mut self, path: &Path) -> Result<Vec<LineMatch>> {
-        let mut lines = vec![];
-        self.searcher.search_path(
-            &self.matcher,
-            path,
-            sinks::UTF8(|number, text| {
-                lines.push(LineMatch {
-                    number,
-                    text: text.to_string(),
-                    ranges: self
-                        .regex
-                        .find_iter(text)
-                        .map(|m| m.start()..m.end())
-                        .collect(),
-                });
-                Ok(true)
-            }),
-        )?;
+        self.search(|searcher, matcher, sink| searcher.search_path(matcher, path, sink))
+    }
 
-        Ok(lines)
+    fn find_text(&mut self, text: &str) -> Result<Vec<LineMatch>> {
+        self.search(|searcher, matcher, sink| searcher.search_slice(matcher, text.as_bytes(), sink))
+    }
+
+    fn search(
+        &mut self,
+        search: impl FnOnce(&mut Searcher, &RegexMatcher, &mut LineSink) -> std::io::Result<()>,
+    ) -> Result<Vec<LineMatch>> {
+        let mut sink = LineSink {
+            regex: &self.regex,
+            lines: vec![],
+            first_only: self.first_only,
+            line_range: &self.lines,
+            max_matches: self.max_matches,
+            matches: 0,
+            skip_non_utf8: self.skip_non_utf8,
+        };
+        search(&mut self.searcher, &self.matcher, &mut sink)?;
+        Ok(sink.lines)
     }
 
     pub fn replace(&self, text: &str, replacement: &str) -> Result<String> {
-        Ok(self.regex.replace_all(text, replacement).to_string())
+        Ok(self.replace_counted(text, replacement).0)
+    }
+
+    // Like replace, but also returns the number of matches replaced
+    fn replace_counted(&self, text: &str, replacement: &str) -> (String, usize) {
+        // An empty match after a trailing newline, as from ^ or $, is on a line that doesn't exist
+        let end = text.ends_with('\n').then_some(text.len());
+        let mut expand = self.expander(replacement);
+        let mut count = 0;
+        let replaced = self
+            .regex
+            .replace_all(text, |caps: &Captures| {
+                let m = caps.get_match();
+                let mut out = String::new();
+                if !(m.is_empty() && Some(m.start()) == end) {
+                    expand(caps, &mut out);
+                    count += 1;
+                }
+                out
+            })
+            .to_string();
+        (replaced, count)
+    }
+
+    // Replace only the match at range, so zero-width assertions like \b see the text around it
+    fn replace_at(&self, text: &str, range: Range<usize>, replacement: &str) -> Result<String> {
+        let caps = self
+            .regex
+            .captures_at(text, range.start)
+            .filter(|caps| caps.get_match().range() == range);
+        let Some(caps) = caps else {
+            // the match can't be found in context, so replace the matched text alone
+            return self.replace(&text[range], replacement);
+        };
+        let mut out = String::new();
+        self.expander(replacement)(&caps, &mut out);
+        Ok(out)
+    }
+
+    // Appends the replacement for a match, expanding capture groups and case transforms
+    fn expander<'a>(&self, replacement: &'a str) -> impl FnMut(&Captures<'_>, &mut String) + 'a {
+        let fixed_string = self.fixed_string;
+        let mut parts = CaseReplacer(parse_replacement(replacement));
+        move |caps, dst| {
+            if fixed_string {
+                dst.push_str(replacement);
+            } else if parts.0 == [CasePart::Text(replacement)] {
+                // no case transforms or escapes, let regex handle it directly
+                caps.expand(replacement, dst);
+            } else {
+                parts.replace_append(caps, dst);
+            }
+        }
+    }
+}
+
+// Ensure replaced ends with a newline if and only if the original file text did
+pub fn match_trailing_newline(original: &str, mut replaced: String) -> String {
+    match (original.ends_with('\n'), replaced.ends_with('\n')) {
+        (true, false) if !replaced.is_empty() => {
+            replaced += if original.ends_with("\r\n") {
+                "\r\n"
+            } else {
+                "\n"
+            };
+        }
+        (false, true) => {
+            replaced.pop();
+            if replaced.ends_with('\r') {
+                replaced.pop();
+            }
+        }
+        _ => {}
     }
+    replaced
+}
+
+#[test]
+fn test_match_trailing_newline() {
+    let check = |original, replaced: &str| match_trailing_newline(original, replaced.into());
+    assert_eq!(check("foo\n", "bar\n"), "bar\n");
+    assert_eq!(check("foo\n", "bar"), "bar\n");
+    assert_eq!(check("foo\r\n", "bar"), "bar\r\n");
+    assert_eq!(check("foo", "bar"), "bar");
+    assert_eq!(check("foo", "bar\n"), "bar");
+    assert_eq!(check("foo", "bar\r\n"), "bar");
+    assert_eq!(check("foo\n", ""), "");
+}
+
+#[test]
+fn test_regex_trailing_newline() {
+    let path = Path::new("a.txt");
+    let finder = Finder::new("o$", &RegexParams::default()).unwrap();
+    assert_eq!(
+        finder.replace(path, "foo\nfoo", "o\n").unwrap(),
+        "foo\n\nfoo"
+    );
+    assert_eq!(finder.replace_match(path, "o", "o\n").unwrap(), "o\n");
+
+    let params = RegexParams {
+        multi_line: true,
+        ..Default::default()
+    };
+    let finder = Finder::new("o\n", &params).unwrap();
+    assert_eq!(finder.replace(path, "foo\nfoo\n", "").unwrap(), "fofo\n");
+}
+
+#[derive(Debug, PartialEq)]
+enum CasePart<'a> {
+    Text(&'a str), // expanded like a normal replacement
+    Upper,         // \U, uppercase until \E
+    Lower,         // \L, lowercase until \E
+    End,           // \E
+    UpperNext,     // \u, uppercase the next character
+    LowerNext,     // \l, lowercase the next character
 }
 
+// Split a replacement into case transforms and text, unescaping \n, \t, \r, and \\ first so
+// that e.g. \\U is a literal \U. Capture refs like $1 are expanded later, so escapes never
+// affect captured text.
+fn parse_replacement(replacement: &str) -> Vec<CasePart<'_>> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut chars = replacement.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != '\\' {
+            continue;
+        }
+        let part = match chars.peek() {
+            Some((_, 'U')) => CasePart::Upper,
+            Some((_, 'L')) => CasePart::Lower,
+            Some((_, 'E')) => CasePart::End,
+            Some((_, 'u')) => CasePart::UpperNext,
+            Some((_, 'l')) => CasePart::LowerNext,
+            Some((_, 'n')) => CasePart::Text("\n"),
+            Some((_, 't')) => CasePart::Text("\t"),
+            Some((_, 'r')) => CasePart::Text("\r"),
+            Some((_, '\\')) => CasePart::Text("\\"),
+            _ => continue,
+        };
+        chars.next();
+        if start < i {
+            parts.push(CasePart::Text(&replacement[start..i]));
+        }
+        parts.push(part);
+        start = i + 2;
+    }
+    if start < replacement.len() || parts.is_empty() {
+        parts.push(CasePart::Text(&replacement[start..]));
+    }
+    parts
+}
+
+// Expands a replacement, applying \U, \L, \E, \u, and \l case transforms
+struct CaseReplacer<'a>(Vec<CasePart<'a>>);
+
+impl Replacer for CaseReplacer<'_> {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        let mut mode = None;
+        let mut next = None;
+        for part in &self.0 {
+            let text = match part {
+                CasePart::Upper | CasePart::Lower => {
+                    mode = Some(part);
+                    continue;
+                }
+                CasePart::End => {
+                    mode = None;
+                    continue;
+                }
+                CasePart::UpperNext | CasePart::LowerNext => {
+                    next = Some(part);
+                    continue;
+                }
+                CasePart::Text(text) => text,
+            };
+            let mut expanded = String::new();
+            caps.expand(text, &mut expanded);
+            let mut expanded = match mode {
+                Some(CasePart::Upper) => expanded.to_uppercase(),
+                Some(CasePart::Lower) => expanded.to_lowercase(),
+                _ => expanded,
+            };
+            if let Some(first) = expanded.chars().next() {
+                let first_len = first.len_utf8();
+                match next.take() {
+                    Some(CasePart::UpperNext) => {
+                        expanded.replace_range(..first_len, &first.to_uppercase().to_string())
+                    }
+                    Some(CasePart::LowerNext) => {
+                        expanded.replace_range(..first_len, &first.to_lowercase().to_string())
+                    }
+                    _ => {}
+                }
+            }
+            dst.push_str(&expanded);
+        }
+    }
+}
+
+#[test]
+fn test_parse_replacement() {
+    use CasePart::*;
+    assert_eq!(parse_replacement(""), [Text("")]);
+    assert_eq!(parse_replacement("$1 foo"), [Text("$1 foo")]);
+    assert_eq!(parse_replacement("\\x\\"), [Text("\\x\\")]);
+    assert_eq!(
+        parse_replacement("a\\n$1\\t\\\\U"),
+        [
+            Text("a"),
+            Text("\n"),
+            Text("$1"),
+            Text("\t"),
+            Text("\\"),
+            Text("U")
+        ]
+    );
+    assert_eq!(
+        parse_replacement("a\\U$1\\Eb\\u${2}\\L"),
+        [
+            Text("a"),
+            Upper,
+            Text("$1"),
+            End,
+            Text("b"),
+            UpperNext,
+            Text("${2}"),
+            Lower
+        ]
+    );
+}
+
+#[test]
+fn test_regex_case_transform() {
+    let finder = RegexFinder::new(r"(\w+)_(?<rest>\w+)", &RegexParams::default()).unwrap();
+    let replace = |r| finder.replace("foo_bar baz_qux", r).unwrap();
+    assert_eq!(replace(r"\u${1}"), "Foo Baz");
+    assert_eq!(replace(r"\U$1\E_$rest"), "FOO_bar BAZ_qux");
+    assert_eq!(replace(r"\U${1}_\l${rest}"), "FOO_bAR BAZ_qUX");
+    assert_eq!(replace(r"${1}\u$rest"), "fooBar bazQux");
+    assert_eq!(replace(r"\L\u${1}X"), "Foox Bazx");
+    // existing behavior is unchanged
+    assert_eq!(replace(r"${0}!"), "foo_bar! baz_qux!");
+    assert_eq!(replace(r"$2-$1"), "bar-foo qux-baz");
+}
+
+#[test]
+fn test_regex_escapes() {
+    let finder = RegexFinder::new(r"(\w+)_(?<rest>\w+)", &RegexParams::default()).unwrap();
+    let replace = |r| finder.replace("foo_bar baz_qux", r).unwrap();
+    assert_eq!(replace(r"$1\n$rest"), "foo\nbar baz\nqux");
+    assert_eq!(replace(r"\t${1}\r\n"), "\tfoo\r\n \tbaz\r\n");
+    assert_eq!(replace(r"\\n$1\\"), "\\nfoo\\ \\nbaz\\");
+    assert_eq!(replace(r"\\U$1"), "\\Ufoo \\Ubaz");
+    // a lone escape, as when splitting on a delimiter
+    assert_eq!(replace(r"\n"), "\n \n");
+    assert_eq!(replace(r"\t"), "\t \t");
+    assert_eq!(
+        replace(r"\x$1"),
+        "\\xfoo \\xbaz",
+        "unknown escapes are literal"
+    );
+
+    // fixed strings are never unescaped
+    let params = RegexParams {
+        fixed_string: true,
+        ..Default::default()
+    };
+    let finder = RegexFinder::new("foo", &params).unwrap();
+    assert_eq!(finder.replace("foo", r"a\nb").unwrap(), r"a\nb");
+}
+
+// Like grep's sinks::UTF8, but also records where each line is in the file
+struct LineSink<'a> {
+    regex: &'a Regex,
+    lines: Vec<LineMatch>,
+    first_only: bool,                            // quit after the first match
+    line_range: &'a Option<RangeInclusive<u64>>, // skip lines outside this range
+    max_matches: Option<usize>,                  // quit after collecting more than this many
+    matches: usize,
+    skip_non_utf8: bool,
+}
+
+impl LineSink<'_> {
+    // Whether the search has gone past the line range, so needn't continue
+    fn past_range(&self, number: u64) -> bool {
+        self.line_range.as_ref().is_some_and(|r| number > *r.end())
+    }
+}
+
+impl Sink for LineSink<'_> {
+    type Error = std::io::Error;
+
+    fn matched(&mut self, _searcher: &Searcher, mat: &SinkMatch<'_>) -> Result<bool, Self::Error> {
+        let text = match std::str::from_utf8(mat.bytes()) {
+            Ok(text) => text,
+            Err(_) if self.skip_non_utf8 => return Ok(true),
+            Err(err) => return Err(std::io::Error::other(err)),
+        };
+        let number = mat
+            .line_number()
+            .ok_or_else(|| std::io::Error::other("line numbers not enabled"))?;
+        if self.past_range(number) {
+            return Ok(false);
+        }
+        if !in_lines(number, text, self.line_range) {
+            return Ok(true);
+        }
+        let ranges: Vec<_> = self
+            .regex
+            .find_iter(text)
+            // an empty match after the line ending belongs to the next line
+            .filter(|m| !(m.is_empty() && m.start() == text.len() && text.ends_with('\n')))
+            .map(|m| m.start()..m.end())
+            .collect();
+        self.matches += ranges.len();
+        self.lines.push(LineMatch {
+            number,
+            text: text.to_string(),
+            offset: mat.absolute_byte_offset() as usize,
+            ranges,
+            captures: vec![],
+        });
+        let over = self.max_matches.is_some_and(|max| self.matches > max);
+        Ok(!self.first_only && !over)
+    }
+
+    fn context(
+        &mut self,
+        _searcher: &Searcher,
+        ctx: &SinkContext<'_>,
+    ) -> Result<bool, Self::Error> {
+        let text = match std::str::from_utf8(ctx.bytes()) {
+            Ok(text) => text,
+            Err(_) if self.skip_non_utf8 => return Ok(true),
+            Err(err) => return Err(std::io::Error::other(err)),
+        };
+        let number = ctx
+            .line_number()
+            .ok_or_else(|| std::io::Error::other("line numbers not enabled"))?;
+        if self.past_range(number) {
+            return Ok(false);
+        }
+        if !in_lines(number, text, self.line_range) {
+            return Ok(true);
+        }
+        self.lines.push(LineMatch {
+            number,
+            text: text.to_string(),
+            offset: ctx.absolute_byte_offset() as usize,
+            ranges: vec![],
+            captures: vec![],
+        });
+        Ok(true)
+    }
+}
+
+#[test]
+fn test_invert_match() {
+    let tmp = tempfile::tempdir().unwrap();
+    let txt = tmp.path().join("a.txt");
+    std::fs::write(&txt, "foo\nbar\nfoo foo\nbaz\n").unwrap();
+
+    let params = RegexParams {
+        invert_match: true,
+        ..Default::default()
+    };
+    let mut finder = Finder::new("foo", &params).unwrap();
+    assert_eq!(
+        finder.find(&txt).unwrap(),
+        [
+            LineMatch {
+                number: 2,
+                text: "bar\n".into(),
+                offset: 4,
+                ranges: vec![],
+                captures: vec![],
+            },
+            LineMatch {
+                number: 4,
+                text: "baz\n".into(),
+                offset: 16,
+                ranges: vec![],
+                captures: vec![],
+            }
+        ]
+    );
+
+    assert_eq!(
+        Finder::new("foo($A)", &params).unwrap_err().to_string(),
+        "Invert match doesn't apply to ast-grep patterns"
+    );
+}
+
+#[test]
+fn test_first_match_only() {
+    let tmp = tempfile::tempdir().unwrap();
+    let txt = tmp.path().join("a.txt");
+    std::fs::write(&txt, "foo\nbar\nfoo foo\n").unwrap();
+    let py = tmp.path().join("a.py");
+    std::fs::write(&py, "foo(1)\nfoo(2)\n").unwrap();
+
+    let params = RegexParams::default();
+    let mut finder = Finder::new("foo", &params).unwrap();
+    assert_eq!(finder.find(&txt).unwrap().len(), 2);
+    let mut finder = finder.first_match_only();
+    assert_eq!(
+        finder.find(&txt).unwrap(),
+        [LineMatch {
+            number: 1,
+            text: "foo\n".into(),
+            offset: 0,
+            ranges: vec![Range { start: 0, end: 3 }],
+            captures: vec![],
+        }]
+    );
+
+    let mut finder = Finder::new("foo($A)", &params).unwrap();
+    assert_eq!(finder.find(&py).unwrap().len(), 2);
+    let mut finder = finder.first_match_only();
+    assert_eq!(finder.find(&py).unwrap().len(), 1);
+}
+
+#[test]
+fn test_max_matches() {
+    let tmp = tempfile::tempdir().unwrap();
+    let txt = tmp.path().join("a.txt");
+    std::fs::write(&txt, "foo foo\nbar\nfoo\nfoo foo foo\nfoo\n").unwrap();
+    let py = tmp.path().join("a.py");
+    std::fs::write(&py, "foo(1)\nfoo(2)\nfoo(3)\n").unwrap();
+
+    // the ranges of each line found, and whether they were truncated
+    let find = |pattern, path, max| {
+        let params = RegexParams {
+            max_matches: Some(max),
+            ..Default::default()
+        };
+        let mut finder = Finder::new(pattern, &params).unwrap();
+        let mut lines = finder.find(path).unwrap();
+        let truncated = finder.truncate(&mut lines);
+        let ranges: Vec<_> = lines.into_iter().map(|l| l.ranges.len()).collect();
+        (ranges, truncated)
+    };
+    assert_eq!(find("foo", &txt, 7), (vec![2, 1, 3, 1], false));
+    assert_eq!(find("foo", &txt, 6), (vec![2, 1, 3], true));
+    assert_eq!(find("foo", &txt, 4), (vec![2, 1, 1], true));
+    assert_eq!(find("foo", &txt, 3), (vec![2, 1], true));
+    assert_eq!(find("foo($A)", &py, 3), (vec![1, 1, 1], false));
+    assert_eq!(find("foo($A)", &py, 2), (vec![1, 1], true));
+}
+
+#[test]
+fn test_binary() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("a.bin");
+    std::fs::write(&path, b"foo\x00bar foo\n\xff foo\nbaz foo\n").unwrap();
+
+    let find = |binary| {
+        let params = RegexParams {
+            binary,
+            ..Default::default()
+        };
+        let mut finder = Finder::new("foo", &params).unwrap();
+        finder
+            .find(&path)
+            .unwrap()
+            .into_iter()
+            .map(|l| (l.number, l.text))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(find(Binary::Quit), []);
+    // NULs count as line endings, and lines that aren't UTF-8 are skipped
+    assert_eq!(
+        find(Binary::Convert),
+        [
+            (1, "foo\n".into()),
+            (2, "bar foo\n".into()),
+            (4, "baz foo\n".into())
+        ]
+    );
+    assert_eq!(
+        find(Binary::Text),
+        [(1, "foo\x00bar foo\n".into()), (3, "baz foo\n".into())]
+    );
+}
+
+#[test]
+fn test_regex_whole_word() {
+    let params = RegexParams {
+        whole_word: true,
+        ..Default::default()
+    };
+    let finder = RegexFinder::new("foo|bar", &params).unwrap();
+    assert_eq!(
+        finder.replace("foo foobar bar_ (bar)", "x").unwrap(),
+        "x foobar bar_ (x)"
+    );
+}
+
+#[test]
+fn test_regex_zero_width() {
+    let text = "foo bar\nbaz\n";
+    let finder = |pattern| RegexFinder::new(pattern, &RegexParams::default()).unwrap();
+    assert_eq!(
+        finder("^").replace(text, "> ").unwrap(),
+        "> foo bar\n> baz\n"
+    );
+    assert_eq!(finder("$").replace(text, ";").unwrap(), "foo bar;\nbaz;\n");
+    assert_eq!(
+        finder(r"\b").replace(text, "|").unwrap(),
+        "|foo| |bar|\n|baz|\n"
+    );
+
+    // a single match is replaced in the context of the text around it
+    assert_eq!(finder(r"\b").replace_at(text, 3..3, "|").unwrap(), "|");
+    assert_eq!(finder(r"\b").replace_at(text, 1..1, "|").unwrap(), "");
+    assert_eq!(
+        finder(r"(\w)\b").replace_at(text, 2..3, "$1|").unwrap(),
+        "o|"
+    );
+
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("file.txt");
+    std::fs::write(&path, text).unwrap();
+    let ranges = |pattern| {
+        finder(pattern)
+            .find(&path)
+            .unwrap()
+            .into_iter()
+            .map(|l| l.ranges)
+            .collect::<Vec<_>>()
+    };
+    let at = |i| vec![Range { start: i, end: i }];
+    assert_eq!(ranges("^"), [at(0), at(0)]);
+    assert_eq!(ranges("$"), [at(7), at(3)]);
+    assert_eq!(
+        ranges(r"\b"),
+        [vec![0..0, 3..3, 4..4, 7..7], vec![0..0, 3..3]]
+    );
+}
+
+#[test]
+fn test_regex_crlf() {
+    let text = "foo one\r\nfoo two\r\n";
+    let replace = |pattern, replacement| {
+        RegexFinder::new(pattern, &RegexParams::default())
+            .unwrap()
+            .replace(text, replacement)
+            .unwrap()
+    };
+    assert_eq!(replace("o .*", "x"), "fox\r\nfox\r\n");
+    assert_eq!(replace("[eo]$", "x"), "foo onx\r\nfoo twx\r\n");
+    assert_eq!(replace("^f", "x"), "xoo one\r\nxoo two\r\n");
+
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("crlf.txt");
+    std::fs::write(&path, text).unwrap();
+    let mut finder = RegexFinder::new("o .*", &RegexParams::default()).unwrap();
+    assert_eq!(
+        finder.find(&path).unwrap(),
+        [
+            LineMatch {
+                number: 1,
+                text: "foo one\r\n".into(),
+                offset: 0,
+                ranges: vec![Range { start: 2, end: 7 }],
+                captures: vec![],
+            },
+            LineMatch {
+                number: 2,
+                text: "foo two\r\n".into(),
+                offset: 9,
+                ranges: vec![Range { start: 2, end: 7 }],
+                captures: vec![],
+            },
+        ]
+    );
+
+    // $ matches before either line ending
+    let mut finder = RegexFinder::new("o$", &RegexParams::default()).unwrap();
+    for text in ["foo\nbar\n", "foo\r\nbar\r\n", "bar\nfoo"] {
+        std::fs::write(&path, text).unwrap();
+        assert_eq!(finder.find(&path).unwrap().len(), 1, "{text:?}");
+    }
+}
+
+#[test]
+#[tracing_test::traced_test]
+fn test_replace_streaming() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("a.txt");
+    let finder = Finder::new("o$", &RegexParams::default()).unwrap();
+    assert!(finder.can_stream());
+
+    let replace = |text: &[u8]| {
+        std::fs::write(&path, text).unwrap();
+        let replaced = finder.replace_streaming(&path, "0").unwrap();
+        replaced.map(|(tmp, n)| (std::fs::read_to_string(tmp.path()).unwrap(), n))
+    };
+    assert_eq!(
+        replace(b"foo\nbar\nfoo\n").unwrap(),
+        ("fo0\nbar\nfo0\n".into(), 2)
+    );
+    assert_eq!(
+        replace(b"foo\r\nbar\r\nfoo").unwrap(),
+        ("fo0\r\nbar\r\nfo0".into(), 2)
+    );
+    assert_eq!(replace(b"bar\nbaz\n"), None);
+    assert_eq!(replace(b"foo\ncaf\xe9\n"), None);
+    assert!(logs_contain("not valid UTF-8"));
+
+    let params = RegexParams {
+        multi_line: true,
+        ..Default::default()
+    };
+    let finder = Finder::new("o$", &params).unwrap();
+    assert!(!finder.can_stream());
+    assert!(finder.replace_streaming(&path, "0").is_err());
+}
+
+#[test]
+fn test_regex_fixed_string() {
+    let params = RegexParams {
+        fixed_string: true,
+        ..Default::default()
+    };
+    let finder = Finder::new("$FOO.(bar)", &params).unwrap();
+    let Finder::Regex(finder) = &finder else {
+        panic!("Expected a regex finder, got {finder:?}");
+    };
+    assert_eq!(
+        finder.replace("$FOO.(bar) $FOOx(bar)", "$1").unwrap(),
+        "$1 $FOOx(bar)"
+    );
+}
+
+#[test]
+fn test_with_filetypes() {
+    use crate::config::FileTypeConfig;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let write = |name, text| {
+        let path = tmp.path().join(name);
+        std::fs::write(&path, text).unwrap();
+        path
+    };
+    let md = write("a.md", "a.b axb\n");
+    let rs = write("a.rs", "A.B a.b\n");
+    let txt = write("a.txt", "A.B a.b axb\n");
+
+    let filetypes = [
+        FileType::new(
+            ".md",
+            FileTypeConfig {
+                fixed_string: Some(true),
+                ..Default::default()
+            },
+        )
+        .unwrap(),
+        FileType::new(
+            "rust",
+            FileTypeConfig {
+                ignore_case: Some(true),
+                ..Default::default()
+            },
+        )
+        .unwrap(),
+    ];
+    let mut finder = Finder::with_filetypes("a.b", &RegexParams::default(), &filetypes).unwrap();
+    let mut ranges = |path| {
+        let lines = finder.find(path).unwrap();
+        lines.into_iter().flat_map(|l| l.ranges).collect::<Vec<_>>()
+    };
+    assert_eq!(ranges(&md), [Range { start: 0, end: 3 }]);
+    assert_eq!(ranges(&rs), [0..3, 4..7]);
+    assert_eq!(ranges(&txt), [4..7, 8..11]);
+    assert_eq!(finder.replace(&md, "a.b axb\n", "c").unwrap(), "c axb\n");
+    assert_eq!(finder.replace(&txt, "a.b axb\n", "c").unwrap(), "c c\n");
+    assert!(finder.can_stream());
+
+    // ast = false treats an ast-grep pattern as a regex
+    let filetypes = [FileType::new(
+        "rust",
+        FileTypeConfig {
+            ast: Some(false),
+            ..Default::default()
+        },
+    )
+    .unwrap()];
+    let finder = Finder::with_filetypes("foo($A)", &RegexParams::default(), &filetypes).unwrap();
+    let Finder::Typed(typed) = &finder else {
+        panic!("Expected a typed finder, got {finder:?}");
+    };
+    assert!(matches!(typed.for_path(&rs), Finder::Regex(_)));
+    assert!(matches!(typed.for_path(Path::new("a.py")), Finder::Ast(_)));
+    assert!(!finder.can_stream());
+
+    // without filetypes, this is the same as new
+    let finder = Finder::with_filetypes("a.b", &RegexParams::default(), &[]).unwrap();
+    assert!(matches!(finder, Finder::Regex(_)));
+}
+
+// Files that an ast-grep pattern couldn't search
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct AstSkipped {
+    pub no_lang: usize,         // no language is known for the file
+    pub invalid_pattern: usize, // the pattern doesn't parse in the file's language
+}
+
+#[derive(Debug, Default)]
+struct AstSkipCounts {
+    no_lang: AtomicUsize,
+    invalid_pattern: AtomicUsize,
+}
+
+// Matches ast-grep patterns. Nodes are compared by their exact text, as ast-grep has no way to
+// ignore case, so ignore_case and whole_word in RegexParams have no effect.
 #[derive(Clone, Debug)]
 pub struct AstFinder {
     pattern: String,
+    constraints: Vec<(String, Regex)>, // meta-variables, and a regex their text must match
+    first_only: bool,                  // stop searching a file after its first match
+    lines: Option<RangeInclusive<u64>>, // only find matches starting or ending on these lines
+    max_matches: Option<usize>,        // see Finder::truncate
+    skipped: Arc<AstSkipCounts>,       // shared by clones, which search on other threads
+    // Patterns compiled for each language seen so far, or None if the pattern is invalid for it.
+    // Each search thread has its own clone, and so its own cache, to avoid locking per file.
+    patterns: HashMap<SupportLang, Option<Pattern>>,
+}
+
+// Parse constraints like "$FN ~ ^get_ and $X ~ \d" on the meta-variables of pattern
+fn parse_constraints(pattern: &str, constraints: &str) -> Result<Vec<(String, Regex)>> {
+    static VAR: OnceLock<Regex> = OnceLock::new();
+    let var_re = VAR.get_or_init(|| Regex::new(r"^\$([A-Z_][A-Z_0-9]*)$").unwrap());
+    constraints
+        .split(" and ")
+        .map(|c| {
+            let Some((var, regex)) = c.split_once('~') else {
+                bail!("Expected a constraint like `$VAR ~ regex`, got `{c}`");
+            };
+            let var = var.trim();
+            let Some(name) = var_re.captures(var).map(|c| c[1].to_string()) else {
+                bail!("Constraints apply to single meta-variables like `$FN`, got `{var}`");
+            };
+            // $$$NAME is a different, multi-node meta-variable
+            let used = Regex::new(&format!(r"(^|[^$])\${name}\b"))?;
+            if !used.is_match(pattern) {
+                bail!("`{var}` is not in the pattern `{pattern}`");
+            }
+            let regex = Regex::new(regex.trim())
+                .with_context(|| format!("Invalid regex in constraint on `{var}`"))?;
+            Ok((name, regex))
+        })
+        .collect()
+}
+
+// A meta-variable computed from another when replacing, like "$NAME = convert($FN, snakeCase)"
+#[derive(Clone, Debug, PartialEq)]
+struct Transform {
+    name: String,         // the new meta-variable, without the $
+    source: MetaVariable, // the meta-variable, or an earlier transform, it is computed from
+    op: TransformOp,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TransformOp {
+    Convert(Case),
+    Substring(Option<isize>, Option<isize>), // char offsets, negative ones count from the end
+}
+
+// The cases ast-grep can convert to, named as in its toCase option
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Case {
+    Lower,
+    Upper,
+    Capitalize,
+    Camel,
+    Snake,
+    Kebab,
+    Pascal,
+}
+
+impl Case {
+    fn parse(s: &str) -> Result<Self> {
+        Ok(match s {
+            "lowerCase" => Case::Lower,
+            "upperCase" => Case::Upper,
+            "capitalize" => Case::Capitalize,
+            "camelCase" => Case::Camel,
+            "snakeCase" => Case::Snake,
+            "kebabCase" => Case::Kebab,
+            "pascalCase" => Case::Pascal,
+            _ => bail!(
+                "Unknown case `{s}`, expected one of lowerCase, upperCase, capitalize, \
+                 camelCase, snakeCase, kebabCase, or pascalCase"
+            ),
+        })
+    }
+
+    fn convert(self, text: &str) -> String {
+        let capitalize = |w: &str| {
+            let mut chars = w.chars();
+            chars.next().map_or(String::new(), |c| {
+                c.to_uppercase()
+                    .chain(chars.flat_map(char::to_lowercase))
+                    .collect()
+            })
+        };
+        let words = split_words(text);
+        let lower = || words.iter().map(|w| w.to_lowercase());
+        match self {
+            Case::Lower => text.to_lowercase(),
+            Case::Upper => text.to_uppercase(),
+            Case::Capitalize => {
+                let mut chars = text.chars();
+                chars
+                    .next()
+                    .map_or(String::new(), |c| c.to_uppercase().chain(chars).collect())
+            }
+            Case::Snake => lower().collect::<Vec<_>>().join("_"),
+            Case::Kebab => lower().collect::<Vec<_>>().join("-"),
+            Case::Pascal => words.iter().map(|w| capitalize(w)).collect(),
+            Case::Camel => words
+                .iter()
+                .enumerate()
+                .map(|(i, w)| {
+                    if i == 0 {
+                        w.to_lowercase()
+                    } else {
+                        capitalize(w)
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+// Split an identifier like "fooBar_baz" or "HTTPServer" into its words
+fn split_words(text: &str) -> Vec<&str> {
+    let mut words = vec![];
+    for part in text.split(|c: char| !c.is_alphanumeric()) {
+        let chars: Vec<_> = part.char_indices().collect();
+        let mut start = 0;
+        for (i, &(offset, c)) in chars.iter().enumerate().skip(1) {
+            let prev = chars[i - 1].1;
+            let next_lower = chars.get(i + 1).is_some_and(|(_, n)| n.is_lowercase());
+            // a word starts at "fooBar" -> "Bar", or "HTTPServer" -> "Server"
+            if c.is_uppercase() && (prev.is_lowercase() || prev.is_uppercase() && next_lower) {
+                words.push(&part[start..offset]);
+                start = offset;
+            }
+        }
+        words.push(&part[start..]);
+    }
+    words.retain(|w| !w.is_empty());
+    words
+}
+
+impl TransformOp {
+    fn apply(&self, text: &str) -> String {
+        match self {
+            TransformOp::Convert(case) => case.convert(text),
+            TransformOp::Substring(start, end) => {
+                let len = text.chars().count() as isize;
+                let index = |i: isize| if i < 0 { (len + i).max(0) } else { i.min(len) };
+                let start = start.map_or(0, index);
+                let end = end.map_or(len, index);
+                text.chars()
+                    .skip(start as usize)
+                    .take((end - start).max(0) as usize)
+                    .collect()
+            }
+        }
+    }
+}
+
+// Split a replacement like "$NAME where $NAME = convert($FN, snakeCase)" into its template and
+// transforms, which are joined by "and". Replacements without transforms are returned as-is.
+fn parse_transforms(replacement: &str) -> Result<(&str, Vec<Transform>)> {
+    static TRANSFORM: OnceLock<Regex> = OnceLock::new();
+    static VAR: OnceLock<Regex> = OnceLock::new();
+    let transform_re = TRANSFORM
+        .get_or_init(|| Regex::new(r"^\s*\$([A-Z_][A-Z_0-9]*)\s*=\s*(\w+)\((.*)\)\s*$").unwrap());
+    let var_re = VAR.get_or_init(|| Regex::new(r"^(\$|\$\$\$)([A-Z_][A-Z_0-9]*)$").unwrap());
+
+    // the template itself may contain " where ", e.g. in a Rust where clause
+    let Some((template, spec)) = replacement
+        .rsplit_once(" where ")
+        .filter(|(_, spec)| spec.trim_start().starts_with('$') && spec.contains('='))
+    else {
+        return Ok((replacement, vec![]));
+    };
+
+    let mut transforms: Vec<Transform> = vec![];
+    for t in spec.split(" and ") {
+        let Some(caps) = transform_re.captures(t) else {
+            bail!("Expected a transform like `$NEW = convert($VAR, snakeCase)`, got `{t}`");
+        };
+        let args: Vec<_> = caps[3].split(',').map(str::trim).collect();
+        let Some(var) = var_re.captures(args[0]) else {
+            bail!(
+                "Transforms apply to meta-variables like `$FN`, got `{}`",
+                args[0]
+            );
+        };
+        let source = match &var[1] {
+            "$" => MetaVariable::Capture(var[2].to_string(), false),
+            _ => MetaVariable::MultiCapture(var[2].to_string()),
+        };
+        let op = match (&caps[2], &args[1..]) {
+            ("convert", [case]) => TransformOp::Convert(Case::parse(case)?),
+            ("substring", [start, end]) => {
+                let index = |s: &str| {
+                    (!s.is_empty())
+                        .then(|| s.parse())
+                        .transpose()
+                        .with_context(|| format!("Invalid substring index `{s}`"))
+                };
+                TransformOp::Substring(index(start)?, index(end)?)
+            }
+            ("convert", _) => bail!("Expected `convert($VAR, case)`, got `{}`", t.trim()),
+            ("substring", _) => bail!("Expected `substring($VAR, start, end)`, got `{}`", t.trim()),
+            (f, _) => bail!("Unknown transform `{f}`, expected convert or substring"),
+        };
+        transforms.push(Transform {
+            name: caps[1].to_string(),
+            source,
+            op,
+        });
+    }
+    Ok((template, transforms))
+}
+
+// Expands a replacement template after computing the meta-variables of its transforms
+struct TransformReplacer<'a> {
+    template: TemplateFix,
+    transforms: &'a [Transform],
+}
+
+impl<D: Doc> AstReplacer<D> for TransformReplacer<'_> {
+    fn generate_replacement(&self, nm: &NodeMatch<'_, D>) -> Underlying<D> {
+        let mut env = nm.get_env().clone();
+        for t in self.transforms {
+            // a transform may also use the result of an earlier one
+            let bytes = match &t.source {
+                MetaVariable::Capture(name, _) => env.get_transformed(name).map(Vec::as_slice),
+                _ => None,
+            }
+            .or_else(|| env.get_var_bytes(&t.source));
+            let Some(bytes) = bytes else {
+                continue;
+            };
+            let text = t.op.apply(&D::Source::encode_bytes(bytes));
+            env.insert_transformation(&t.source, &t.name, D::Source::decode_str(&text).to_vec());
+        }
+        let nm = NodeMatch::new(nm.get_node().clone(), env);
+        self.template.generate_replacement(&nm)
+    }
+}
+
+// The spans of each meta-variable in a match, relative to the matched node
+fn capture_ranges<D: Doc>(m: &NodeMatch<'_, D>) -> Vec<Range<usize>> {
+    let env = m.get_env();
+    let start = m.range().start;
+    let mut ranges: Vec<_> = env
+        .get_matched_variables()
+        .filter_map(|var| match var {
+            MetaVariable::Capture(name, _) => env.get_match(&name).map(|n| n.range()),
+            MetaVariable::MultiCapture(name) => {
+                let nodes = env.get_multiple_matches(&name);
+                Some(nodes.first()?.range().start..nodes.last()?.range().end)
+            }
+            MetaVariable::Dropped(_) | MetaVariable::Multiple => None,
+        })
+        .filter(|r| !r.is_empty())
+        .map(|r| r.start - start..r.end - start)
+        .collect();
+    ranges.sort_by_key(|r| (r.start, r.end));
+    ranges
+}
+
+// A pattern whose meta-variables must also match the given regexes
+struct ConstrainedPattern<'a> {
+    pattern: &'a Pattern,
+    constraints: &'a [(String, Regex)],
+}
+
+impl Matcher for ConstrainedPattern<'_> {
+    fn match_node_with_env<'tree, D: Doc>(
+        &self,
+        node: Node<'tree, D>,
+        env: &mut Cow<MetaVarEnv<'tree, D>>,
+    ) -> Option<Node<'tree, D>> {
+        let node = self.pattern.match_node_with_env(node, env)?;
+        self.constraints
+            .iter()
+            .all(|(var, re)| env.get_match(var).is_some_and(|n| re.is_match(&n.text())))
+            .then_some(node)
+    }
+
+    fn get_match_len<D: Doc>(&self, node: Node<'_, D>) -> Option<usize> {
+        self.pattern.get_match_len(node)
+    }
 }
 
 impl AstFinder {
-    pub fn new(pattern: impl Into<String>) -> Self {
-        Self {
+    // The pattern may end with constraints on its meta-variables,
+    // e.g. "$FN($$$ARGS) where $FN ~ ^get_" only matches calls to functions starting with get_
+    pub fn new(pattern: &str) -> Result<Self> {
+        static CONSTRAINT: OnceLock<Regex> = OnceLock::new();
+        let constraint_re = CONSTRAINT
+            .get_or_init(|| Regex::new(r"^\s*\$(\$\$)?[A-Z_][A-Z_0-9]*\s*(~|$)").unwrap());
+        // the pattern itself may contain " where ", e.g. in a Rust where clause
+        let split = pattern
+            .rsplit_once(" where ")
+            .filter(|(_, constraints)| constraint_re.is_match(constraints));
+        let (pattern, constraints) = match split {
+            Some((pattern, constraints)) => (pattern, parse_constraints(pattern, constraints)?),
+            None => (pattern, vec![]),
+        };
+        Ok(Self {
             pattern: pattern.into(),
-        }
+            constraints,
+            first_only: false,
+            lines: None,
+            max_matches: None,
+            skipped: Arc::default(),
+            patterns: HashMap::new(),
+        })
     }
 
     fn find(&mut self, path: &Path) -> Result<Vec<LineMatch>> {
+        self.search(path, || read_text(path))
+    }
+
+    fn find_text(&mut self, path: &Path, text: &str) -> Result<Vec<LineMatch>> {
+        self.search(path, || Ok(Some(text.to_string())))
+    }
+
+    // Search the source from read, which is only called if path has a language the pattern is
+    // valid for
+    fn search(
+        &mut self,
+        path: &Path,
+        read: impl FnOnce() -> Result<Option<String>>,
+    ) -> Result<Vec<LineMatch>> {
         let Some(lang) = SupportLang::from_path(path) else {
             trace!("No AST language for {path:?}");
+            self.skipped.no_lang.fetch_add(1, Ordering::Relaxed);
             return Ok(vec![]);
         };
 
-        let pattern = match Pattern::try_new(&self.pattern, lang) {
-            Ok(p) => p,
-            Err(e) => {
-                trace!("Invalid pattern for language {lang:?}: {e}");
-                return Ok(vec![]);
-            }
+        let compiled = self.patterns.entry(lang).or_insert_with(|| {
+            Pattern::try_new(&self.pattern, lang)
+                .inspect_err(|e| trace!("Invalid pattern for language {lang:?}: {e}"))
+                .ok()
+        });
+        let Some(pattern) = compiled else {
+            self.skipped.invalid_pattern.fetch_add(1, Ordering::Relaxed);
+            return Ok(vec![]);
+        };
+        let pattern = ConstrainedPattern {
+            pattern,
+            constraints: &self.constraints,
         };
 
         trace!(
             "reading {path:?} of lang {lang} with pattern {}",
             self.pattern
         );
-        let src = std::fs::read_to_string(path).with_context(|| format!("Reading {path:?}"))?;
+        let Some(src) = read()? else {
+            return Ok(vec![]);
+        };
         let root = lang.ast_grep(src);
         let node = root.root();
 
-        Ok(node
-            .find_all(pattern)
+        let matches = node.find_all(pattern);
+        let limit = match self.max_matches {
+            _ if self.first_only => 1,
+            Some(max) => max.saturating_add(1),
+            None => usize::MAX,
+        };
+        Ok(matches
+            // ast-grep lines count from 0
+            .filter(|m| in_lines(m.start_pos().line() as u64 + 1, &m.text(), &self.lines))
             .map(|m| {
                 let text = m.text();
                 LineMatch {
                     number: m.start_pos().line() as u64,
+                    offset: m.range().start,
                     ranges: vec![Range {
                         start: 0,
                         end: text.len(),
                     }],
+                    captures: capture_ranges(&m),
                     text: text.into(),
                 }
             })
+            .take(limit)
             .collect())
     }
 
-    fn replace(&self, path: &Path, text: &str, replacement: &str) -> Result<String> {
+    // Returns the replaced text and the number of matches replaced
+    fn replace_counted(
+        &self,
+        path: &Path,
+        text: &str,
+        replacement: &str,
+    ) -> Result<(String, usize)> {
         let lang =
             SupportLang::from_path(path).with_context(|| format!("No language for {path:?}"))?;
 
-        let pattern = Pattern::try_new(&self.pattern, lang)
-            .with_context(|| format!("Invalid pattern for language {lang:?}"))?;
+        let compiled = match self.patterns.get(&lang) {
+            Some(Some(p)) => Cow::Borrowed(p),
+            _ => Cow::Owned(
+                Pattern::try_new(&self.pattern, lang)
+                    .with_context(|| format!("Invalid pattern for language {lang:?}"))?,
+            ),
+        };
+        let pattern = ConstrainedPattern {
+            pattern: &compiled,
+            constraints: &self.constraints,
+        };
+
+        let (template, transforms) = parse_transforms(replacement)?;
+        let names: Vec<_> = transforms.iter().map(|t| t.name.clone()).collect();
+        let replacer = TransformReplacer {
+            template: TemplateFix::with_transform(template, &lang, &names),
+            transforms: &transforms,
+        };
 
         let mut root = lang.ast_grep(text);
         let node = root.root();
 
-        let edits = node.replace_all(&pattern, replacement);
+        let edits = node.replace_all(&pattern, replacer);
+        let count = edits.len();
 
         // edits must be applied in reverse to avoid offset issues
         for edit in edits.into_iter().rev() {
@@ -220,7 +1964,7 @@ impl AstFinder {
                 bail!("Failed to edit {path:?}: {e}");
             }
         }
-        Ok(root.generate())
+        Ok((root.generate(), count))
     }
 }
 
@@ -229,6 +1973,122 @@ mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_ast_find_non_utf8() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("latin1.py");
+        std::fs::write(&path, b"print('caf\xe9')\n").unwrap();
+
+        let mut finder = Finder::new("print($$$ARGS)", &RegexParams::default()).unwrap();
+        assert_eq!(finder.find(&path).unwrap(), []);
+        assert!(logs_contain("not valid UTF-8"));
+    }
+
+    #[test]
+    fn test_ast_ignore_case() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("a.py");
+        std::fs::write(&path, "foo(1)\nFOO(2)\n").unwrap();
+
+        // ast-grep can't ignore case, so only the exact text matches
+        let params = RegexParams {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let mut finder = Finder::new("foo($A)", &params).unwrap();
+        assert!(finder.is_ast());
+        let lines: Vec<_> = finder
+            .find(&path)
+            .unwrap()
+            .into_iter()
+            .map(|l| l.text)
+            .collect();
+        assert_eq!(lines, ["foo(1)"]);
+        assert_eq!(
+            finder
+                .replace(&path, "foo(1)\nFOO(2)\n", "bar($A)")
+                .unwrap(),
+            "bar(1)\nFOO(2)\n"
+        );
+    }
+
+    #[test]
+    fn test_ast_constraints() {
+        let mut finder =
+            Finder::new("$FN($$$ARGS) where $FN ~ ^th", &RegexParams::default()).unwrap();
+        let mut find = |path| {
+            finder
+                .find(Path::new(path))
+                .unwrap()
+                .into_iter()
+                .map(|l| l.text)
+                .collect::<Vec<_>>()
+        };
+        // print(x + y) is not matched
+        assert_eq!(find("testdata/main.py"), ["thing(3, 5)"]);
+        assert_eq!(find("testdata/main.rs"), ["thing(3, 5)"]);
+
+        // a where clause is part of the pattern, and constraints may follow it
+        let path = Path::new("a.rs");
+        let text = "fn foo<T>() where T: Clone {}\nfn bar<T>() where T: Clone {}\n";
+        for (pattern, count) in [
+            ("fn $F<$T>() where $T: Clone {}", 2),
+            ("fn $F<$T>() where $T: Clone {} where $F ~ ^f", 1),
+        ] {
+            let finder = Finder::new(pattern, &RegexParams::default()).unwrap();
+            assert_eq!(finder.replace_counted(path, text, "").unwrap().1, count);
+        }
+
+        let path = Path::new("a.py");
+        let finder = Finder::new(
+            "$FN($A) where $FN ~ ^get_ and $A ~ ^\\d+$",
+            &RegexParams::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            finder
+                .replace(path, "get_x(1)\nget_y(a)\nset_x(1)\n", "$FN($A, 0)")
+                .unwrap(),
+            "get_x(1, 0)\nget_y(a)\nset_x(1)\n"
+        );
+
+        let err = |pattern| {
+            Finder::new(pattern, &RegexParams::default())
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            err("$FN() where $FN"),
+            "Expected a constraint like `$VAR ~ regex`, got `$FN`"
+        );
+        assert_eq!(
+            err("$FN($$$ARGS) where $$$ARGS ~ x"),
+            "Constraints apply to single meta-variables like `$FN`, got `$$$ARGS`"
+        );
+        assert_eq!(
+            err("$FN($$$ARGS) where $ARGS ~ x"),
+            "`$ARGS` is not in the pattern `$FN($$$ARGS)`"
+        );
+        assert_eq!(
+            err("$FN() where $FN ~ ("),
+            "Invalid regex in constraint on `$FN`"
+        );
+    }
+
+    #[test]
+    fn test_ast_pattern_cache() {
+        let mut finder = AstFinder::new("thing($$$ARGS)").unwrap();
+        let mut find = |path| {
+            let lines = finder.find(Path::new(path)).unwrap();
+            let langs = finder.patterns.len();
+            (lines.into_iter().map(|l| l.text).collect::<Vec<_>>(), langs)
+        };
+        assert_eq!(find("testdata/main.py"), (vec!["thing(3, 5)".into()], 1));
+        assert_eq!(find("testdata/main.py"), (vec!["thing(3, 5)".into()], 1));
+        assert_eq!(find("testdata/main.rs"), (vec!["thing(3, 5)".into()], 2));
+    }
+
     #[test]
     fn test_ast_replace() {
         let finder = Finder::new(
@@ -236,6 +2096,7 @@ mod tests {
             &RegexParams {
                 ignore_case: true,
                 multi_line: true,
+                ..Default::default()
             },
         )
         .unwrap();
@@ -267,6 +2128,7 @@ thing(3, 5, 5)
             &RegexParams {
                 ignore_case: true,
                 multi_line: true,
+                ..Default::default()
             },
         )
         .unwrap();
@@ -279,4 +2141,122 @@ thing(3, 5, 5)
         let expected = "foo(32, bar(s, y, baz()), 5)";
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn test_ast_replace_trailing_newline() {
+        let finder = Finder::new("print($A)", &RegexParams::default()).unwrap();
+        let replace = |src| {
+            finder
+                .replace(Path::new("example.py"), src, "log($A)")
+                .unwrap()
+        };
+        assert_eq!(replace("print(1)\nprint(2)\n"), "log(1)\nlog(2)\n");
+        assert_eq!(replace("print(1)\nprint(2)"), "log(1)\nlog(2)");
+    }
+
+    #[test]
+    fn test_empty_pattern() {
+        let err = Finder::new("", &RegexParams::default()).unwrap_err();
+        assert_eq!(err.to_string(), "The pattern is empty");
+    }
+
+    #[test]
+    fn test_ast_replace_reorder() {
+        let finder = Finder::new("$A + $B", &RegexParams::default()).unwrap();
+        let actual = finder
+            .replace(Path::new("example.py"), "x = foo(1) + bar\n", "$B + $A")
+            .unwrap();
+        assert_eq!(actual, "x = bar + foo(1)\n");
+
+        let finder = Finder::new("thing($X, $Y)", &RegexParams::default()).unwrap();
+        let actual = finder
+            .replace(Path::new("example.py"), "thing(3, [5])\n", "thing($Y, $X)")
+            .unwrap();
+        assert_eq!(actual, "thing([5], 3)\n");
+    }
+
+    #[test]
+    fn test_ast_replace_wrap() {
+        let finder = Finder::new("print($$$ARGS)", &RegexParams::default()).unwrap();
+        let actual = finder
+            .replace(
+                Path::new("example.py"),
+                "print(x, y)\nprint()\n",
+                "log(str($$$ARGS))",
+            )
+            .unwrap();
+        assert_eq!(actual, "log(str(x, y))\nlog(str())\n");
+    }
+
+    #[test]
+    fn test_ast_replace_transform() {
+        let finder = Finder::new("def $FN($$$ARGS): $$$BODY", &RegexParams::default()).unwrap();
+        let replace =
+            |r| finder.replace(Path::new("example.py"), "def getHTTPResponse(x): pass\n", r);
+        assert_eq!(
+            replace("def $NAME($$$ARGS): $$$BODY where $NAME = convert($FN, snakeCase)").unwrap(),
+            "def get_http_response(x): pass\n"
+        );
+        assert_eq!(
+            replace(
+                "def $NAME(): $$$BODY where $SHORT = substring($FN, 3,) \
+                 and $NAME = convert($SHORT, kebabCase)"
+            )
+            .unwrap(),
+            "def http-response(): pass\n"
+        );
+        assert_eq!(
+            replace("$FN where $FN = substring($FN, 0, -8)").unwrap(),
+            "getHTTP\n"
+        );
+        // a template with " where " that isn't followed by a transform is left alone
+        assert_eq!(
+            replace("def $FN(): x where y").unwrap(),
+            "def getHTTPResponse(): x where y\n"
+        );
+
+        let err = |r| replace(r).unwrap_err().to_string();
+        assert_eq!(
+            err("$N where $N = convert($FN, titleCase)"),
+            "Unknown case `titleCase`, expected one of lowerCase, upperCase, capitalize, \
+             camelCase, snakeCase, kebabCase, or pascalCase"
+        );
+        assert_eq!(
+            err("$N where $N = trim($FN)"),
+            "Unknown transform `trim`, expected convert or substring"
+        );
+        assert_eq!(
+            err("$N where $N = substring($FN, 1)"),
+            "Expected `substring($VAR, start, end)`, got `$N = substring($FN, 1)`"
+        );
+        assert_eq!(
+            err("$N where $N = convert(FN, snakeCase)"),
+            "Transforms apply to meta-variables like `$FN`, got `FN`"
+        );
+        assert_eq!(
+            err("$N where $N = $FN"),
+            "Expected a transform like `$NEW = convert($VAR, snakeCase)`, got `$N = $FN`"
+        );
+    }
+
+    #[test]
+    fn test_convert_case() {
+        let convert = |case, s| Case::convert(case, s);
+        assert_eq!(
+            split_words("getHTTPResponse2"),
+            ["get", "HTTP", "Response2"]
+        );
+        assert_eq!(
+            split_words("_foo__bar-baz qux"),
+            ["foo", "bar", "baz", "qux"]
+        );
+        assert_eq!(convert(Case::Snake, "fooBarBaz"), "foo_bar_baz");
+        assert_eq!(convert(Case::Kebab, "FooBar"), "foo-bar");
+        assert_eq!(convert(Case::Camel, "foo_bar_baz"), "fooBarBaz");
+        assert_eq!(convert(Case::Pascal, "foo-bar"), "FooBar");
+        assert_eq!(convert(Case::Upper, "foo_bar"), "FOO_BAR");
+        assert_eq!(convert(Case::Lower, "Foo_Bar"), "foo_bar");
+        assert_eq!(convert(Case::Capitalize, "fooBar"), "FooBar");
+        assert_eq!(convert(Case::Capitalize, ""), "");
+    }
 }
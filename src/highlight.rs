@@ -0,0 +1,325 @@
+use std::{
+    collections::BTreeMap,
+    io::Cursor,
+    path::Path,
+    sync::{Arc, LazyLock},
+};
+
+use crate::config::FileType;
+use anyhow::Result;
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::Span,
+};
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{self, FontStyle, Theme, ThemeSet},
+    parsing::{SyntaxReference, SyntaxSet},
+};
+use tracing::{debug, error, warn};
+
+// Loading these is slow, so do it once and share them between highlighters
+static SYNTAXES: LazyLock<Arc<SyntaxSet>> =
+    LazyLock::new(|| Arc::new(SyntaxSet::load_defaults_newlines()));
+static ANSI_THEME: LazyLock<Arc<Theme>> = LazyLock::new(|| {
+    let mut reader = Cursor::new(include_bytes!("ansi.tmTheme"));
+    Arc::new(ThemeSet::load_from_reader(&mut reader).unwrap_or_else(|e| {
+        error!("Failed to load ANSI theme: {e}");
+        Theme::default()
+    }))
+});
+static THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+#[derive(Clone)]
+pub struct Highlighter {
+    syntaxes: Arc<SyntaxSet>,
+    theme: Arc<Theme>,
+    filetype_themes: Vec<(FileType, Arc<Theme>)>, // used instead of theme for matching files
+}
+
+impl Default for Highlighter {
+    fn default() -> Self {
+        Self {
+            syntaxes: SYNTAXES.clone(),
+            theme: ANSI_THEME.clone(),
+            filetype_themes: vec![],
+        }
+    }
+}
+
+// The default syntaxes, plus any found in dir
+fn load_syntaxes(dir: &Path) -> Result<SyntaxSet> {
+    let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+    builder.add_from_folder(dir, true)?;
+    Ok(builder.build())
+}
+
+impl Highlighter {
+    // Use the named syntect theme, falling back to ANSI colors if it is unknown.
+    // Syntaxes and themes are loaded from syntax_dir and theme_dir in addition to the defaults.
+    // Filetypes with a syntax_theme use that instead.
+    pub fn new(
+        theme: Option<&str>,
+        syntax_dir: Option<&Path>,
+        theme_dir: Option<&Path>,
+        filetypes: &[FileType],
+    ) -> Self {
+        let syntaxes = match syntax_dir.map(|dir| (dir, load_syntaxes(dir))) {
+            None => SYNTAXES.clone(),
+            Some((_, Ok(syntaxes))) => Arc::new(syntaxes),
+            Some((dir, Err(e))) => {
+                error!("Failed to load syntaxes from {dir:?}: {e}");
+                SYNTAXES.clone()
+            }
+        };
+
+        let custom_themes = match theme_dir.map(|dir| (dir, ThemeSet::load_from_folder(dir))) {
+            None => BTreeMap::new(),
+            Some((_, Ok(themes))) => themes.themes,
+            Some((dir, Err(e))) => {
+                error!("Failed to load themes from {dir:?}: {e}");
+                BTreeMap::new()
+            }
+        };
+
+        let load_theme = |theme: Option<&str>| match theme {
+            None => ANSI_THEME.clone(),
+            Some(name) => match custom_themes.get(name).or(THEMES.themes.get(name)) {
+                Some(theme) => Arc::new(theme.clone()),
+                None => {
+                    let names: Vec<_> = custom_themes.keys().chain(THEMES.themes.keys()).collect();
+                    warn!("Unknown syntax theme {name:?}, available themes are: {names:?}");
+                    ANSI_THEME.clone()
+                }
+            },
+        };
+
+        let filetype_themes = filetypes
+            .iter()
+            .filter_map(|t| {
+                let name = t.config.syntax_theme.as_deref()?;
+                Some((t.clone(), load_theme(Some(name))))
+            })
+            .collect();
+
+        Self {
+            syntaxes,
+            theme: load_theme(theme),
+            filetype_themes,
+        }
+    }
+}
+
+// The ANSI theme encodes palette colors in the alpha channel, see ansi.tmTheme
+fn to_color(color: highlighting::Color) -> Option<Color> {
+    match color.a {
+        0 => Some(Color::Indexed(color.r)),
+        1 => None,
+        _ => Some(Color::Rgb(color.r, color.g, color.b)),
+    }
+}
+
+fn to_style(style: highlighting::Style) -> Style {
+    let mut s = Style {
+        fg: to_color(style.foreground),
+        ..Default::default()
+    };
+    if style.font_style.contains(FontStyle::BOLD) {
+        s = s.add_modifier(Modifier::BOLD);
+    }
+    if style.font_style.contains(FontStyle::ITALIC) {
+        s = s.add_modifier(Modifier::ITALIC);
+    }
+    if style.font_style.contains(FontStyle::UNDERLINE) {
+        s = s.add_modifier(Modifier::UNDERLINED);
+    }
+    s
+}
+
+impl Highlighter {
+    // Find a syntax by the file's extension, falling back to its first line (e.g. a shebang)
+    fn find_syntax(&self, path: &Path, first_line: &str) -> Option<&SyntaxReference> {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| self.syntaxes.find_syntax_by_extension(ext))
+            .or_else(|| self.syntaxes.find_syntax_by_first_line(first_line))
+    }
+
+    // Highlight a line from the file at path, whose first line is first_line.
+    // Returns a single unstyled span if the syntax is unknown.
+    pub fn highlight<'a>(&self, path: &Path, first_line: &str, line: &'a str) -> Vec<Span<'a>> {
+        let Some(syntax) = self.find_syntax(path, first_line) else {
+            return vec![Span::raw(line)];
+        };
+        let theme = self
+            .filetype_themes
+            .iter()
+            .find(|(t, _)| t.matches(path))
+            .map_or(&self.theme, |(_, theme)| theme);
+        let mut h = HighlightLines::new(syntax, theme);
+        match h.highlight_line(line, &self.syntaxes) {
+            Ok(ranges) => ranges
+                .into_iter()
+                .map(|(style, s)| Span::styled(s, to_style(style)))
+                .collect(),
+            Err(e) => {
+                debug!("Failed to highlight {path:?}: {e}");
+                vec![Span::raw(line)]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_highlight() {
+        let h = Highlighter::default();
+        let spans = h.highlight(Path::new("foo.rs"), "", "let x = 5;\n");
+        assert_eq!(
+            spans.iter().map(|s| s.content.as_ref()).collect::<String>(),
+            "let x = 5;\n"
+        );
+        assert!(spans.len() > 1);
+        assert!(spans.iter().any(|s| s.style.fg.is_some()));
+    }
+
+    #[test]
+    fn test_highlight_unknown() {
+        let h = Highlighter::default();
+        assert_eq!(
+            h.highlight(Path::new("foo.unknown"), "", "let x = 5;\n"),
+            vec![Span::raw("let x = 5;\n")]
+        );
+        assert_eq!(
+            h.highlight(Path::new("foo"), "", "let x = 5;\n"),
+            vec![Span::raw("let x = 5;\n")]
+        );
+    }
+
+    #[test]
+    fn test_highlight_first_line() {
+        let h = Highlighter::default();
+        let path = Path::new("script");
+        let line = "echo $HOME\n";
+        let spans = h.highlight(path, "#!/bin/bash\n", line);
+        assert!(spans.len() > 1);
+        assert_eq!(
+            spans,
+            h.highlight(Path::new("script.sh"), "", line),
+            "should highlight as bash"
+        );
+
+        // the extension takes precedence
+        assert_eq!(
+            h.highlight(Path::new("script.txt"), "#!/bin/bash\n", line),
+            h.highlight(Path::new("script.txt"), "", line),
+        );
+        assert_eq!(h.highlight(path, "", line), vec![Span::raw(line)]);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_highlighter_theme() {
+        let line = "let x = 5;\n";
+        let path = Path::new("foo.rs");
+        let ansi = Highlighter::default().highlight(path, "", line);
+
+        let named =
+            Highlighter::new(Some("base16-ocean.dark"), None, None, &[]).highlight(path, "", line);
+        assert_ne!(named, ansi);
+        assert!(
+            named
+                .iter()
+                .all(|s| matches!(s.style.fg, Some(Color::Rgb(..))))
+        );
+
+        let unknown = Highlighter::new(Some("nope"), None, None, &[]).highlight(path, "", line);
+        assert_eq!(unknown, ansi);
+        assert!(logs_contain("Unknown syntax theme \"nope\""));
+        assert!(logs_contain("base16-ocean.dark"));
+
+        assert_eq!(
+            Highlighter::new(None, None, None, &[]).highlight(path, "", line),
+            ansi
+        );
+
+        // a filetype can use its own theme
+        let filetypes = [FileType::new(
+            "rust",
+            crate::config::FileTypeConfig {
+                syntax_theme: Some("base16-ocean.dark".into()),
+                ..Default::default()
+            },
+        )
+        .unwrap()];
+        let h = Highlighter::new(None, None, None, &filetypes);
+        assert_eq!(h.highlight(path, "", line), named);
+        let py = Path::new("foo.py");
+        assert_eq!(
+            h.highlight(py, "", "x = 5\n"),
+            Highlighter::default().highlight(py, "", "x = 5\n"),
+        );
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_highlighter_custom_dirs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let syntax_dir = tmp.path().join("syntaxes");
+        let theme_dir = tmp.path().join("themes");
+        std::fs::create_dir(&syntax_dir).unwrap();
+        std::fs::create_dir(&theme_dir).unwrap();
+        std::fs::write(
+            syntax_dir.join("myl.sublime-syntax"),
+            "\
+%YAML 1.2
+---
+name: Myl
+file_extensions: [myl]
+scope: source.myl
+contexts:
+  main:
+    - match: '\\bfrob\\b'
+      scope: keyword.control.myl
+",
+        )
+        .unwrap();
+        std::fs::write(
+            theme_dir.join("custom.tmTheme"),
+            include_str!("ansi.tmTheme"),
+        )
+        .unwrap();
+
+        let h = Highlighter::new(Some("custom"), Some(&syntax_dir), Some(&theme_dir), &[]);
+        let spans = h.highlight(Path::new("foo.myl"), "", "frob x\n");
+        assert_eq!(spans[0].content, "frob");
+        assert!(spans[0].style.fg.is_some());
+        // default syntaxes are still available
+        assert_eq!(
+            h.highlight(Path::new("foo.rs"), "", "let x = 5;\n"),
+            Highlighter::default().highlight(Path::new("foo.rs"), "", "let x = 5;\n"),
+        );
+
+        // missing dirs fall back to the defaults
+        let missing = tmp.path().join("missing");
+        let h = Highlighter::new(Some("custom"), Some(&missing), Some(&missing), &[]);
+        assert_eq!(
+            h.highlight(Path::new("foo.myl"), "", "frob x\n"),
+            vec![Span::raw("frob x\n")]
+        );
+        assert!(logs_contain("Failed to load syntaxes"));
+        assert!(logs_contain("Failed to load themes"));
+    }
+
+    #[test]
+    fn test_highlighter_shared() {
+        let a = Highlighter::default();
+        let b = Highlighter::default();
+        assert!(Arc::ptr_eq(&a.syntaxes, &b.syntaxes));
+        assert!(Arc::ptr_eq(&a.theme, &b.theme));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::config::{Action, Key};
+use crate::config::{Action, KeySeq};
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use ratatui::layout::Rect;
 use ratatui::style::Style;
@@ -6,12 +6,33 @@ use ratatui::{
     Frame,
     widgets::{Block, Borders, Paragraph},
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use unicode_width::UnicodeWidthStr;
+
+// The char auto-inserted after c, if auto_pairs is enabled
+fn closing_pair(c: char) -> Option<char> {
+    match c {
+        '(' => Some(')'),
+        '{' => Some('}'),
+        '[' => Some(']'),
+        '"' => Some('"'),
+        '\'' => Some('\''),
+        _ => None,
+    }
+}
 
 pub struct LineInput {
     pattern: String,
-    cursor_pos: usize,
+    cursor_pos: usize, // byte offset, always on a char boundary
     auto_pairs: bool,
+    history: VecDeque<String>,  // oldest first
+    history_idx: usize,         // history.len() when not browsing history
+    draft: String,              // pattern being edited before browsing history
+    killed: String,             // text last removed by a kill action, inserted by Yank
+    killing: bool,              // whether the last action was a kill, so the next one accumulates
+    undo: Vec<(String, usize)>, // pattern and cursor before each edit
+    redo: Vec<(String, usize)>, // edits reverted by undo
+    typing: bool, // whether the last edit inserted a char, so the next one joins its undo step
 }
 
 impl LineInput {
@@ -20,37 +41,221 @@ impl LineInput {
             pattern: "".into(),
             cursor_pos: 0,
             auto_pairs,
+            history: VecDeque::new(),
+            history_idx: 0,
+            draft: "".into(),
+            killed: "".into(),
+            killing: false,
+            undo: vec![],
+            redo: vec![],
+            typing: false,
+        }
+    }
+
+    pub fn history(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().map(String::as_str)
+    }
+
+    pub fn set_history(&mut self, history: impl IntoIterator<Item = String>) {
+        self.history = history.into_iter().collect();
+        self.history_idx = self.history.len();
+    }
+
+    // Add the current pattern to the history, keeping at most max entries
+    pub fn push_history(&mut self, max: usize) {
+        if !self.pattern.is_empty() && self.history.back() != Some(&self.pattern) {
+            self.history.push_back(self.pattern.clone());
+        }
+        while self.history.len() > max {
+            self.history.pop_front();
+        }
+        self.history_idx = self.history.len();
+    }
+
+    // Replace the pattern, leaving the cursor at the end
+    pub fn set_pattern(&mut self, pattern: &str) {
+        self.pattern = pattern.into();
+        self.cursor_pos = self.pattern.len();
+    }
+
+    // Insert text at the cursor, keeping newlines if multi_line, otherwise joining lines with spaces
+    pub fn insert(&mut self, text: &str, multi_line: bool) -> Option<&str> {
+        let text = if multi_line {
+            text.replace("\r\n", "\n")
+        } else {
+            text.lines().collect::<Vec<_>>().join(" ")
+        };
+        if text.is_empty() {
+            return None;
+        }
+        self.undo.push((self.pattern.clone(), self.cursor_pos));
+        self.redo.clear();
+        self.typing = false;
+        self.pattern.insert_str(self.cursor_pos, &text);
+        self.cursor_pos += text.len();
+        tracing::debug!("Inserted '{text}', new pattern: {}", self.pattern);
+        Some(&self.pattern)
+    }
+
+    fn recall(&mut self, idx: usize) {
+        if self.history_idx == self.history.len() {
+            self.draft = self.pattern.clone();
+        }
+        self.history_idx = idx;
+        self.pattern = self.history.get(idx).unwrap_or(&self.draft).to_string();
+        self.cursor_pos = self.pattern.len();
+        tracing::debug!("Recalled history {idx}: {}", self.pattern);
+    }
+
+    // Save removed text for Yank. Consecutive kills accumulate, before or after the previous one.
+    fn kill(&mut self, text: &str, prepend: bool) {
+        if !self.killing {
+            self.killed.clear();
         }
+        if prepend {
+            self.killed.insert_str(0, text);
+        } else {
+            self.killed.push_str(text);
+        }
+        self.killing = true;
+        tracing::debug!("Killed '{text}', kill ring: {}", self.killed);
+    }
+
+    // byte offset of the start of the line containing the cursor
+    fn line_start(&self) -> usize {
+        self.pattern[..self.cursor_pos]
+            .rfind('\n')
+            .map_or(0, |i| i + 1)
+    }
+
+    // byte offset of the end of the line containing the cursor, before any newline
+    fn line_end(&self) -> usize {
+        self.pattern[self.cursor_pos..]
+            .find('\n')
+            .map_or(self.pattern.len(), |i| self.cursor_pos + i)
+    }
+
+    // byte offset of the char before the cursor
+    fn prev_boundary(&self) -> usize {
+        self.pattern[..self.cursor_pos]
+            .chars()
+            .next_back()
+            .map_or(0, |c| self.cursor_pos - c.len_utf8())
+    }
+
+    // byte offset of the char after the cursor
+    fn next_boundary(&self) -> usize {
+        self.pattern[self.cursor_pos..]
+            .chars()
+            .next()
+            .map_or(self.pattern.len(), |c| self.cursor_pos + c.len_utf8())
+    }
+
+    // byte offset of the start of the word before the cursor, skipping whitespace
+    fn prev_word(&self) -> usize {
+        self.pattern[..self.cursor_pos]
+            .trim_end()
+            .char_indices()
+            .rfind(|(_, c)| c.is_whitespace())
+            .map_or(0, |(idx, c)| idx + c.len_utf8())
+    }
+
+    // byte offset of the end of the word after the cursor, skipping whitespace
+    fn next_word(&self) -> usize {
+        let rest = &self.pattern[self.cursor_pos..];
+        let word = rest.trim_start();
+        let start = self.cursor_pos + rest.len() - word.len();
+        start + word.find(char::is_whitespace).unwrap_or(word.len())
     }
 
     // Returns true if the pattern changed
     pub fn handle_key_event(
         &mut self,
         key_event: KeyEvent,
-        key_map: &HashMap<Key, Action>,
+        key_map: &HashMap<KeySeq, Action>,
     ) -> Option<&str> {
-        if let Some(action) = key_map.get(&key_event.into()) {
+        self.handle_key(key_event, key_map.get(&key_event.into()).copied())
+    }
+
+    // Like handle_key_event, with the action bound to the key (or key sequence) already resolved
+    pub fn handle_key(&mut self, key_event: KeyEvent, action: Option<Action>) -> Option<&str> {
+        match action {
+            Some(Action::InputUndo) => return self.restore(true),
+            Some(Action::InputRedo) => return self.restore(false),
+            _ => {}
+        }
+        let before = (self.pattern.clone(), self.cursor_pos);
+        let typing = action.is_none() && matches!(key_event.code, KeyCode::Char(_));
+        let was_typing = std::mem::replace(&mut self.typing, false);
+        self.edit(key_event, action)?;
+        // consecutive chars are undone together
+        self.typing = typing;
+        if !(typing && was_typing) {
+            self.undo.push(before);
+        }
+        self.redo.clear();
+        Some(&self.pattern)
+    }
+
+    // Undo (or redo) the last edit, returning the restored pattern
+    fn restore(&mut self, undo: bool) -> Option<&str> {
+        let (from, to) = if undo {
+            (&mut self.undo, &mut self.redo)
+        } else {
+            (&mut self.redo, &mut self.undo)
+        };
+        let (pattern, cursor_pos) = from.pop()?;
+        to.push((
+            std::mem::replace(&mut self.pattern, pattern),
+            std::mem::replace(&mut self.cursor_pos, cursor_pos),
+        ));
+        self.typing = false;
+        self.killing = false;
+        tracing::debug!("Restored pattern: {}", self.pattern);
+        Some(&self.pattern)
+    }
+
+    // Apply the edit for a key, returning the new pattern if it changed
+    fn edit(&mut self, key_event: KeyEvent, action: Option<Action>) -> Option<&str> {
+        let killing = std::mem::take(&mut self.killing);
+        if let Some(action) = action {
             match action {
                 Action::CursorLeft => {
                     tracing::debug!("Moving cursor left");
-                    self.cursor_pos = self.cursor_pos.saturating_sub(1);
+                    self.cursor_pos = self.prev_boundary();
                     return None;
                 }
                 Action::CursorRight => {
                     tracing::debug!("Moving cursor right");
-                    self.cursor_pos = (self.cursor_pos + 1).min(self.pattern.len());
+                    self.cursor_pos = self.next_boundary();
+                    return None;
+                }
+                Action::CursorWordLeft => {
+                    tracing::debug!("Moving cursor left one word");
+                    self.cursor_pos = self.prev_word();
+                    return None;
+                }
+                Action::CursorWordRight => {
+                    tracing::debug!("Moving cursor right one word");
+                    self.cursor_pos = self.next_word();
                     return None;
                 }
                 Action::CursorHome => {
                     tracing::debug!("Moving cursor to beginning of line");
-                    self.cursor_pos = 0;
+                    self.cursor_pos = self.line_start();
                     return None;
                 }
                 Action::CursorEnd => {
                     tracing::debug!("Moving cursor to end of line");
-                    self.cursor_pos = self.pattern.len();
+                    self.cursor_pos = self.line_end();
                     return None;
                 }
+                Action::InsertNewline => {
+                    self.pattern.insert(self.cursor_pos, '\n');
+                    self.cursor_pos += 1;
+                    tracing::debug!("Inserted newline, new pattern: {:?}", self.pattern);
+                    return Some(&self.pattern);
+                }
                 Action::DeleteChar => {
                     if self.cursor_pos >= self.pattern.len() {
                         return None;
@@ -63,9 +268,15 @@ impl LineInput {
                     if self.cursor_pos == 0 {
                         return None;
                     };
-                    self.cursor_pos -= 1;
-                    // BUG: Doesn't handle unicode
+                    self.cursor_pos = self.prev_boundary();
                     let c = self.pattern.remove(self.cursor_pos);
+                    // delete both chars of an empty pair
+                    if self.auto_pairs
+                        && closing_pair(c)
+                            .is_some_and(|p| self.pattern[self.cursor_pos..].starts_with(p))
+                    {
+                        self.pattern.remove(self.cursor_pos);
+                    }
                     tracing::debug!("Removed '{c}' from pattern, new pattern: {}", self.pattern);
                     return Some(&self.pattern);
                 }
@@ -78,33 +289,77 @@ impl LineInput {
                         self.pattern,
                         self.cursor_pos
                     );
-                    let (s, rest) = self.pattern.split_at(self.cursor_pos);
-                    if let Some(idx) = s.trim_end().rfind(char::is_whitespace) {
-                        self.cursor_pos = idx + 1;
-                        self.pattern = s[0..=idx].to_owned() + rest;
-                        tracing::debug!("Truncated pattern to {}", self.pattern);
-                    } else {
-                        self.pattern = rest.into();
-                        self.cursor_pos = 0;
-                        tracing::debug!("Cleared pattern");
+                    let start = self.prev_word();
+                    let word = self.pattern[start..self.cursor_pos].to_owned();
+                    self.pattern.replace_range(start..self.cursor_pos, "");
+                    self.cursor_pos = start;
+                    tracing::debug!("Truncated pattern to {}", self.pattern);
+                    self.killing = killing;
+                    self.kill(&word, true);
+                    return Some(&self.pattern);
+                }
+                Action::TransposeChar => {
+                    if self.cursor_pos == 0 || self.pattern.chars().nth(1).is_none() {
+                        return None;
+                    }
+                    // at the end of the line, swap the last two chars instead
+                    if self.cursor_pos == self.pattern.len() {
+                        self.cursor_pos = self.prev_boundary();
                     }
+                    let start = self.prev_boundary();
+                    let end = self.next_boundary();
+                    let c = self.pattern.remove(start);
+                    self.pattern.insert(end - c.len_utf8(), c);
+                    self.cursor_pos = end;
+                    tracing::debug!("Transposed chars, new pattern: {}", self.pattern);
                     return Some(&self.pattern);
                 }
                 Action::DeleteToEndOfLine => {
-                    if self.cursor_pos >= self.pattern.len() {
+                    let end = self.line_end();
+                    if self.cursor_pos >= end {
                         return None;
                     }
                     tracing::debug!("Deleting from cursor to end of line");
-                    self.pattern.truncate(self.cursor_pos);
+                    let text = self.pattern[self.cursor_pos..end].to_owned();
+                    self.pattern.replace_range(self.cursor_pos..end, "");
+                    self.killing = killing;
+                    self.kill(&text, false);
                     return Some(&self.pattern);
                 }
                 Action::DeleteLine => {
-                    if self.pattern.is_empty() {
+                    // only the cursor's line, keeping the newlines around it
+                    let (start, end) = (self.line_start(), self.line_end());
+                    if start == end {
                         return None;
                     }
                     tracing::debug!("Deleting entire line");
-                    self.pattern.clear();
-                    self.cursor_pos = 0;
+                    let text = self.pattern[start..end].to_owned();
+                    self.pattern.replace_range(start..end, "");
+                    self.cursor_pos = start;
+                    self.kill(&text, false);
+                    return Some(&self.pattern);
+                }
+                Action::Yank => {
+                    if self.killed.is_empty() {
+                        return None;
+                    }
+                    self.pattern.insert_str(self.cursor_pos, &self.killed);
+                    self.cursor_pos += self.killed.len();
+                    tracing::debug!("Yanked '{}', new pattern: {}", self.killed, self.pattern);
+                    return Some(&self.pattern);
+                }
+                Action::HistoryPrev => {
+                    if self.history_idx == 0 {
+                        return None;
+                    }
+                    self.recall(self.history_idx - 1);
+                    return Some(&self.pattern);
+                }
+                Action::HistoryNext => {
+                    if self.history_idx >= self.history.len() {
+                        return None;
+                    }
+                    self.recall(self.history_idx + 1);
                     return Some(&self.pattern);
                 }
                 _ => {} // Ignore other actions
@@ -114,27 +369,27 @@ impl LineInput {
         // Fall back to character input if no action matched
         match key_event.code {
             KeyCode::Char(c) if (key_event.modifiers & !KeyModifiers::SHIFT).is_empty() => {
+                let prev = self.pattern[..self.cursor_pos].chars().next_back();
+                // an escaped char like \( is literal, so isn't paired
+                let escaped = prev == Some('\\');
                 if self.auto_pairs
-                    && ")}]".contains(c)
-                    && self
-                        .pattern
-                        .get(self.cursor_pos..self.cursor_pos + 1)
-                        .is_some_and(|x| x == c.to_string())
+                    && !escaped
+                    && ")}]\"'".contains(c)
+                    && self.pattern[self.cursor_pos..].starts_with(c)
                 {
                     // Matching pair already exists
                 } else {
                     self.pattern.insert(self.cursor_pos, c);
                 }
-                self.cursor_pos += 1;
-                if self.auto_pairs {
-                    if let Some(pair) = match c {
-                        '(' => Some(')'),
-                        '{' => Some('}'),
-                        '[' => Some(']'),
-                        _ => None,
-                    } {
-                        self.pattern.insert(self.cursor_pos, pair);
-                    }
+                self.cursor_pos += c.len_utf8();
+                // a quote after a word is more likely an apostrophe, as in don't
+                let apostrophe = "\"'".contains(c) && prev.is_some_and(char::is_alphanumeric);
+                if self.auto_pairs
+                    && !escaped
+                    && !apostrophe
+                    && let Some(pair) = closing_pair(c)
+                {
+                    self.pattern.insert(self.cursor_pos, pair);
                 }
                 tracing::debug!("Updated filter pattern: {}", self.pattern);
                 Some(&self.pattern)
@@ -143,8 +398,14 @@ impl LineInput {
         }
     }
 
+    // The display column of the cursor within its line, as opposed to its byte offset
     pub fn cursor_pos(&self) -> u16 {
-        self.cursor_pos as u16
+        self.pattern[self.line_start()..self.cursor_pos].width() as u16
+    }
+
+    // The line of the cursor, for patterns containing newlines
+    pub fn cursor_line(&self) -> u16 {
+        self.pattern[..self.cursor_pos].matches('\n').count() as u16
     }
 
     pub fn pattern(&self) -> &str {
@@ -153,12 +414,27 @@ impl LineInput {
 
     pub fn size(&self) -> u16 {
         // +2 for borders
-        self.pattern.len() as u16 + 2
+        self.pattern
+            .split('\n')
+            .map(|l| l.width())
+            .max()
+            .unwrap_or(0) as u16
+            + 2
+    }
+
+    pub fn height(&self) -> u16 {
+        // +2 for borders
+        self.pattern.split('\n').count() as u16 + 2
     }
 
-    pub fn draw(&self, frame: &mut Frame, area: Rect, title: &str, style: Style) {
+    pub fn draw(&self, frame: &mut Frame, area: Rect, title: &str, style: Style, border: Style) {
         let input = Paragraph::new(self.pattern.as_str())
-            .block(Block::new().borders(Borders::all()).title(title))
+            .block(
+                Block::new()
+                    .borders(Borders::all())
+                    .border_style(border)
+                    .title(title),
+            )
             .style(style);
         frame.render_widget(input, area);
     }
@@ -270,6 +546,62 @@ mod tests {
         assert_eq!(app.cursor_pos, 6);
     }
 
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_auto_pairs_quotes() {
+        let mut app = LineInput::new(true);
+
+        input(&mut app, "\"a");
+        assert_eq!(app.pattern, "\"a\"");
+        assert_eq!(app.cursor_pos, 2);
+
+        input(&mut app, "\"");
+        assert_eq!(app.pattern, "\"a\"");
+        assert_eq!(app.cursor_pos, 3);
+
+        // apostrophes and escaped chars aren't paired
+        input(&mut app, " don't \\(");
+        assert_eq!(app.pattern, "\"a\" don't \\(");
+        assert_eq!(app.cursor_pos, 12);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_auto_pairs_delete() {
+        let mut app = LineInput::new(true);
+        let config = Config::default();
+
+        input(&mut app, "a([");
+        assert_eq!(app.pattern, "a([])");
+        assert_eq!(app.cursor_pos, 3);
+
+        assert_eq!(
+            app.handle_key_event(KeyCode::Backspace.into(), &config.keys),
+            Some("a()")
+        );
+        assert_eq!(app.cursor_pos, 2);
+
+        input(&mut app, "b");
+        assert_eq!(app.pattern, "a(b)");
+
+        // not an empty pair, so only one char is deleted
+        app.cursor_pos = 2;
+        assert_eq!(
+            app.handle_key_event(KeyCode::Backspace.into(), &config.keys),
+            Some("ab)")
+        );
+        assert_eq!(app.cursor_pos, 1);
+
+        // pairs are left alone without auto_pairs
+        let mut app = LineInput::new(false);
+        input(&mut app, "()");
+        app.cursor_pos = 1;
+        assert_eq!(
+            app.handle_key_event(KeyCode::Backspace.into(), &config.keys),
+            Some(")")
+        );
+    }
+
     #[test]
     #[tracing_test::traced_test]
     fn test_delete_word() {
@@ -325,6 +657,99 @@ mod tests {
         assert_eq!(app.cursor_pos, 0);
     }
 
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_yank() {
+        let mut app = LineInput::new(false);
+        let config = Config::default();
+        let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+
+        // nothing killed yet
+        assert_eq!(app.handle_key_event(ctrl('y'), &config.keys), None);
+
+        input(&mut app, "abc def ghi");
+        assert_eq!(
+            app.handle_key_event(ctrl('w'), &config.keys),
+            Some("abc def ")
+        );
+        app.cursor_pos = 0;
+        assert_eq!(
+            app.handle_key_event(ctrl('y'), &config.keys),
+            Some("ghiabc def ")
+        );
+        assert_eq!(app.cursor_pos, 3);
+
+        // consecutive kills accumulate
+        app.cursor_pos = 6;
+        assert_eq!(app.handle_key_event(ctrl('w'), &config.keys), Some(" def "));
+        assert_eq!(app.handle_key_event(ctrl('k'), &config.keys), Some(""));
+        assert_eq!(
+            app.handle_key_event(ctrl('y'), &config.keys),
+            Some("ghiabc def ")
+        );
+
+        // yanking again inserts the same text
+        assert_eq!(
+            app.handle_key_event(ctrl('y'), &config.keys),
+            Some("ghiabc def ghiabc def ")
+        );
+
+        // any other key starts a new kill
+        app.cursor_pos = 3;
+        assert_eq!(app.handle_key_event(ctrl('k'), &config.keys), Some("ghi"));
+        assert_eq!(
+            app.handle_key_event(KeyCode::Left.into(), &config.keys),
+            None
+        );
+        assert_eq!(app.handle_key_event(ctrl('k'), &config.keys), Some("gh"));
+        assert_eq!(app.handle_key_event(ctrl('y'), &config.keys), Some("ghi"));
+
+        assert_eq!(app.handle_key_event(ctrl('u'), &config.keys), Some(""));
+        input(&mut app, "x");
+        assert_eq!(app.handle_key_event(ctrl('y'), &config.keys), Some("xghi"));
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_undo() {
+        let mut app = LineInput::new(false);
+        let config = Config::default();
+        let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+
+        assert_eq!(app.handle_key_event(ctrl('z'), &config.keys), None);
+        assert_eq!(app.handle_key_event(ctrl('r'), &config.keys), None);
+
+        // typed chars are undone together
+        input(&mut app, "abc");
+        assert_eq!(
+            app.handle_key_event(KeyCode::Left.into(), &config.keys),
+            None
+        );
+        input(&mut app, "de");
+        assert_eq!(app.pattern, "abdec");
+        assert_eq!(app.handle_key_event(ctrl('z'), &config.keys), Some("abc"));
+        assert_eq!(app.cursor_pos, 2);
+        assert_eq!(app.handle_key_event(ctrl('z'), &config.keys), Some(""));
+        assert_eq!(app.cursor_pos, 0);
+        assert_eq!(app.handle_key_event(ctrl('z'), &config.keys), None);
+
+        assert_eq!(app.handle_key_event(ctrl('r'), &config.keys), Some("abc"));
+        assert_eq!(app.cursor_pos, 2);
+        assert_eq!(app.handle_key_event(ctrl('r'), &config.keys), Some("abdec"));
+        assert_eq!(app.cursor_pos, 4);
+        assert_eq!(app.handle_key_event(ctrl('r'), &config.keys), None);
+
+        // recover a deleted line
+        assert_eq!(app.handle_key_event(ctrl('u'), &config.keys), Some(""));
+        assert_eq!(app.handle_key_event(ctrl('z'), &config.keys), Some("abdec"));
+
+        // a new edit clears the redo stack
+        assert_eq!(app.handle_key_event(ctrl('z'), &config.keys), Some("abc"));
+        assert_eq!(app.insert("x", false), Some("abxc"));
+        assert_eq!(app.handle_key_event(ctrl('r'), &config.keys), None);
+        assert_eq!(app.handle_key_event(ctrl('z'), &config.keys), Some("abc"));
+    }
+
     #[test]
     #[tracing_test::traced_test]
     fn test_cursor_movement() {
@@ -374,6 +799,94 @@ mod tests {
         assert_eq!(app.cursor_pos, 11);
     }
 
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_cursor_word() {
+        let mut app = LineInput::new(false);
+        let config = Config::default();
+        let alt_b = KeyEvent::new(KeyCode::Char('b'), KeyModifiers::ALT);
+        let alt_f = KeyEvent::new(KeyCode::Char('f'), KeyModifiers::ALT);
+
+        input(&mut app, "abc  def   ghi");
+        app.cursor_pos = 6; // within "def"
+
+        assert_eq!(app.handle_key_event(alt_b, &config.keys), None);
+        assert_eq!(app.cursor_pos, 5);
+        assert_eq!(app.handle_key_event(alt_b, &config.keys), None);
+        assert_eq!(app.cursor_pos, 0);
+        assert_eq!(app.handle_key_event(alt_b, &config.keys), None);
+        assert_eq!(app.cursor_pos, 0);
+
+        assert_eq!(app.handle_key_event(alt_f, &config.keys), None);
+        assert_eq!(app.cursor_pos, 3);
+        assert_eq!(app.handle_key_event(alt_f, &config.keys), None);
+        assert_eq!(app.cursor_pos, 8);
+        assert_eq!(app.handle_key_event(alt_f, &config.keys), None);
+        assert_eq!(app.cursor_pos, 14);
+        assert_eq!(app.handle_key_event(alt_f, &config.keys), None);
+        assert_eq!(app.cursor_pos, 14);
+
+        // from the middle of a run of spaces
+        app.cursor_pos = 10;
+        assert_eq!(app.handle_key_event(alt_b, &config.keys), None);
+        assert_eq!(app.cursor_pos, 5);
+        app.cursor_pos = 9;
+        assert_eq!(app.handle_key_event(alt_f, &config.keys), None);
+        assert_eq!(app.cursor_pos, 14);
+
+        // multi-byte chars
+        let mut app = LineInput::new(false);
+        input(&mut app, "héllo wörld");
+        assert_eq!(app.handle_key_event(alt_b, &config.keys), None);
+        assert_eq!(app.cursor_pos, "héllo ".len());
+        assert_eq!(app.handle_key_event(alt_b, &config.keys), None);
+        assert_eq!(app.cursor_pos, 0);
+        assert_eq!(app.handle_key_event(alt_f, &config.keys), None);
+        assert_eq!(app.cursor_pos, "héllo".len());
+        assert_eq!(app.pattern, "héllo wörld");
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_transpose_char() {
+        let config = Config::default();
+        let ctrl_t = KeyEvent::new(KeyCode::Char('t'), KeyModifiers::CONTROL);
+
+        let mut app = LineInput::new(false);
+        input(&mut app, "abcd");
+        app.cursor_pos = 2;
+        assert_eq!(app.handle_key_event(ctrl_t, &config.keys), Some("acbd"));
+        assert_eq!(app.cursor_pos, 3);
+        assert_eq!(app.handle_key_event(ctrl_t, &config.keys), Some("acdb"));
+        assert_eq!(app.cursor_pos, 4);
+
+        // at the end, swap the last two
+        assert_eq!(app.handle_key_event(ctrl_t, &config.keys), Some("acbd"));
+        assert_eq!(app.cursor_pos, 4);
+
+        // at the start, nothing to transpose
+        app.cursor_pos = 0;
+        assert_eq!(app.handle_key_event(ctrl_t, &config.keys), None);
+        assert_eq!(app.pattern, "acbd");
+
+        // multi-byte chars
+        let mut app = LineInput::new(false);
+        input(&mut app, "aé");
+        assert_eq!(app.handle_key_event(ctrl_t, &config.keys), Some("éa"));
+        assert_eq!(app.cursor_pos, "éa".len());
+        app.cursor_pos = "é".len();
+        assert_eq!(app.handle_key_event(ctrl_t, &config.keys), Some("aé"));
+        assert_eq!(app.cursor_pos, "aé".len());
+
+        // too short
+        let mut app = LineInput::new(false);
+        assert_eq!(app.handle_key_event(ctrl_t, &config.keys), None);
+        input(&mut app, "a");
+        assert_eq!(app.handle_key_event(ctrl_t, &config.keys), None);
+        assert_eq!(app.pattern, "a");
+        assert_eq!(app.cursor_pos, 1);
+    }
+
     #[test]
     #[tracing_test::traced_test]
     fn test_cursor_input() {
@@ -612,6 +1125,252 @@ mod tests {
         assert_eq!(app.cursor_pos, 0);
     }
 
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_unicode() {
+        let mut app = LineInput::new(false);
+        let config = Config::default();
+
+        input(&mut app, "aé你👍b");
+        assert_eq!(app.pattern, "aé你👍b");
+        assert_eq!(app.cursor_pos(), 7);
+
+        assert_eq!(
+            app.handle_key_event(KeyCode::Left.into(), &config.keys),
+            None
+        );
+        assert_eq!(app.cursor_pos(), 6);
+
+        // backspace removes the emoji before the cursor
+        assert_eq!(
+            app.handle_key_event(KeyCode::Backspace.into(), &config.keys),
+            Some("aé你b")
+        );
+        assert_eq!(app.cursor_pos(), 4);
+
+        input(&mut app, "ü");
+        assert_eq!(app.pattern, "aé你üb");
+        assert_eq!(app.cursor_pos(), 5);
+
+        // ctrl-d removes the char at the cursor
+        for _ in 0..3 {
+            assert_eq!(
+                app.handle_key_event(KeyCode::Left.into(), &config.keys),
+                None
+            );
+        }
+        assert_eq!(app.cursor_pos(), 1);
+        assert_eq!(
+            app.handle_key_event(
+                KeyEvent::new(KeyCode::Char('d'), KeyModifiers::CONTROL),
+                &config.keys
+            ),
+            Some("a你üb")
+        );
+        assert_eq!(app.cursor_pos(), 1);
+
+        assert_eq!(
+            app.handle_key_event(KeyCode::Right.into(), &config.keys),
+            None
+        );
+        assert_eq!(app.cursor_pos(), 3);
+        assert_eq!(
+            app.handle_key_event(KeyCode::Backspace.into(), &config.keys),
+            Some("aüb")
+        );
+        assert_eq!(app.cursor_pos(), 1);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_newline() {
+        let mut app = LineInput::new(false);
+        let config = Config::default();
+        let newline = KeyEvent::new(KeyCode::Char('j'), KeyModifiers::CONTROL);
+
+        input(&mut app, "foo");
+        assert_eq!(app.handle_key_event(newline, &config.keys), Some("foo\n"));
+        assert_eq!((app.cursor_line(), app.cursor_pos()), (1, 0));
+        assert_eq!((app.size(), app.height()), (3 + 2, 2 + 2));
+
+        input(&mut app, "ba");
+        assert_eq!(app.pattern, "foo\nba");
+        assert_eq!((app.cursor_line(), app.cursor_pos()), (1, 2));
+
+        // home and end stay on the cursor's line
+        app.handle_key_event(KeyCode::Home.into(), &config.keys);
+        assert_eq!((app.cursor_line(), app.cursor_pos()), (1, 0));
+        app.handle_key_event(KeyCode::Left.into(), &config.keys);
+        assert_eq!((app.cursor_line(), app.cursor_pos()), (0, 3));
+        app.handle_key_event(KeyCode::Home.into(), &config.keys);
+        assert_eq!(app.cursor_pos, 0);
+        app.handle_key_event(KeyCode::End.into(), &config.keys);
+        assert_eq!(app.cursor_pos, 3);
+
+        // ctrl-k only deletes to the end of the line
+        app.handle_key_event(KeyCode::Left.into(), &config.keys);
+        assert_eq!(
+            app.handle_key_event(
+                KeyEvent::new(KeyCode::Char('k'), KeyModifiers::CONTROL),
+                &config.keys
+            ),
+            Some("fo\nba")
+        );
+
+        // ctrl-u only deletes the cursor's line
+        let delete_line = KeyEvent::new(KeyCode::Char('u'), KeyModifiers::CONTROL);
+        assert_eq!(
+            app.handle_key_event(delete_line, &config.keys),
+            Some("\nba")
+        );
+        assert_eq!((app.cursor_line(), app.cursor_pos()), (0, 0));
+        assert_eq!(app.handle_key_event(delete_line, &config.keys), None);
+        app.handle_key_event(KeyCode::End.into(), &config.keys);
+        app.handle_key_event(KeyCode::Right.into(), &config.keys);
+        assert_eq!(app.handle_key_event(delete_line, &config.keys), Some("\n"));
+        assert_eq!((app.cursor_line(), app.cursor_pos()), (1, 0));
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_wide_chars() {
+        let mut app = LineInput::new(false);
+        let config = Config::default();
+
+        input(&mut app, "你好");
+        assert_eq!(app.pattern.len(), 6);
+        assert_eq!(app.cursor_pos(), 4);
+        assert_eq!(app.size(), 4 + 2);
+
+        assert_eq!(
+            app.handle_key_event(KeyCode::Left.into(), &config.keys),
+            None
+        );
+        assert_eq!(app.cursor_pos(), 2);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_unicode_delete_word() {
+        let mut app = LineInput::new(false);
+        let config = Config::default();
+
+        // includes a multi-byte ideographic space
+        input(&mut app, "héllo 你好\u{3000}wörld 👍");
+        assert_eq!(
+            app.handle_key_event(
+                KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL),
+                &config.keys
+            ),
+            Some("héllo 你好\u{3000}wörld ")
+        );
+        assert_eq!(app.cursor_pos(), 18);
+
+        assert_eq!(
+            app.handle_key_event(
+                KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL),
+                &config.keys
+            ),
+            Some("héllo 你好\u{3000}")
+        );
+        assert_eq!(app.cursor_pos(), 12);
+
+        assert_eq!(
+            app.handle_key_event(
+                KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL),
+                &config.keys
+            ),
+            Some("héllo ")
+        );
+        assert_eq!(app.cursor_pos(), 6);
+
+        assert_eq!(
+            app.handle_key_event(
+                KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL),
+                &config.keys
+            ),
+            Some("")
+        );
+        assert_eq!(app.cursor_pos(), 0);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_insert() {
+        let mut app = LineInput::new(true);
+        let config = Config::default();
+
+        input(&mut app, "ab");
+        app.handle_key_event(KeyCode::Left.into(), &config.keys);
+        assert_eq!(app.insert("(x", false), Some("a(xb"));
+        assert_eq!(app.cursor_pos, 3);
+
+        assert_eq!(
+            app.insert("foo\nbar\r\nbaz\n", false),
+            Some("a(xfoo bar bazb")
+        );
+        assert_eq!(app.cursor_pos, 14);
+
+        assert_eq!(app.insert("", false), None);
+
+        let mut app = LineInput::new(false);
+        assert_eq!(app.insert("foo\nbar\r\n", true), Some("foo\nbar\n"));
+        assert_eq!(app.cursor_pos, 8);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_history() {
+        let mut app = LineInput::new(false);
+        let config = Config::default();
+
+        // nothing to recall
+        assert_eq!(app.handle_key_event(KeyCode::Up.into(), &config.keys), None);
+
+        for s in ["one", "two", "two", "three"] {
+            app.handle_key_event(
+                KeyEvent::new(KeyCode::Char('u'), KeyModifiers::CONTROL),
+                &config.keys,
+            );
+            input(&mut app, s);
+            app.push_history(2);
+        }
+        // consecutive duplicates are skipped and the oldest entries dropped
+        assert_eq!(app.history().collect::<Vec<_>>(), ["two", "three"]);
+
+        app.handle_key_event(
+            KeyEvent::new(KeyCode::Char('u'), KeyModifiers::CONTROL),
+            &config.keys,
+        );
+        input(&mut app, "draft");
+
+        assert_eq!(
+            app.handle_key_event(KeyCode::Up.into(), &config.keys),
+            Some("three")
+        );
+        assert_eq!(app.cursor_pos, 5);
+        assert_eq!(
+            app.handle_key_event(KeyCode::Up.into(), &config.keys),
+            Some("two")
+        );
+        assert_eq!(app.cursor_pos, 3);
+        assert_eq!(app.handle_key_event(KeyCode::Up.into(), &config.keys), None);
+        assert_eq!(
+            app.handle_key_event(KeyCode::Down.into(), &config.keys),
+            Some("three")
+        );
+        // going past the newest entry restores what was being typed
+        assert_eq!(
+            app.handle_key_event(KeyCode::Down.into(), &config.keys),
+            Some("draft")
+        );
+        assert_eq!(
+            app.handle_key_event(KeyCode::Down.into(), &config.keys),
+            None
+        );
+        assert_eq!(app.pattern, "draft");
+    }
+
     #[test]
     #[tracing_test::traced_test]
     fn test_delete_char_backward() {
@@ -1,5 +1,7 @@
+pub mod batch;
 pub mod config;
 pub mod finder;
+pub mod highlight;
 pub mod input;
 pub mod search;
 pub mod tui;
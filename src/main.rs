@@ -1,17 +1,28 @@
-use std::path::PathBuf;
+use std::{
+    io::{IsTerminal as _, Read as _, Write as _},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use anyhow::{Result, bail};
-use clap::Parser;
+use anyhow::{Context, Result, bail};
+use clap::{ArgAction, ArgGroup, Parser, ValueEnum};
 use etcetera::{AppStrategy, AppStrategyArgs, choose_app_strategy};
-use lasr::config::Config;
+use lasr::config::{Action, Binary, Config, FileSize, FileType};
+use lasr::finder::{Finder, RegexParams, SearchMode, SearchParams};
 use lasr::tui::App;
-use tracing::debug;
+use tracing::{debug, warn};
 use tracing_error::ErrorLayer;
-use tracing_subscriber::{Layer as _, layer::SubscriberExt as _, util::SubscriberInitExt as _};
+use tracing_subscriber::{
+    EnvFilter, Layer as _, filter::LevelFilter, layer::SubscriberExt as _,
+    util::SubscriberInitExt as _,
+};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 #[command(propagate_version = true)]
+#[command(group(ArgGroup::new("batch").args(["replace", "json", "files_with_matches"])))]
 pub struct Cli {
     /// Paths to search, defaults to "."
     paths: Vec<PathBuf>,
@@ -33,6 +44,83 @@ pub struct Cli {
     /// Matches can span multiple lines
     multi_line: bool,
 
+    #[arg(short, long)]
+    /// Only match whole words
+    word_regexp: bool,
+
+    #[arg(short = 'v', long, conflicts_with = "replace")]
+    /// Show lines that don't match the pattern. There is nothing to replace, so the replacement is disabled.
+    invert_match: bool,
+
+    #[arg(short = 'F', long)]
+    /// Treat the pattern and replacement as literal strings, rather than a regex or ast-grep pattern
+    fixed_strings: bool,
+
+    #[arg(long, value_name = "MODE", default_value = "auto")]
+    /// Treat the pattern as a regex or ast-grep pattern. auto uses ast-grep if the pattern contains a meta-variable like $X.
+    /// One of auto, regex, ast.
+    mode: SearchMode,
+
+    #[arg(long)]
+    /// Search files ignored by .gitignore or .ignore files, and hidden files
+    no_ignore: bool,
+
+    #[arg(short = 'H', long)]
+    /// Search hidden files and directories, while still respecting ignore files
+    hidden: bool,
+
+    #[arg(long = "ignore-file", value_name = "PATH")]
+    /// Ignore files matching the gitignore-style globs in PATH. May be repeated.
+    /// These have the lowest precedence, and are respected even with --no-ignore.
+    ignore_files: Vec<PathBuf>,
+
+    #[arg(long, value_name = "DIR")]
+    /// Treat DIR as the root of the git repository, overriding `git_root` in the config.
+    /// Ignore files apply as if searching from DIR, whatever paths are given, and none above it are read.
+    git_root: Option<PathBuf>,
+
+    #[arg(short = 'j', long, value_name = "NUM")]
+    /// Search with NUM threads, overriding `threads` in the config. 0 picks a number automatically.
+    threads: Option<usize>,
+
+    #[arg(long, value_name = "NUM")]
+    /// Descend at most NUM directories below the given paths. 0 searches only the paths themselves.
+    max_depth: Option<usize>,
+
+    #[arg(long, value_name = "MODE")]
+    /// How to search files containing a NUL byte, overriding `binary` in the config.
+    /// quit stops searching the file at the NUL, convert treats NULs as line endings, and text searches the whole file.
+    binary: Option<Binary>,
+
+    #[arg(long, value_name = "SIZE")]
+    /// Skip files larger than SIZE bytes. Accepts a K, M, or G suffix, e.g. 10M.
+    max_filesize: Option<FileSize>,
+
+    #[arg(long, value_name = "FILE", conflicts_with = "paths")]
+    /// Search only the files listed in FILE, one per line, or "-" to read them from stdin
+    files_from: Option<PathBuf>,
+
+    #[arg(short = '0', long, requires = "files_from")]
+    /// Paths given to --files-from are separated by NUL rather than newlines, as from `find -print0`
+    null: bool,
+
+    #[arg(long, value_name = "START:END", value_parser = lasr::finder::parse_lines, conflicts_with = "replace")]
+    /// Only show matches on lines START to END of each file. Multi-line matches are shown if any of their lines are in range.
+    /// Either end may be omitted, e.g. 100: searches from line 100 to the end.
+    lines: Option<RangeInclusive<u64>>,
+
+    #[arg(short = 'A', long, value_name = "NUM")]
+    /// Show NUM lines after each match
+    after_context: Option<usize>,
+
+    #[arg(short = 'B', long, value_name = "NUM")]
+    /// Show NUM lines before each match
+    before_context: Option<usize>,
+
+    #[arg(short = 'C', long, value_name = "NUM")]
+    /// Show NUM lines before and after each match
+    context: Option<usize>,
+
     #[arg(short, long = "type", default_values_t=["all".to_string()])]
     /// File types to search, use --type-list to view available types
     types: Vec<String>,
@@ -40,6 +128,80 @@ pub struct Cli {
     #[arg(long)]
     /// List all file types available to -t
     type_list: bool,
+
+    #[arg(long)]
+    /// List all actions that keys can be bound to in the config
+    list_actions: bool,
+
+    #[arg(short, long, requires = "batch")]
+    /// Pattern to search for. Replaces without starting the UI if --replace is also given.
+    pattern: Option<String>,
+
+    #[arg(short, long, requires = "pattern")]
+    /// Replacement for --pattern. Replaces without starting the UI if --pattern is also given.
+    replace: Option<String>,
+
+    #[arg(long, requires = "replace")]
+    /// Print a diff of the changes --replace would make, without modifying any files
+    dry_run: bool,
+
+    #[arg(long, requires = "replace")]
+    /// Replace even if more files match than confirm_threshold in the config
+    force: bool,
+
+    #[arg(long, requires = "pattern", conflicts_with = "replace")]
+    /// Print matches for --pattern as JSON, one object per file, without starting the UI
+    json: bool,
+
+    #[arg(long, value_enum, default_value_t)]
+    /// When to color output outside the UI, auto colors only if stdout is a terminal
+    color: ColorChoice,
+
+    #[arg(short = 'l', long, requires = "pattern", conflicts_with_all = ["replace", "json"])]
+    /// Print the path of each file matching --pattern, without starting the UI
+    files_with_matches: bool,
+
+    #[arg(long)]
+    /// Print results in path order. With more than one thread, results are printed once the whole search completes.
+    sorted: bool,
+
+    #[arg(long, conflicts_with_all = ["paths", "files_from", "batch"])]
+    /// Restore the pattern, replacement, flags, and paths of the last search in the UI
+    resume: bool,
+
+    #[arg(long)]
+    /// Print statistics about the search when it completes, like the number of files searched
+    stats: bool,
+
+    #[arg(long, action = ArgAction::Count, conflicts_with = "quiet")]
+    /// Log more detail, repeat for more (info, debug, trace). Without the UI, logs are also printed to stderr.
+    /// RUST_LOG takes precedence if set.
+    verbose: u8,
+
+    #[arg(short, long)]
+    /// Don't log anything, unless RUST_LOG is set
+    quiet: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    // Whether to write ANSI colors to stdout, honoring https://no-color.org in auto mode
+    fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
 }
 
 fn strategy() -> AppStrategyArgs {
@@ -50,7 +212,31 @@ fn strategy() -> AppStrategyArgs {
     }
 }
 
-fn initialize_logging() -> Result<()> {
+// The level to log at unless RUST_LOG is set, errors only by default
+fn log_level(verbose: u8, quiet: bool) -> LevelFilter {
+    match verbose {
+        _ if quiet => LevelFilter::OFF,
+        0 => LevelFilter::ERROR,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+// Logs go to log.txt, and also to stderr if given, which would garble the UI
+fn initialize_logging(level: LevelFilter, stderr: bool) -> Result<()> {
+    let filter = || {
+        EnvFilter::builder()
+            .with_default_directive(level.into())
+            .from_env_lossy()
+    };
+    let stderr_subscriber = stderr.then(|| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(std::io::stderr)
+            .with_target(false)
+            .with_ansi(std::io::stderr().is_terminal())
+            .with_filter(filter())
+    });
     let strategy = choose_app_strategy(strategy())?;
     let cache_dir = strategy.cache_dir();
     let log_path = cache_dir.join("log.txt");
@@ -61,56 +247,78 @@ fn initialize_logging() -> Result<()> {
         .with_writer(log_file)
         .with_target(false)
         .with_ansi(false)
-        .with_filter(tracing_subscriber::filter::EnvFilter::from_default_env());
+        .with_filter(filter());
     tracing_subscriber::registry()
         .with(file_subscriber)
+        .with(stderr_subscriber)
         .with(ErrorLayer::default())
         .init();
     Ok(())
 }
 
-fn load_config(path: Option<PathBuf>) -> Result<Config> {
+// The config file to load, or None if loading is disabled by an empty path
+fn config_path(path: Option<PathBuf>) -> Result<Option<PathBuf>> {
     let path = if let Some(path) = path {
         path
     } else {
         let strategy = choose_app_strategy(strategy())?;
         strategy.config_dir().join("lasr.toml")
     };
-    if path.as_os_str().is_empty() {
+    Ok((!path.as_os_str().is_empty()).then_some(path))
+}
+
+fn load_config(path: Option<&Path>) -> Result<Config> {
+    let Some(path) = path else {
         debug!("Skipping config load");
         return Ok(Config::default());
-    }
+    };
     debug!("Loading config from {path:?}");
-    match std::fs::read_to_string(path) {
-        Ok(s) => Ok(toml::from_str(&s)?),
-        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
-        Err(err) => bail!(err),
+    Config::load(path)
+}
+
+// The finder for a pattern given on the command line, warning about flags it ignores
+fn batch_finder(pattern: &str, params: &RegexParams, filetypes: &[FileType]) -> Result<Finder> {
+    let finder = Finder::with_filetypes(pattern, params, filetypes)
+        .with_context(|| format!("Invalid pattern: {pattern}"))?;
+    if finder.is_ast() && (params.ignore_case || params.whole_word) {
+        eprintln!("Warning: --ignore-case and --word-regexp don't apply to ast-grep patterns");
     }
+    Ok(finder)
 }
 
 fn main() -> Result<()> {
-    initialize_logging()?;
-
     let cli = Cli::parse();
 
-    if cli.type_list {
-        let mut types = ignore::types::TypesBuilder::new();
-        types.add_defaults();
-        for def in types.build()?.definitions() {
-            println!("{}: {:?}", def.name(), def.globs());
+    // only the batch modes take a pattern on the command line
+    let batch = cli.pattern.is_some();
+    initialize_logging(log_level(cli.verbose, cli.quiet), batch && cli.verbose > 0)?;
+
+    if cli.list_actions {
+        for action in Action::ALL {
+            println!("{action}: {}", action.description());
         }
         return Ok(());
     }
 
-    let config = load_config(cli.config_path)?;
+    let config_path = config_path(cli.config_path)?;
+
+    let mut config = load_config(config_path.as_deref())?;
+    let watch_config = config.watch_config;
 
     if cli.dump_config {
         print!("{}", toml::to_string_pretty(&config)?);
         return Ok(());
     }
+    config.stats |= cli.stats;
 
-    let mut types = ignore::types::TypesBuilder::new();
-    types.add_defaults();
+    if cli.type_list {
+        for def in config.types()?.build()?.definitions() {
+            println!("{}: {:?}", def.name(), def.globs());
+        }
+        return Ok(());
+    }
+
+    let mut types = config.types()?;
     for t in cli.types {
         types.select(&t);
     }
@@ -122,33 +330,190 @@ fn main() -> Result<()> {
         }
     };
 
-    let mut terminal = ratatui::init();
-    crossterm::execute!(
-        std::io::stdout(),
-        crossterm::cursor::SetCursorStyle::BlinkingBar
-    )?;
+    let regex_params = RegexParams {
+        mode: cli.mode,
+        ignore_case: cli.ignore_case,
+        multi_line: cli.multi_line,
+        whole_word: cli.word_regexp,
+        fixed_string: cli.fixed_strings,
+        invert_match: cli.invert_match,
+        before_context: cli.before_context.or(cli.context).unwrap_or(0),
+        after_context: cli.after_context.or(cli.context).unwrap_or(0),
+        lines: cli.lines,
+        max_matches: config.max_matches_per_file,
+        binary: cli.binary.unwrap_or(config.binary),
+    };
+
+    // piped text with nowhere else to search, as in `cat big.log | lasr`, is searched in the UI
+    let search_stdin = !batch
+        && !cli.resume
+        && cli.paths.is_empty()
+        && cli.files_from.is_none()
+        && !std::io::stdin().is_terminal();
 
-    let (tx, rx) = crossbeam::channel::bounded(0);
-    std::thread::spawn(move || {
-        loop {
-            let ev = crossterm::event::read().unwrap();
-            if tx.send(ev).is_err() {
-                break;
-            };
+    let files = match cli.files_from {
+        Some(path) if path == Path::new("-") => {
+            let stdin = std::io::stdin();
+            if stdin.is_terminal() {
+                bail!("--files-from - expects a list of paths piped to stdin");
+            }
+            Some(lasr::search::read_paths(stdin.lock(), cli.null)?)
         }
-    });
+        Some(path) => {
+            let file =
+                std::fs::File::open(&path).with_context(|| format!("Failed to open {path:?}"))?;
+            Some(lasr::search::read_paths(file, cli.null)?)
+        }
+        None => None,
+    };
+
+    let stdin = if search_stdin {
+        if !std::io::stdout().is_terminal() {
+            bail!("Searching stdin needs stdout to be a terminal to show the UI");
+        }
+        let mut text = String::new();
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .context("Failed to read stdin")?;
+        Some(Arc::new(text))
+    } else {
+        None
+    };
+
+    let search_params = SearchParams {
+        paths: if cli.paths.is_empty() {
+            vec![".".into()]
+        } else {
+            cli.paths
+        },
+        types,
+        threads: cli.threads.unwrap_or(config.threads),
+        no_ignore: cli.no_ignore,
+        hidden: cli.hidden,
+        ignore_files: config.ignore_files.clone(),
+        ignore_paths: cli.ignore_files,
+        git_root: cli.git_root.or(config.git_root.clone()),
+        max_depth: cli.max_depth.or(config.max_depth),
+        max_filesize: cli.max_filesize.or(config.max_filesize).map(u64::from),
+        files,
+        stdin,
+        stats: Arc::default(),
+        sorted: cli.sorted,
+    };
+    let stats = search_params.stats.clone();
+    // checked up front, as the UI searches in the background and can't show a failed search
+    lasr::search::check_paths(&search_params.paths)?;
+    lasr::search::check_git_root(&search_params)?;
+
+    let filetypes = config.filetypes()?;
+
+    if let Some(pattern) = cli.pattern.as_deref()
+        && (cli.json || cli.files_with_matches)
     {
-        let mut app = App::new(
-            cli.paths,
-            types,
-            config,
-            rx,
-            cli.ignore_case,
-            cli.multi_line,
-        );
-        app.run(&mut terminal)?;
+        let finder = batch_finder(pattern, &regex_params, &filetypes)?;
+        let mut out = std::io::stdout().lock();
+        if cli.json {
+            lasr::batch::json(finder, search_params, &mut out)?;
+            if cli.stats {
+                serde_json::to_writer(&mut out, &stats.summary())?;
+                writeln!(out)?;
+            }
+        } else {
+            lasr::batch::files_with_matches(finder, search_params, &mut out, cli.color.enabled())?;
+            if cli.stats {
+                writeln!(out, "\n{}", stats.summary())?;
+            }
+        }
+        return Ok(());
     }
 
-    ratatui::restore();
-    Ok(())
+    if let (Some(pattern), Some(replacement)) = (cli.pattern, cli.replace) {
+        let finder = batch_finder(&pattern, &regex_params, &filetypes)?;
+        if cli.dry_run {
+            let mut out = std::io::stdout().lock();
+            lasr::batch::diff(
+                finder,
+                search_params,
+                &replacement,
+                &mut out,
+                cli.color.enabled(),
+            )?;
+        } else {
+            let max_files =
+                (!cli.force && config.confirm_threshold > 0).then_some(config.confirm_threshold);
+            let report = lasr::batch::replace(
+                finder,
+                search_params,
+                &replacement,
+                max_files,
+                &config.backup,
+            )?;
+            println!("{report}");
+        }
+        if cli.stats {
+            println!("\n{}", stats.summary());
+        }
+        return Ok(());
+    }
+
+    let mut terminal = lasr::tui::init()?;
+    // ratatui restores the terminal on panic, but doesn't know about mouse capture or pasting
+    let panic_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        _ = crossterm::execute!(
+            std::io::stdout(),
+            crossterm::event::DisableMouseCapture,
+            crossterm::event::DisableBracketedPaste
+        );
+        panic_hook(info);
+    }));
+
+    let (tx, rx) = crossbeam::channel::bounded(0);
+    // Held while reading events, so the app can stop reading while it runs an editor
+    let events = Arc::new(Mutex::new(()));
+    std::thread::spawn({
+        let events = events.clone();
+        move || {
+            loop {
+                let ev = {
+                    let _lock = events.lock();
+                    if !crossterm::event::poll(Duration::from_millis(50)).unwrap() {
+                        continue;
+                    }
+                    crossterm::event::read().unwrap()
+                };
+                if tx.send(ev).is_err() {
+                    break;
+                };
+            }
+        }
+    });
+    let cache_dir = choose_app_strategy(strategy())?.cache_dir();
+    let (res, output) = {
+        let mut app = App::new(search_params, regex_params, config, rx);
+        app.load_history(&cache_dir);
+        if cli.resume {
+            app.resume(&cache_dir);
+        }
+        if let Some(path) = config_path.filter(|_| watch_config)
+            && let Err(err) = app.watch_config(path)
+        {
+            warn!("Failed to watch config: {err:#}");
+        }
+        let res = app.run(&mut terminal, &events);
+        if let Err(err) = app.save_history(&cache_dir) {
+            warn!("Failed to save history: {err}");
+        }
+        if let Err(err) = app.save_session(&cache_dir) {
+            warn!("Failed to save session: {err:#}");
+        }
+        (res, app.take_output())
+    };
+
+    lasr::tui::restore();
+    // the replaced stdin is printed once the UI is gone
+    if let Some(output) = output {
+        print!("{output}");
+    }
+    res
 }
@@ -1,89 +1,436 @@
 #![allow(clippy::single_range_in_vec_init)]
-use crate::finder::{FileMatch, Finder, SearchParams};
-use anyhow::Result;
-use crossbeam::channel::Sender;
+use crate::finder::{FileMatch, Finder, LineMatch, STDIN_PATH, SearchParams, count};
+use anyhow::{Context, Result, bail};
+use crossbeam::channel::{Sender, unbounded};
 use ignore::WalkState;
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    fmt::Display,
+    fs::Metadata,
+    io::Read,
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
 use tracing::{debug, warn};
 
+// Totals for a search, counted by each search thread
+#[derive(Debug)]
+pub struct Stats {
+    started: Instant,
+    files_searched: AtomicUsize,
+    files_matched: AtomicUsize,
+    matches: AtomicUsize,
+    bytes: AtomicU64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            files_searched: AtomicUsize::new(0),
+            files_matched: AtomicUsize::new(0),
+            matches: AtomicUsize::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Stats {
+    // The totals so far, and the time since the stats were created
+    pub fn summary(&self) -> Summary {
+        Summary {
+            files_searched: self.files_searched.load(Ordering::Relaxed),
+            files_matched: self.files_matched.load(Ordering::Relaxed),
+            matches: self.matches.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            elapsed: self.started.elapsed(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename = "summary")]
+pub struct Summary {
+    pub files_searched: usize,
+    pub files_matched: usize,
+    pub matches: usize,
+    pub bytes: u64,
+    #[serde(serialize_with = "as_secs")]
+    pub elapsed: Duration,
+}
+
+fn as_secs<S: serde::Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(d.as_secs_f64())
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} in {}, {} searched, {} searched, {:.3}s",
+            count(self.matches, "match", "matches"),
+            count(self.files_matched, "file", "files"),
+            count(self.files_searched, "file", "files"),
+            count(self.bytes as usize, "byte", "bytes"),
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+// Whether the error is from a file being removed or made unreadable after the walk found it,
+// which is common in an active repo and not worth a warning
+fn is_vanished(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| {
+        let io = e.downcast_ref::<std::io::Error>().or_else(|| {
+            e.downcast_ref::<ignore::Error>()
+                .and_then(ignore::Error::io_error)
+        });
+        io.is_some_and(|e| {
+            matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied
+            )
+        })
+    })
+}
+
 fn walk(
     finder: &mut Finder,
     path: Result<ignore::DirEntry, ignore::Error>,
+    params: &SearchParams,
+    bases: &[Base],
     tx: &Sender<FileMatch>,
-) -> Result<WalkState> {
+) -> WalkState {
     debug!("Searching path {path:?}");
-    let path = path?;
-    let meta = path.metadata()?;
+    let res = path.map_err(Into::into).and_then(|entry| {
+        let path = rebase(entry.path(), bases);
+        let meta = entry
+            .metadata()
+            .with_context(|| format!("Reading metadata of {path:?}"))?;
+        search_file(finder, &path, &meta, params, tx).with_context(|| format!("Searching {path:?}"))
+    });
+    match res {
+        Ok(state) => state,
+        Err(e) if is_vanished(&e) => {
+            debug!("Skipping: {e:#}");
+            WalkState::Continue
+        }
+        Err(e) => {
+            warn!("Search error: {e:#}");
+            WalkState::Continue
+        }
+    }
+}
+
+fn search_file(
+    finder: &mut Finder,
+    path: &Path,
+    meta: &Metadata,
+    params: &SearchParams,
+    tx: &Sender<FileMatch>,
+) -> Result<WalkState> {
+    let SearchParams {
+        max_filesize,
+        stats,
+        ..
+    } = params;
     if !meta.is_file() {
         return Ok(WalkState::Continue);
     };
-    let lines = finder.find(path.path())?;
-    if lines.is_empty() {
+    // The walker skips large files, but not those passed explicitly as paths
+    if max_filesize.is_some_and(|max| meta.len() > max) {
+        debug!("Skipping {path:?}, size {} exceeds limit", meta.len());
         return Ok(WalkState::Continue);
     }
+    let lines = finder.find(path)?;
+    Ok(send_matches(finder, path, lines, meta.len(), stats, tx))
+}
+
+// Count a searched file in the stats, and send its matches if it had any
+fn send_matches(
+    finder: &Finder,
+    path: &Path,
+    mut lines: Vec<LineMatch>,
+    len: u64,
+    stats: &Stats,
+    tx: &Sender<FileMatch>,
+) -> WalkState {
+    let truncated = finder.truncate(&mut lines);
+    stats.files_searched.fetch_add(1, Ordering::Relaxed);
+    stats.bytes.fetch_add(len, Ordering::Relaxed);
+    if lines.is_empty() {
+        return WalkState::Continue;
+    }
+    let matches = lines.iter().map(|l| l.ranges.len()).sum();
+    stats.files_matched.fetch_add(1, Ordering::Relaxed);
+    stats.matches.fetch_add(matches, Ordering::Relaxed);
     if tx
         .send(FileMatch {
-            path: path.into_path(),
+            path: path.into(),
+            lang: finder.language(path),
             lines,
+            truncated,
         })
         .is_err()
     {
         debug!("TX closed, ending search thread");
-        return Ok(WalkState::Quit);
+        return WalkState::Quit;
     }
-    Ok(WalkState::Continue)
+    WalkState::Continue
+}
+
+// Read a list of paths, one per line or separated by NUL bytes, e.g. from `fd` or `find -print0`
+pub fn read_paths(mut reader: impl Read, nul: bool) -> Result<Vec<PathBuf>> {
+    let mut text = String::new();
+    reader
+        .read_to_string(&mut text)
+        .context("Failed to read paths")?;
+    let paths = if nul {
+        text.split('\0').map(PathBuf::from).collect::<Vec<_>>()
+    } else {
+        text.lines().map(PathBuf::from).collect()
+    };
+    Ok(paths
+        .into_iter()
+        .filter(|p| !p.as_os_str().is_empty())
+        .collect())
+}
+
+// Search a given list of files, without walking directories or applying ignore rules
+fn search_files(
+    mut finder: Finder,
+    files: &[PathBuf],
+    params: &SearchParams,
+    tx: Sender<FileMatch>,
+) {
+    for path in files {
+        debug!("Searching file {path:?}");
+        let res = std::fs::metadata(path)
+            .with_context(|| format!("Reading metadata of {path:?}"))
+            .and_then(|meta| search_file(&mut finder, path, &meta, params, &tx));
+        match res {
+            Ok(WalkState::Quit) => return,
+            Ok(_) => {}
+            Err(e) if is_vanished(&e) => debug!("Skipping: {e:#}"),
+            Err(e) => warn!("Search error in {path:?}: {e:#}"),
+        }
+    }
+}
+
+// Fail if any of the paths to search doesn't exist, rather than quietly finding nothing in it
+pub fn check_paths(paths: &[PathBuf]) -> Result<()> {
+    for path in paths {
+        std::fs::metadata(path).with_context(|| format!("Cannot search {path:?}"))?;
+    }
+    Ok(())
+}
+
+// The canonical path of a directory searched under a git root, and the path it was given as
+type Base = (PathBuf, PathBuf);
+
+// Show a path found under the git root under the directory it was searched as, see git_root_walk
+fn rebase<'a>(path: &'a Path, bases: &[Base]) -> Cow<'a, Path> {
+    bases
+        .iter()
+        .find_map(|(base, given)| Some(given.join(path.strip_prefix(base).ok()?)))
+        .map_or(Cow::Borrowed(path), Cow::Owned)
+}
+
+// With a git root, directories are walked from the root rather than from themselves, so the same
+// ignore rules apply whichever directories are searched. Returns the roots to walk, and the
+// bases of the directories searched.
+fn git_root_walk(git_root: &Path, paths: Vec<&PathBuf>) -> Result<(Vec<PathBuf>, Vec<Base>)> {
+    let root = git_root
+        .canonicalize()
+        .with_context(|| format!("Cannot use git root {git_root:?}"))?;
+    let mut roots = vec![];
+    let mut bases = vec![];
+    for path in paths {
+        // files given explicitly are searched as always
+        if !path.is_dir() {
+            roots.push(path.clone());
+            continue;
+        }
+        let canonical = path.canonicalize()?;
+        if !canonical.starts_with(&root) {
+            bail!("Cannot search {path:?}, it is not inside the git root {git_root:?}");
+        }
+        bases.push((canonical, path.clone()));
+    }
+    if !bases.is_empty() {
+        roots.insert(0, root);
+    }
+    Ok((roots, bases))
+}
+
+// Fail if a git root is set that the directories to search aren't inside
+pub fn check_git_root(params: &SearchParams) -> Result<()> {
+    match &params.git_root {
+        Some(git_root) => git_root_walk(git_root, roots(&params.paths)).map(drop),
+        None => Ok(()),
+    }
+}
+
+// The paths to walk, without duplicates or paths inside another, which would be searched twice,
+// e.g. from `lasr src src/*.rs`
+fn roots(paths: &[PathBuf]) -> Vec<&PathBuf> {
+    // "./src" and "src" are the same, and "." contains "src"
+    let normal =
+        |p: &Path| -> PathBuf { p.components().filter(|c| *c != Component::CurDir).collect() };
+    let normalized: Vec<_> = paths.iter().map(|p| normal(p)).collect();
+    // but not "/tmp" or "../x", so only compare paths that stay within the same base
+    let comparable = |a: &Path, b: &Path| {
+        a.is_absolute() == b.is_absolute()
+            && ![a, b]
+                .iter()
+                .any(|p| p.components().any(|c| c == Component::ParentDir))
+    };
+    paths
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| {
+            !normalized.iter().enumerate().any(|(j, other)| {
+                let inside = comparable(&normalized[i], other) && normalized[i].starts_with(other);
+                // of two equal paths, keep the first
+                inside && (normalized[i] != *other || j < i)
+            })
+        })
+        .map(|(_, p)| p)
+        .collect()
 }
 
 pub fn search(mut finder: Finder, params: SearchParams, tx: Sender<FileMatch>) -> Result<()> {
     debug!("Starting search with params: {params:?}");
 
-    let mut builder = ignore::WalkBuilder::new(&params.paths[0]);
+    if let Some(text) = &params.stdin {
+        let path = Path::new(STDIN_PATH);
+        let lines = finder.find_text(path, text)?;
+        send_matches(&finder, path, lines, text.len() as u64, &params.stats, &tx);
+        return Ok(());
+    }
+
+    if let Some(files) = &params.files {
+        search_files(finder, files, &params, tx);
+        return Ok(());
+    }
+
+    // A parallel walk finishes files in any order, so collect them all before sending any
+    if params.sorted && params.threads != 1 {
+        let mut all = search_collect(
+            finder,
+            SearchParams {
+                sorted: false,
+                ..params
+            },
+        )?;
+        all.sort_by(|a, b| a.path.cmp(&b.path));
+        for m in all {
+            if tx.send(m).is_err() {
+                debug!("TX closed, ending search");
+                break;
+            }
+        }
+        return Ok(());
+    }
+
+    check_paths(&params.paths)?;
+    let roots = roots(&params.paths);
+    let (roots, bases) = match &params.git_root {
+        Some(git_root) => git_root_walk(git_root, roots)?,
+        None => (roots.into_iter().cloned().collect(), vec![]),
+    };
+    let Some((first, rest)) = roots.split_first() else {
+        return Ok(());
+    };
+
+    // files given explicitly are always searched, even if ignored or of another type
+    let mut builder = ignore::WalkBuilder::new(first);
     builder
         .sort_by_file_name(|a, b| a.cmp(b))
         .threads(params.threads)
-        .types(params.types);
-    for path in params.paths.iter().skip(1) {
+        .max_depth(params.max_depth)
+        .max_filesize(params.max_filesize)
+        .types(params.types.clone());
+    if params.no_ignore {
+        builder.git_ignore(false).ignore(false).hidden(false);
+    } else {
+        // these take precedence over .ignore and .gitignore files in the same directory
+        for name in &params.ignore_files {
+            builder.add_custom_ignore_filename(name);
+        }
+    }
+    if params.hidden {
+        builder.hidden(false);
+    }
+    // given explicitly, so respected even with no_ignore, but with the lowest precedence
+    for path in &params.ignore_paths {
+        if let Some(err) = builder.add_ignore(path) {
+            warn!("Failed to read ignore file {path:?}: {err}");
+        }
+    }
+    for path in rest {
         builder.add(path);
     }
+    if !bases.is_empty() {
+        let bases = bases.clone();
+        let max_depth = params.max_depth;
+        // only descend towards and into the directories searched, as deep as they would have been
+        builder
+            .max_depth(None)
+            .parents(false)
+            // the root's rules apply to nested repositories, which git would treat separately
+            .require_git(false)
+            .filter_entry(move |entry| {
+                let path = entry.path();
+                match bases
+                    .iter()
+                    .find_map(|(base, _)| path.strip_prefix(base).ok())
+                {
+                    Some(rest) => max_depth.is_none_or(|max| rest.components().count() <= max),
+                    None => {
+                        entry.depth() == 0 || bases.iter().any(|(base, _)| base.starts_with(path))
+                    }
+                }
+            });
+    }
 
     // Mostly for testing, this supports sorting entries for predictable results
     if params.threads == 1 {
         for path in builder.build() {
-            match walk(&mut finder, path, &tx) {
-                Ok(WalkState::Quit) => {
-                    return Ok(());
-                }
-                Ok(_) => {}
-                Err(e) => {
-                    warn!("Search error: {e}");
-                }
+            if let WalkState::Quit = walk(&mut finder, path, &params, &bases, &tx) {
+                return Ok(());
             }
         }
         return Ok(());
     }
 
-    builder.build_parallel().run(move || {
+    builder.build_parallel().run(|| {
         let tx = tx.clone();
         let mut finder = finder.clone();
-        Box::new(move |path| -> WalkState {
-            match walk(&mut finder, path, &tx) {
-                Ok(state) => state,
-                Err(e) => {
-                    warn!("Search error: {e}");
-                    WalkState::Continue
-                }
-            }
-        })
+        let (params, bases) = (&params, &bases);
+        Box::new(move |path| walk(&mut finder, path, params, bases, &tx))
     });
 
     Ok(())
 }
 
+// Search to completion on the calling thread, returning every file that matched.
+// Results arrive in the order found, so set params.sorted for a stable order.
+pub fn search_collect(finder: Finder, params: SearchParams) -> Result<Vec<FileMatch>> {
+    let (tx, rx) = unbounded();
+    search(finder, params, tx)?;
+    Ok(rx.into_iter().collect())
+}
+
 #[cfg(test)]
 mod tests {
-    use crossbeam::channel::{RecvError, unbounded};
+    use crossbeam::channel::bounded;
     use pretty_assertions::assert_eq;
 
-    use crate::finder::{LineMatch, RegexParams};
+    use crate::finder::{AstSkipped, LineMatch, RegexParams};
+    use ast_grep_language::SupportLang;
 
     use super::*;
 
@@ -96,27 +443,43 @@ mod tests {
         types.build().unwrap()
     }
 
+    // The paths of files matching pattern, in the order they were found
+    fn found(pattern: &str, params: SearchParams) -> Vec<PathBuf> {
+        let finder = Finder::new(pattern, &RegexParams::default()).unwrap();
+        search_collect(finder, params)
+            .unwrap()
+            .into_iter()
+            .map(|m| m.path)
+            .collect()
+    }
+
+    // Like found, but only the file names
+    fn found_names(pattern: &str, params: SearchParams) -> Vec<String> {
+        found(pattern, params)
+            .iter()
+            .map(|p| p.file_name().unwrap().to_str().unwrap().to_string())
+            .collect()
+    }
+
     #[test]
     #[tracing_test::traced_test]
     fn test_search() {
-        let (tx, rx) = unbounded();
-
         let params = SearchParams {
             paths: vec!["testdata".into()],
             types: types(&[]),
             threads: 1,
+            ..Default::default()
         };
         let finder = Finder::new(
             "line",
             &RegexParams {
                 ignore_case: false,
                 multi_line: false,
+                ..Default::default()
             },
         )
         .unwrap();
-        search(finder, params, tx).unwrap();
-
-        let mut results: Vec<_> = rx.iter().collect();
+        let mut results = search_collect(finder, params).unwrap();
         results.sort_by(|a, b| a.path.cmp(&b.path));
 
         assert_eq!(
@@ -124,157 +487,744 @@ mod tests {
             [
                 FileMatch {
                     path: "testdata/dir1/file2.txt".into(),
+                    lang: None,
                     lines: vec![
                         LineMatch {
                             number: 1,
                             text: "The first line.\n".into(),
+                            offset: 0,
                             ranges: vec![10..14],
+                            captures: vec![],
                         },
                         LineMatch {
                             number: 2,
                             text: "The second line.\n".into(),
+                            offset: 16,
                             ranges: vec![11..15],
+                            captures: vec![],
                         },
                         LineMatch {
                             number: 3,
                             text: "The third line.\n".into(),
+                            offset: 33,
                             ranges: vec![10..14],
+                            captures: vec![],
                         },
                     ],
+                    truncated: false,
                 },
                 FileMatch {
                     path: "testdata/file1.txt".into(),
+                    lang: None,
                     lines: vec![
                         LineMatch {
                             number: 1,
                             text: "This is line one.\n".into(),
+                            offset: 0,
                             ranges: vec![8..12],
+                            captures: vec![],
                         },
                         LineMatch {
                             number: 2,
                             text: "This is line two.\n".into(),
+                            offset: 18,
                             ranges: vec![8..12],
+                            captures: vec![],
                         },
                         LineMatch {
                             number: 3,
                             text: "This is line three.\n".into(),
+                            offset: 36,
                             ranges: vec![8..12],
+                            captures: vec![],
                         },
                     ],
+                    truncated: false,
                 }
             ]
         );
-
-        assert_eq!(rx.recv(), Err(RecvError));
     }
 
     #[test]
     #[tracing_test::traced_test]
     fn test_search_ignore_case() {
-        let (tx, rx) = unbounded();
-
         let params = SearchParams {
             paths: vec!["testdata".into()],
             types: types(&[]),
             threads: 1,
+            ..Default::default()
         };
         let finder = Finder::new(
             "the",
             &RegexParams {
                 ignore_case: true,
                 multi_line: false,
+                ..Default::default()
             },
         )
         .unwrap();
-        search(finder, params, tx).unwrap();
-        let mut results: Vec<_> = rx.iter().collect();
+        let mut results = search_collect(finder, params).unwrap();
         results.sort_by(|a, b| a.path.cmp(&b.path));
 
         assert_eq!(
             results,
             [FileMatch {
                 path: "testdata/dir1/file2.txt".into(),
+                lang: None,
                 lines: vec![
                     LineMatch {
                         number: 1,
                         text: "The first line.\n".into(),
+                        offset: 0,
                         ranges: vec![0..3],
+                        captures: vec![],
                     },
                     LineMatch {
                         number: 2,
                         text: "The second line.\n".into(),
+                        offset: 16,
                         ranges: vec![0..3],
+                        captures: vec![],
                     },
                     LineMatch {
                         number: 3,
                         text: "The third line.\n".into(),
+                        offset: 33,
                         ranges: vec![0..3],
+                        captures: vec![],
                     },
                 ],
+                truncated: false,
             },]
         );
-
-        assert_eq!(rx.recv(), Err(RecvError));
     }
 
     #[test]
     #[tracing_test::traced_test]
     fn test_search_file_types() {
-        let (tx, rx) = unbounded();
-
         let params = SearchParams {
             paths: vec!["testdata".into()],
             types: types(&["md"]),
             threads: 1,
+            ..Default::default()
         };
         let finder = Finder::new(
             "First",
             &RegexParams {
                 ignore_case: true,
                 multi_line: false,
+                ..Default::default()
             },
         )
         .unwrap();
-        search(finder, params, tx).unwrap();
-        let mut results: Vec<_> = rx.iter().collect();
+        let mut results = search_collect(finder, params).unwrap();
         results.sort_by(|a, b| a.path.cmp(&b.path));
 
         assert_eq!(
             results,
             [FileMatch {
                 path: "testdata/example.md".into(),
+                lang: None,
                 lines: vec![LineMatch {
                     number: 1,
                     text: "# First heading\n".into(),
+                    offset: 0,
                     ranges: vec![2..7],
+                    captures: vec![],
                 },],
+                truncated: false,
             },]
         );
+    }
 
-        assert_eq!(rx.recv(), Err(RecvError));
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_context() {
+        let params = SearchParams {
+            paths: vec!["testdata/file1.txt".into()],
+            types: types(&[]),
+            threads: 1,
+            ..Default::default()
+        };
+        let finder = Finder::new(
+            "two",
+            &RegexParams {
+                before_context: 1,
+                after_context: 2,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let results = search_collect(finder, params).unwrap();
+        assert_eq!(
+            results,
+            [FileMatch {
+                path: "testdata/file1.txt".into(),
+                lang: None,
+                lines: vec![
+                    LineMatch {
+                        number: 1,
+                        text: "This is line one.\n".into(),
+                        offset: 0,
+                        ranges: vec![],
+                        captures: vec![],
+                    },
+                    LineMatch {
+                        number: 2,
+                        text: "This is line two.\n".into(),
+                        offset: 18,
+                        ranges: vec![13..16],
+                        captures: vec![],
+                    },
+                    LineMatch {
+                        number: 3,
+                        text: "This is line three.\n".into(),
+                        offset: 36,
+                        ranges: vec![],
+                        captures: vec![],
+                    },
+                    LineMatch {
+                        number: 4,
+                        text: "Line four.\n".into(),
+                        offset: 56,
+                        ranges: vec![],
+                        captures: vec![],
+                    },
+                ],
+                truncated: false,
+            }]
+        );
     }
 
     #[test]
     #[tracing_test::traced_test]
-    fn test_search_ast() {
+    fn test_search_no_ignore() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join(".ignore"), "ignored.txt").unwrap();
+        std::fs::write(tmp.path().join("ignored.txt"), "foo").unwrap();
+        std::fs::write(tmp.path().join(".hidden.txt"), "foo").unwrap();
+        std::fs::write(tmp.path().join("visible.txt"), "foo").unwrap();
+
+        let paths = |no_ignore, hidden| {
+            let params = SearchParams {
+                paths: vec![tmp.path().into()],
+                types: types(&[]),
+                threads: 1,
+                no_ignore,
+                hidden,
+                ..Default::default()
+            };
+            found_names("foo", params)
+        };
+
+        assert_eq!(paths(false, false), ["visible.txt"]);
+        assert_eq!(
+            paths(true, false),
+            [".hidden.txt", "ignored.txt", "visible.txt"]
+        );
+        // hidden files are searched, but ignore files still apply
+        assert_eq!(paths(false, true), [".hidden.txt", "visible.txt"]);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_cancel() {
+        for threads in [1, 4] {
+            let params = SearchParams {
+                paths: vec!["testdata".into()],
+                types: types(&[]),
+                threads,
+                ..Default::default()
+            };
+            let finder = Finder::new("line", &RegexParams::default()).unwrap();
+            let (tx, rx) = bounded(0);
+            let (done_tx, done_rx) = bounded(1);
+            std::thread::spawn(move || {
+                done_tx.send(search(finder, params, tx)).unwrap();
+            });
+
+            // take one result, then dropping the receiver should stop the search
+            rx.recv().unwrap();
+            drop(rx);
+            let res = done_rx.recv_timeout(std::time::Duration::from_secs(5));
+            assert!(res.unwrap().is_ok(), "threads={threads}");
+        }
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_max_depth() {
+        let paths = |paths: &[&str], max_depth| {
+            let params = SearchParams {
+                paths: paths.iter().map(Into::into).collect(),
+                types: types(&[]),
+                threads: 1,
+                max_depth,
+                ..Default::default()
+            };
+            found("line|aaa", params)
+                .iter()
+                .map(|p| p.to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(paths(&["testdata"], Some(1)), ["testdata/file1.txt"]);
+        assert_eq!(
+            paths(&["testdata"], Some(2)),
+            [
+                "testdata/aaa/aaa1.txt",
+                "testdata/aaa/aaa2.txt",
+                "testdata/aaa/aaa3.txt",
+                "testdata/aaa/aaa4.txt",
+                "testdata/dir1/file2.txt",
+                "testdata/file1.txt"
+            ]
+        );
+        // only the given files are searched
+        assert!(paths(&["testdata"], Some(0)).is_empty());
+        assert_eq!(
+            paths(&["testdata/file1.txt", "testdata/dir1"], Some(0)),
+            ["testdata/file1.txt"]
+        );
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_git_root() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        let sub = root.join("sub");
+        std::fs::create_dir_all(sub.join("nested/.git")).unwrap();
+        for path in ["sub/a.txt", "sub/b.txt", "sub/nested/a.txt", "c.txt"] {
+            std::fs::write(root.join(path), "foo\n").unwrap();
+        }
+        // there's no .git at the root, so these only apply with it as the git root
+        std::fs::write(root.join(".gitignore"), "a.txt\n").unwrap();
+        // above the git root, so never read
+        std::fs::write(tmp.path().join(".gitignore"), "b.txt\n").unwrap();
+
+        let search = |paths: &[&Path], git_root: Option<&Path>, max_depth| {
+            let params = SearchParams {
+                paths: paths.iter().map(PathBuf::from).collect(),
+                types: types(&[]),
+                threads: 1,
+                git_root: git_root.map(Into::into),
+                max_depth,
+                ..Default::default()
+            };
+            let finder = Finder::new("foo", &RegexParams::default()).unwrap();
+            let found = search_collect(finder, params)?;
+            anyhow::Ok(found.into_iter().map(|m| m.path).collect::<Vec<_>>())
+        };
+
+        assert_eq!(
+            search(&[&sub], None, None).unwrap(),
+            [
+                sub.join("a.txt"),
+                sub.join("b.txt"),
+                sub.join("nested/a.txt")
+            ]
+        );
+        // results are shown under the path searched, even though the walk starts at the root
+        assert_eq!(
+            search(&[&sub], Some(&root), None).unwrap(),
+            [sub.join("b.txt")]
+        );
+        assert!(
+            search(&[&sub.join("nested")], Some(&root), None)
+                .unwrap()
+                .is_empty()
+        );
+        // depth counts from the path searched, and files given are always searched
+        assert_eq!(
+            search(&[&root], Some(&root), Some(1)).unwrap(),
+            [root.join("c.txt")]
+        );
+        assert_eq!(
+            search(
+                &[&sub.join("a.txt"), &sub.join("nested")],
+                Some(&root),
+                None
+            )
+            .unwrap(),
+            [sub.join("a.txt")]
+        );
+        let err = search(&[tmp.path()], Some(&root), None).unwrap_err();
+        assert!(err.to_string().contains("not inside the git root"), "{err}");
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_ignore_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("dir");
+        std::fs::create_dir(&dir).unwrap();
+        for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+            std::fs::write(dir.join(name), "foo\n").unwrap();
+        }
+        std::fs::write(dir.join(".ignore"), "a.txt\nb.txt\n").unwrap();
+        // custom ignore files take precedence over .ignore
+        std::fs::write(dir.join(".lasrignore"), "b.txt\n!a.txt\n").unwrap();
+        let global = tmp.path().join("global");
+        std::fs::write(&global, "c.txt\n").unwrap();
+
+        let paths = |ignore_files: &[&str], no_ignore| {
+            let params = SearchParams {
+                paths: vec![dir.clone()],
+                types: types(&[]),
+                threads: 1,
+                no_ignore,
+                ignore_files: ignore_files.iter().map(|s| s.to_string()).collect(),
+                ignore_paths: vec![global.clone()],
+                ..Default::default()
+            };
+            found_names("foo", params)
+        };
+
+        assert_eq!(paths(&[], false), ["d.txt"]);
+        assert_eq!(paths(&[".lasrignore"], false), ["a.txt", "d.txt"]);
+        // only the explicit ignore file applies with no_ignore
+        assert_eq!(paths(&[".lasrignore"], true), ["a.txt", "b.txt", "d.txt"]);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_max_filesize() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("small.txt"), "foo\n").unwrap();
+        std::fs::write(tmp.path().join("large.txt"), "foo\n".repeat(100)).unwrap();
+
+        let paths = |paths: Vec<std::path::PathBuf>| {
+            let params = SearchParams {
+                paths,
+                types: types(&[]),
+                threads: 1,
+                max_filesize: Some(100),
+                ..Default::default()
+            };
+            found_names("foo", params)
+        };
+
+        assert_eq!(paths(vec![tmp.path().into()]), ["small.txt"]);
+        // explicitly listed files are also skipped
+        assert_eq!(
+            paths(vec![
+                tmp.path().join("large.txt"),
+                tmp.path().join("small.txt")
+            ]),
+            ["small.txt"]
+        );
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_vanished_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            std::fs::write(tmp.path().join(name), "foo\n").unwrap();
+        }
+        let entries: Vec<_> = ignore::WalkBuilder::new(tmp.path())
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build()
+            .collect();
+        // removed after the walk found it, but before it was searched
+        std::fs::remove_file(tmp.path().join("b.txt")).unwrap();
+
+        let (tx, rx) = unbounded();
+        let mut finder = Finder::new("foo", &RegexParams::default()).unwrap();
+        let params = SearchParams::default();
+        for entry in entries {
+            assert!(matches!(
+                walk(&mut finder, entry, &params, &[], &tx),
+                WalkState::Continue
+            ));
+        }
+        drop(tx);
+
+        let names: Vec<_> = rx
+            .iter()
+            .map(|m| m.path.file_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(names, ["a.txt", "c.txt"]);
+        assert!(logs_contain("Skipping: Reading metadata"));
+        assert!(!logs_contain("Search error"));
+
+        // likewise for files listed by --files-from
+        let params = SearchParams {
+            files: Some(
+                ["a.txt", "b.txt", "c.txt"]
+                    .map(|n| tmp.path().join(n))
+                    .into(),
+            ),
+            ..Default::default()
+        };
+        assert_eq!(found_names("foo", params), ["a.txt", "c.txt"]);
+        assert!(!logs_contain("Search error"));
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_files() {
+        let files = read_paths(
+            "testdata/file1.txt\ntestdata/dir1\n\ntestdata/missing.txt\ntestdata/aaa/aaa1.txt\n"
+                .as_bytes(),
+            false,
+        )
+        .unwrap();
+        let params = SearchParams {
+            files: Some(files),
+            types: types(&["md"]),
+            threads: 4,
+            ..Default::default()
+        };
+        // in the given order, ignoring directories and file types
+        assert_eq!(
+            found("line|aaa", params),
+            [
+                PathBuf::from("testdata/file1.txt"),
+                PathBuf::from("testdata/aaa/aaa1.txt")
+            ]
+        );
+        assert!(logs_contain("testdata/missing.txt"));
+    }
+
+    #[test]
+    fn test_search_paths() {
+        let search_paths = |paths: &[&str], threads| {
+            let params = SearchParams {
+                paths: paths.iter().map(PathBuf::from).collect(),
+                types: types(&["md"]),
+                threads,
+                ..Default::default()
+            };
+            let mut paths = found("line|aaa|heading", params);
+            paths.sort();
+            paths
+        };
+
+        for threads in [1, 4] {
+            // exactly the files given, even of another type, each searched once
+            assert_eq!(
+                search_paths(
+                    &[
+                        "testdata/file1.txt",
+                        "testdata/aaa/aaa2.txt",
+                        "./testdata/file1.txt",
+                        "testdata/file1.txt",
+                    ],
+                    threads
+                ),
+                [
+                    PathBuf::from("testdata/aaa/aaa2.txt"),
+                    PathBuf::from("testdata/file1.txt"),
+                ]
+            );
+            // a file inside a directory that is also given
+            assert_eq!(
+                search_paths(&["testdata/dir1/file2.txt", "testdata"], threads),
+                [PathBuf::from("testdata/example.md")]
+            );
+        }
+    }
+
+    #[test]
+    fn test_search_collect() {
+        let params = SearchParams {
+            paths: vec!["testdata".into()],
+            types: types(&["txt"]),
+            threads: 4,
+            sorted: true,
+            ..Default::default()
+        };
+        let finder = Finder::new("line", &RegexParams::default()).unwrap();
+        let found: Vec<_> = search_collect(finder, params)
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.path, m.lines.len()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (PathBuf::from("testdata/dir1/file2.txt"), 3),
+                (PathBuf::from("testdata/file1.txt"), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_search_stdin() {
+        let params = SearchParams {
+            paths: vec!["testdata".into()],
+            stdin: Some(std::sync::Arc::new("foo\nbar\nfoo bar\n".into())),
+            ..Default::default()
+        };
+        let stats = params.stats.clone();
+        let finder = Finder::new("bar", &RegexParams::default()).unwrap();
+        // only stdin is searched, not the paths
+        let found: Vec<_> = search_collect(finder, params)
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.path, m.lines.iter().map(|l| l.number).collect::<Vec<_>>()))
+            .collect();
+        assert_eq!(found, [(PathBuf::from(STDIN_PATH), vec![2, 3])]);
+        let summary = stats.summary();
+        assert_eq!(summary.files_searched, 1);
+        assert_eq!(summary.bytes, 16);
+    }
+
+    #[test]
+    fn test_search_missing_path() {
         let (tx, rx) = unbounded();
+        let params = SearchParams {
+            paths: vec!["testdata/file1.txt".into(), "testdata/missing.txt".into()],
+            threads: 1,
+            ..Default::default()
+        };
+        let finder = Finder::new("line", &RegexParams::default()).unwrap();
+        let err = search(finder, params, tx).unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "Cannot search \"testdata/missing.txt\": No such file or directory (os error 2)"
+        );
+        assert_eq!(rx.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_summary() {
+        let summary = Summary {
+            files_searched: 2,
+            files_matched: 1,
+            matches: 1,
+            bytes: 32,
+            elapsed: Duration::from_millis(1500),
+        };
+        assert_eq!(
+            summary.to_string(),
+            "1 match in 1 file, 2 files searched, 32 bytes searched, 1.500s"
+        );
+    }
 
+    #[test]
+    fn test_roots() {
+        let paths: Vec<_> = [
+            "src/main.rs",
+            "./src",
+            "src/",
+            "lib",
+            "src/lib.rs",
+            "./lib/a",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(roots(&paths), [&paths[1], &paths[3]]);
+
+        let paths = [PathBuf::from("src"), PathBuf::from(".")];
+        assert_eq!(roots(&paths), [&paths[1]]);
+
+        let paths = [PathBuf::from("."), PathBuf::from("/abs/dir")];
+        assert_eq!(roots(&paths), [&paths[0], &paths[1]]);
+
+        let paths = [PathBuf::from("."), PathBuf::from("../x")];
+        assert_eq!(roots(&paths), [&paths[0], &paths[1]]);
+
+        let paths = [PathBuf::from("/abs"), PathBuf::from("/abs/dir")];
+        assert_eq!(roots(&paths), [&paths[0]]);
+    }
+
+    #[test]
+    fn test_search_sorted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut expected = vec![];
+        for dir in ["a", "b", "c"] {
+            for file in ["x.txt", "y.txt", "z.txt"] {
+                let path = tmp.path().join(dir).join(file);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(&path, "foo\n").unwrap();
+                expected.push(path);
+            }
+        }
+        let params = SearchParams {
+            paths: vec![tmp.path().into()],
+            types: types(&["all"]),
+            threads: 4,
+            sorted: true,
+            ..Default::default()
+        };
+        let finder = Finder::new("foo", &RegexParams::default()).unwrap();
+        let (tx, rx) = bounded(0);
+        std::thread::spawn(move || search(finder, params, tx).unwrap());
+        let found: Vec<_> = rx.iter().map(|f| f.path).collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_search_stats() {
+        let params = SearchParams {
+            paths: vec!["testdata".into()],
+            types: types(&["all"]),
+            threads: 1,
+            ..Default::default()
+        };
+        let finder = Finder::new(
+            "line",
+            &RegexParams {
+                ignore_case: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let stats = params.stats.clone();
+        let found = search_collect(finder, params).unwrap();
+        let summary = stats.summary();
+        let bytes = found
+            .iter()
+            .map(|f| std::fs::metadata(&f.path).unwrap().len())
+            .sum::<u64>();
+        assert_eq!(summary.files_matched, found.len());
+        assert_eq!(
+            summary.matches,
+            found
+                .iter()
+                .flat_map(|f| &f.lines)
+                .map(|l| l.ranges.len())
+                .sum::<usize>()
+        );
+        assert!(summary.files_searched > summary.files_matched);
+        assert!(summary.bytes > bytes);
+    }
+
+    #[test]
+    fn test_read_paths() {
+        let paths = read_paths("a.txt\r\nb c.txt\n\nd\n".as_bytes(), false).unwrap();
+        assert_eq!(
+            paths,
+            [Path::new("a.txt"), Path::new("b c.txt"), Path::new("d")]
+        );
+
+        let paths = read_paths("a\nb.txt\0c.txt\0".as_bytes(), true).unwrap();
+        assert_eq!(paths, [Path::new("a\nb.txt"), Path::new("c.txt")]);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_ast() {
         let params = SearchParams {
             paths: vec!["testdata".into()],
             types: types(&[]),
             threads: 1,
+            ..Default::default()
         };
         let finder = Finder::new(
             "$FN($$$ARGS)",
             &RegexParams {
                 ignore_case: false,
                 multi_line: false,
+                ..Default::default()
             },
         )
         .unwrap();
-        search(finder, params, tx).unwrap();
-
-        let mut results: Vec<_> = rx.iter().collect();
+        let mut results = search_collect(finder, params).unwrap();
         results.sort_by(|a, b| a.path.cmp(&b.path));
 
         assert_eq!(
@@ -282,76 +1232,95 @@ mod tests {
             [
                 FileMatch {
                     path: "testdata/main.py".into(),
+                    lang: Some(SupportLang::Python),
                     lines: vec![
                         LineMatch {
                             number: 1,
                             text: "print(x + y)".into(),
+                            offset: 21,
                             ranges: vec![0..12],
+                            captures: vec![0..5, 6..11],
                         },
                         LineMatch {
                             number: 4,
                             text: "thing(3, 5)".into(),
+                            offset: 36,
                             ranges: vec![0..11],
+                            captures: vec![0..5, 6..10],
                         },
                     ],
+                    truncated: false,
                 },
                 FileMatch {
                     path: "testdata/main.rs".into(),
+                    lang: Some(SupportLang::Rust),
                     lines: vec![LineMatch {
                         number: 5,
                         text: "thing(3, 5)".into(),
+                        offset: 71,
                         ranges: vec![0..11],
+                        captures: vec![0..5, 6..10],
                     },],
+                    truncated: false,
                 },
             ]
         );
-
-        assert_eq!(rx.recv(), Err(RecvError));
     }
 
     #[test]
     #[tracing_test::traced_test]
     fn test_search_ast_invalid_pattern() {
         // This is a valid pattern for rust but not python
-        let (tx, rx) = unbounded();
-
         let params = SearchParams {
             paths: vec!["testdata".into()],
             types: types(&[]),
             threads: 1,
+            ..Default::default()
         };
         let finder = Finder::new(
             "fn $FN",
             &RegexParams {
                 ignore_case: false,
                 multi_line: false,
+                ..Default::default()
             },
         )
         .unwrap();
-        search(finder, params, tx).unwrap();
-
-        let mut results: Vec<_> = rx.iter().collect();
+        // clones share the count of skipped files
+        let skipped = finder.clone();
+        let mut results = search_collect(finder, params).unwrap();
         results.sort_by(|a, b| a.path.cmp(&b.path));
 
+        assert_eq!(
+            skipped.ast_skipped(),
+            AstSkipped {
+                no_lang: 7,
+                invalid_pattern: 1,
+            }
+        );
         assert_eq!(
             results,
             [FileMatch {
                 path: "testdata/main.rs".into(),
+                lang: Some(SupportLang::Rust),
                 lines: vec![
                     LineMatch {
                         number: 0,
                         text: "fn thing(x: u64, y: u64) {\n    println!(\"{x} {y}\");\n}".into(),
+                        offset: 0,
                         ranges: vec![0..53],
+                        captures: vec![3..8],
                     },
                     LineMatch {
                         number: 4,
                         text: "fn main() {\n    thing(3, 5);\n}".into(),
+                        offset: 55,
                         ranges: vec![0..30],
+                        captures: vec![3..7],
                     },
                 ],
+                truncated: false,
             },]
         );
-
-        assert_eq!(rx.recv(), Err(RecvError));
     }
 }
@@ -1,64 +1,146 @@
 use std::{
+    cell::{OnceCell, RefCell},
+    cmp::Ordering,
+    collections::HashSet,
+    io::{BufRead, BufReader},
     ops::Range,
     path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
 };
 
 use super::input::LineInput;
 use crate::{
-    config::{Action, Config, Theme},
-    finder::{FileMatch, Finder, LineMatch, RegexParams, SearchParams},
+    config::{Action, Config, FileType, KeySeq, Sort, Theme},
+    finder::{
+        self, FileMatch, Finder, LineMatch, RegexParams, ReplaceReport, STDIN_PATH, SearchMode,
+        SearchParams, count,
+    },
+    highlight::Highlighter,
     search::{self},
 };
 use anyhow::{Context, Result};
-use crossbeam::channel::{Receiver, RecvError, bounded, never, select_biased};
-use crossterm::event::{Event, KeyEvent, KeyEventKind};
+use ast_grep_language::SupportLang;
+use crossbeam::channel::{Receiver, RecvError, after, bounded, never, select_biased, tick};
+use crossterm::event::{
+    Event, KeyCode, KeyEvent, KeyEventKind, MouseButton, MouseEvent, MouseEventKind,
+};
+use notify::Watcher as _;
 use ratatui::{
     DefaultTerminal, Frame,
-    layout::{Constraint, Direction, Layout, Position},
-    style::Style,
+    layout::{Constraint, Direction, Flex, Layout, Position, Rect},
+    style::{Modifier, Style},
     text::{Line, Span, Text},
-    widgets::{Block, Paragraph, Row, Table, TableState},
+    widgets::{Block, Borders, Clear, Paragraph, Row, Table, TableState},
 };
+use serde::{Deserialize, Serialize};
 use tracing::{debug, error, info, trace, warn};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 // How many off-screen results to pre-populate
 const SEARCH_BUFFER: usize = 3;
 
-#[derive(Debug)]
+// Animation shown while searching, advanced every SPINNER_INTERVAL
+const SPINNER: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const SPINNER_INTERVAL: Duration = Duration::from_millis(100);
+
+// Max width of the line number column in results
+const LINE_NUMBER_WIDTH: u16 = 6;
+// Max width of the line number column when it also shows the column, like 12:5
+const LINE_COLUMN_WIDTH: u16 = 10;
+
+// How many columns to scroll results horizontally
+const HSCROLL_STEP: usize = 8;
+
+// Drawn for a zero-width match, like ^ or \b, that has no replacement to show
+const ZERO_WIDTH_MARKER: &str = "▏";
+
+// How long to wait for the rest of a multi-key binding
+const KEY_SEQ_TIMEOUT: Duration = Duration::from_secs(1);
+// How long a message stays in the status bar, unless a key is pressed first
+const STATUS_TIMEOUT: Duration = Duration::from_secs(3);
+const INVERT_NO_REPLACE: &str = "Replacement is disabled while inverting matches";
+
+// History files, stored in the cache dir
+const SEARCH_HISTORY: &str = "search_history.txt";
+const REPLACE_HISTORY: &str = "replace_history.txt";
+
+// History is stored one entry per line, so newlines within an entry are stored as this
+const HISTORY_NEWLINE: char = '\u{2424}';
+
+fn read_history(path: &Path) -> Vec<String> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => s
+            .lines()
+            .map(|l| l.replace(HISTORY_NEWLINE, "\n"))
+            .collect(),
+        Err(err) => {
+            debug!("Not loading history from {path:?}: {err}");
+            vec![]
+        }
+    }
+}
+
+fn write_history<'a>(path: &Path, history: impl Iterator<Item = &'a str>) -> Result<()> {
+    let s: String = history
+        .map(|h| format!("{}\n", h.replace('\n', &HISTORY_NEWLINE.to_string())))
+        .collect();
+    std::fs::write(path, s).with_context(|| format!("Writing history to {path:?}"))
+}
+
+// The last search, saved in the cache dir on exit and restored by --resume
+const SESSION: &str = "session.toml";
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct Session {
+    pattern: String,
+    replacement: String,
+    ignore_case: bool,
+    multi_line: bool,
+    whole_word: bool,
+    fixed_string: bool,
+    paths: Vec<PathBuf>, // absolute, so the session can be resumed from another directory
+}
+
+#[derive(Debug, Clone)]
 struct Substitution {
     range: Range<usize>,
     replacement: String, // only set if we have a replacement string
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct TextSubstitution {
     start_line: u64,
-    line_count: u16,
+    offset: usize, // byte offset of text within the file
     text: String,
     matches: Vec<Substitution>,
+    captures: Vec<Range<usize>>, // meta-variables of an ast-grep match, within text
+    // syntax styles of text, computed when first drawn. Replacements are styled by the theme, so
+    // these don't change when the replacement does.
+    syntax: OnceCell<Vec<(Range<usize>, Style)>>,
 }
 
 impl TextSubstitution {
     fn new(path: &Path, line: LineMatch, finder: &Finder, replacement: &str) -> Result<Self> {
-        Ok(Self {
+        let mut sub = Self {
             start_line: line.number,
-            line_count: line.text.lines().count() as u16,
+            offset: line.offset,
             matches: line
                 .ranges
                 .into_iter()
-                .map(|range| {
-                    let replacement = if replacement.is_empty() {
-                        "".to_string()
-                    } else {
-                        finder
-                            .replace(path, &line.text[range.clone()], replacement)?
-                            .to_string()
-                    };
-                    anyhow::Ok(Substitution { range, replacement })
+                .map(|range| Substitution {
+                    range,
+                    replacement: String::new(),
                 })
-                .collect::<Result<Vec<_>>>()?,
+                .collect(),
             text: line.text,
-        })
+            captures: line.captures,
+            syntax: OnceCell::new(),
+        };
+        sub.update_replacement(path, finder, replacement)?;
+        Ok(sub)
     }
 
     fn update_replacement(
@@ -71,29 +153,66 @@ impl TextSubstitution {
             m.replacement = if replacement.is_empty() {
                 "".to_string()
             } else {
-                finder
-                    .replace(path, &self.text[m.range.clone()], replacement)?
-                    .to_string()
+                let replaced = finder.replace_at(path, &self.text, m.range.clone(), replacement)?;
+                finder::match_trailing_newline(&self.text[m.range.clone()], replaced)
             }
         }
         Ok(())
     }
+
+    // The range of a match within the file, rather than within the text
+    fn file_range(&self, sub: &Substitution) -> Range<usize> {
+        self.offset + sub.range.start..self.offset + sub.range.end
+    }
+
+    // The display columns spanned by a match, relative to the start of its line
+    fn columns(&self, sub: &Substitution, tab_width: usize) -> Range<usize> {
+        let line_start = self.text[..sub.range.start]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let start = advance_columns(0, &self.text[line_start..sub.range.start], tab_width);
+        start..advance_columns(start, &self.text[sub.range.clone()], tab_width)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct FileSubstitution {
     path: PathBuf,
+    lang: Option<SupportLang>, // the language parsed for an ast-grep pattern
+    first_line: String,        // used to detect the syntax of files without an extension
+    modified: Option<SystemTime>, // used to sort by modified time
     subs: Vec<TextSubstitution>,
+    truncated: bool, // stopped at max_matches_per_file
+}
+
+fn read_first_line(path: &Path) -> String {
+    let mut line = String::new();
+    let res = std::fs::File::open(path).and_then(|f| BufReader::new(f).read_line(&mut line));
+    if let Err(err) = res {
+        debug!("Failed to read first line of {path:?}: {err}");
+    }
+    line
 }
 
 impl FileSubstitution {
     fn new(file: FileMatch, finder: &Finder, replacement: &str) -> Result<Self> {
+        let first_line = match file.lines.first() {
+            Some(line) if line.number == 1 => line.text.lines().next().unwrap_or("").to_string(),
+            _ => read_first_line(&file.path),
+        };
+        let modified = std::fs::metadata(&file.path)
+            .and_then(|m| m.modified())
+            .ok();
         Ok(Self {
+            first_line,
+            modified,
+            lang: file.lang,
             subs: file
                 .lines
                 .into_iter()
                 .map(|line| TextSubstitution::new(&file.path, line, finder, replacement))
                 .collect::<Result<_>>()?,
+            truncated: file.truncated,
             path: file.path,
         })
     }
@@ -106,26 +225,326 @@ impl FileSubstitution {
         }
     }
 
-    fn line_count(&self) -> u16 {
-        self.subs.iter().map(|s| s.line_count).sum()
+    fn match_count(&self) -> usize {
+        self.subs.iter().map(|s| s.matches.len()).sum()
+    }
+
+    // Order files for the results, falling back to the path to break ties
+    fn compare(&self, other: &Self, sort: Sort) -> Ordering {
+        let order = match sort {
+            Sort::Path => Ordering::Equal,
+            Sort::Modified => other.modified.cmp(&self.modified),
+            Sort::Matches => other.match_count().cmp(&self.match_count()),
+        };
+        order.then_with(|| self.path.cmp(&other.path))
+    }
+
+    // Just the lines containing filter, or the whole file if its path does
+    fn filtered(&self, filter: &str) -> Option<Self> {
+        if self.path.to_string_lossy().contains(filter) {
+            return Some(self.clone());
+        }
+        let subs: Vec<_> = self
+            .subs
+            .iter()
+            .filter(|s| !s.matches.is_empty() && s.text.contains(filter))
+            .cloned()
+            .collect();
+        (!subs.is_empty()).then(|| Self {
+            path: self.path.clone(),
+            lang: self.lang,
+            first_line: self.first_line.clone(),
+            modified: self.modified,
+            subs,
+            truncated: self.truncated,
+        })
+    }
+
+    // How many matches will be replaced, skipping rejected matches
+    fn accepted_count(&self, rejected: &HashSet<(PathBuf, Range<usize>)>) -> usize {
+        self.subs
+            .iter()
+            .flat_map(|s| s.matches.iter().map(|m| s.file_range(m)))
+            .filter(|range| !rejected.contains(&(self.path.clone(), range.clone())))
+            .count()
+    }
+
+    // Apply every match that was not rejected to the file contents, returning the new contents
+    // and the number of matches applied
+    fn replace(
+        &self,
+        text: &str,
+        finder: &Finder,
+        replacement: &str,
+        rejected: &HashSet<(PathBuf, Range<usize>)>,
+    ) -> Result<(String, usize)> {
+        let changed = || format!("{:?} changed since it was searched", self.path);
+        let mut out = String::with_capacity(text.len());
+        let mut last_end = 0;
+        let mut count = 0;
+        for line in &self.subs {
+            for m in &line.matches {
+                let range = line.file_range(m);
+                if range.start < last_end {
+                    trace!("Skipping overlapping match at {range:?}");
+                    continue;
+                }
+                if rejected.contains(&(self.path.clone(), range.clone())) {
+                    trace!("Skipping rejected match at {range:?}");
+                    continue;
+                }
+                out += text.get(last_end..range.start).with_context(changed)?;
+                text.get(range.clone()).with_context(changed)?;
+                out += &finder.replace_at(&self.path, text, range.clone(), replacement)?;
+                last_end = range.end;
+                count += 1;
+            }
+        }
+        out += text.get(last_end..).with_context(changed)?;
+        Ok((finder::match_trailing_newline(text, out), count))
     }
 }
 
 fn push_lines<'a>(s: &'a str, text: &mut Text<'a>, style: Style) {
-    let mut lines = s.lines();
-    if let Some(first_line) = lines.next() {
-        text.push_span(Span::styled(first_line, style));
+    // text starting with a newline still ends the first line
+    if text.lines.is_empty() {
+        text.push_line(Line::default());
+    }
+    let mut lines = s.split('\n').peekable();
+    while let Some(line) = lines.next() {
+        let ends_line = lines.peek().is_some();
+        let line = match line.strip_suffix('\r') {
+            Some(line) if ends_line => line,
+            _ => line,
+        };
+        // an empty span would leave the style of a match that only touches this line
+        if !line.is_empty() {
+            text.push_span(Span::styled(line, style));
+        }
+        if ends_line {
+            text.push_line(Line::default());
+        }
     }
+}
 
-    for line in lines {
-        text.push_line(Line::default());
-        text.push_span(Span::styled(line, style));
+// The display column after s, starting from column col, with tabs stopping every tab_width columns
+fn advance_columns(mut col: usize, s: &str, tab_width: usize) -> usize {
+    if tab_width == 0 {
+        return col + s.width();
+    }
+    for (i, part) in s.split('\t').enumerate() {
+        if i > 0 {
+            col += tab_width - col % tab_width;
+        }
+        col += part.width();
     }
+    col
+}
 
-    // Handle case where string ends with newline
-    if s.ends_with('\n') {
-        text.push_line(Line::default());
+// Replace tabs with spaces up to the next tab stop, keeping the styles of the spans they're in.
+// The terminal's own tab stops don't know where the results start, and ignore the styles.
+fn expand_tabs(line: Line<'_>, tab_width: usize) -> Line<'_> {
+    if tab_width == 0 || !line.spans.iter().any(|s| s.content.contains('\t')) {
+        return line;
+    }
+    let mut col = 0;
+    let mut spans = vec![];
+    for span in line.spans {
+        if !span.content.contains('\t') {
+            col += span.content.width();
+            spans.push(span);
+            continue;
+        }
+        let mut expanded = String::new();
+        for (i, part) in span.content.split('\t').enumerate() {
+            if i > 0 {
+                let n = tab_width - col % tab_width;
+                expanded.extend(std::iter::repeat_n(' ', n));
+                col += n;
+            }
+            expanded.push_str(part);
+            col += part.width();
+        }
+        spans.push(Span::styled(expanded, span.style));
+    }
+    Line::from(spans).style(line.style)
+}
+
+// Fit the text of a result to the width of the results, wrapping it or scrolling it horizontally
+fn fit_text(text: &mut Text<'_>, tab_width: usize, wrap_width: Option<usize>, hscroll: usize) {
+    // drop the empty line after a trailing newline
+    if text.lines.len() > 1 && text.lines.last().is_some_and(|l| l.width() == 0) {
+        text.lines.pop();
+    }
+    text.lines = std::mem::take(&mut text.lines)
+        .into_iter()
+        .map(|line| expand_tabs(line, tab_width))
+        .collect();
+    if let Some(width) = wrap_width {
+        text.lines = std::mem::take(&mut text.lines)
+            .into_iter()
+            .flat_map(|line| wrap_line(line, width))
+            .collect();
+    } else if hscroll > 0 {
+        text.lines = std::mem::take(&mut text.lines)
+            .into_iter()
+            .map(|line| skip_columns(line, hscroll))
+            .collect();
+    }
+}
+
+// Drop the first n display columns of a line, keeping the styles of what remains
+fn skip_columns(line: Line<'_>, mut n: usize) -> Line<'_> {
+    let mut spans = vec![];
+    for span in line.spans {
+        if n == 0 {
+            spans.push(span);
+            continue;
+        }
+        let width = span.content.width();
+        if width <= n {
+            n -= width;
+            continue;
+        }
+        let mut skipped = 0;
+        let idx = span
+            .content
+            .char_indices()
+            .find(|(_, c)| {
+                let done = skipped >= n;
+                skipped += c.width().unwrap_or(0);
+                done
+            })
+            .map_or(span.content.len(), |(i, _)| i);
+        n = 0;
+        spans.push(Span::styled(span.content[idx..].to_string(), span.style));
+    }
+    Line::from(spans).style(line.style)
+}
+
+// Split a line into lines no wider than width, keeping styles
+fn wrap_line(line: Line<'_>, width: usize) -> Vec<Line<'_>> {
+    let mut lines = vec![Line::default().style(line.style)];
+    let mut col = 0;
+    for span in line.spans {
+        let mut start = 0;
+        for (i, c) in span.content.char_indices() {
+            let w = c.width().unwrap_or(0);
+            if col > 0 && col + w > width {
+                if start < i {
+                    let s = span.content[start..i].to_string();
+                    lines
+                        .last_mut()
+                        .unwrap()
+                        .push_span(Span::styled(s, span.style));
+                }
+                lines.push(Line::default().style(line.style));
+                start = i;
+                col = 0;
+            }
+            col += w;
+        }
+        if start < span.content.len() {
+            let s = span.content[start..].to_string();
+            lines
+                .last_mut()
+                .unwrap()
+                .push_span(Span::styled(s, span.style));
+        }
     }
+    lines
+}
+
+#[test]
+fn test_expand_tabs() {
+    let style = Style::default().fg(ratatui::style::Color::Red);
+    let line = Line::from(vec![
+        Span::raw("\tab"),
+        Span::styled("\tc", style),
+        Span::raw("d\t"),
+    ]);
+    assert_eq!(
+        expand_tabs(line.clone(), 4),
+        Line::from(vec![
+            Span::raw("    ab"),
+            Span::styled("  c", style),
+            Span::raw("d  "),
+        ])
+    );
+    assert_eq!(expand_tabs(line.clone(), 0), line);
+    assert_eq!(advance_columns(0, "\tab\tcd\t", 4), 12);
+    assert_eq!(advance_columns(2, "\t", 8), 8);
+    assert_eq!(advance_columns(0, "\tab", 0), "\tab".width());
+}
+
+#[test]
+fn test_wrap_line() {
+    let style = Style::default().fg(ratatui::style::Color::Red);
+    let line = Line::from(vec![
+        Span::raw("foo "),
+        Span::styled("bar", style),
+        Span::raw(" baz"),
+    ]);
+    assert_eq!(wrap_line(line.clone(), 20), vec![line.clone()]);
+    assert_eq!(
+        wrap_line(line.clone(), 5),
+        vec![
+            Line::from(vec![Span::raw("foo "), Span::styled("b", style)]),
+            Line::from(vec![Span::styled("ar", style), Span::raw(" ba")]),
+            Line::from(vec![Span::raw("z")]),
+        ]
+    );
+    assert_eq!(
+        wrap_line(line, 4),
+        vec![
+            Line::from(vec![Span::raw("foo ")]),
+            Line::from(vec![Span::styled("bar", style), Span::raw(" ")]),
+            Line::from(vec![Span::raw("baz")]),
+        ]
+    );
+    assert_eq!(wrap_line(Line::default(), 4), vec![Line::default()]);
+    // wide chars are not split
+    assert_eq!(
+        wrap_line(Line::raw("你好x"), 3),
+        vec![Line::raw("你"), Line::raw("好x")]
+    );
+}
+
+#[test]
+fn test_skip_columns() {
+    let style = Style::default().fg(ratatui::style::Color::Red);
+    let line = Line::from(vec![
+        Span::raw("foo "),
+        Span::styled("bar", style),
+        Span::raw(" baz"),
+    ]);
+    assert_eq!(skip_columns(line.clone(), 0), line);
+    assert_eq!(
+        skip_columns(line.clone(), 2),
+        Line::from(vec![
+            Span::raw("o "),
+            Span::styled("bar", style),
+            Span::raw(" baz")
+        ])
+    );
+    assert_eq!(
+        skip_columns(line.clone(), 4),
+        Line::from(vec![Span::styled("bar", style), Span::raw(" baz")])
+    );
+    assert_eq!(
+        skip_columns(line.clone(), 5),
+        Line::from(vec![Span::styled("ar", style), Span::raw(" baz")])
+    );
+    assert_eq!(skip_columns(line, 20), Line::default());
+    // wide chars are skipped whole
+    assert_eq!(
+        skip_columns(Line::raw("你好x"), 1),
+        Line::from(vec![Span::raw("好x")])
+    );
+    assert_eq!(
+        skip_columns(Line::raw("你好x"), 2),
+        Line::from(vec![Span::raw("好x")])
+    );
 }
 
 #[test]
@@ -160,22 +579,143 @@ fn test_push_lines() {
 }
 
 impl TextSubstitution {
-    fn to_text<'a>(&'a self, theme: &Theme) -> Text<'a> {
+    // Syntax styles for consecutive byte ranges covering the text, cached so that only results
+    // which are drawn are highlighted, and only once
+    fn syntax_styles(
+        &self,
+        highlight: Option<(&Highlighter, &Path, &str)>,
+    ) -> &[(Range<usize>, Style)] {
+        self.syntax.get_or_init(|| {
+            let Some((highlighter, path, first_line)) = highlight else {
+                return vec![(0..self.text.len(), Style::default())];
+            };
+            let mut styles = vec![];
+            let mut start = 0;
+            for line in self.text.split_inclusive('\n') {
+                for span in highlighter.highlight(path, first_line, line) {
+                    let end = start + span.content.len();
+                    styles.push((start..end, span.style));
+                    start = end;
+                }
+            }
+            styles
+        })
+    }
+
+    // selected is the index of the selected match within this text, if any
+    // highlight is (highlighter, path, first line of the file), used to syntax highlight unmatched text
+    fn to_text<'a>(
+        &'a self,
+        theme: &Theme,
+        selected: Option<usize>,
+        is_rejected: impl Fn(Range<usize>) -> bool,
+        highlight: Option<(&Highlighter, &Path, &str)>,
+    ) -> Text<'a> {
+        self.render(theme, selected, is_rejected, highlight, false)
+    }
+
+    // Like to_text, but always draws the matched text rather than its replacement
+    fn to_original_text<'a>(
+        &'a self,
+        theme: &Theme,
+        selected: Option<usize>,
+        is_rejected: impl Fn(Range<usize>) -> bool,
+        highlight: Option<(&Highlighter, &Path, &str)>,
+    ) -> Text<'a> {
+        self.render(theme, selected, is_rejected, highlight, true)
+    }
+
+    // Push the matched text in range, with any captures within it patched by capture
+    fn push_captures<'a>(
+        &'a self,
+        range: Range<usize>,
+        text: &mut Text<'a>,
+        style: Style,
+        capture: Style,
+    ) {
+        let mut pos = range.start;
+        for c in &self.captures {
+            let start = c.start.clamp(pos, range.end);
+            let end = c.end.clamp(start, range.end);
+            if start == end {
+                continue;
+            }
+            if pos < start {
+                push_lines(&self.text[pos..start], text, style);
+            }
+            push_lines(&self.text[start..end], text, style.patch(capture));
+            pos = end;
+        }
+        if pos < range.end {
+            push_lines(&self.text[pos..range.end], text, style);
+        }
+    }
+
+    fn render<'a>(
+        &'a self,
+        theme: &Theme,
+        selected: Option<usize>,
+        is_rejected: impl Fn(Range<usize>) -> bool,
+        highlight: Option<(&Highlighter, &Path, &str)>,
+        original: bool,
+    ) -> Text<'a> {
         let mut text = Text::default();
         let mut last_end = 0;
 
-        for sub in &self.matches {
-            let range = &sub.range;
+        let syntax = self.syntax_styles(highlight);
+        let push_base = |range: Range<usize>, text: &mut Text<'a>| {
+            for (r, style) in syntax {
+                let start = r.start.max(range.start);
+                let end = r.end.min(range.end);
+                if start < end {
+                    push_lines(&self.text[start..end], text, theme.base.patch(*style));
+                }
+            }
+        };
+
+        // Draw matches in order, clamping any that overlap an earlier match so
+        // no text is drawn twice
+        let mut order: Vec<usize> = (0..self.matches.len()).collect();
+        order.sort_by_key(|&i| (self.matches[i].range.start, self.matches[i].range.end));
+        for i in order {
+            let sub = &self.matches[i];
+            let end = sub.range.end.min(self.text.len()).max(last_end);
+            let range = sub.range.start.clamp(last_end, end)..end;
+            if range.is_empty() && !sub.range.is_empty() {
+                // covered entirely by an earlier match
+                continue;
+            }
             // Add text before the match
             if last_end < range.start {
-                push_lines(&self.text[last_end..range.start], &mut text, theme.base);
+                push_base(last_end..range.start, &mut text);
             }
 
-            if sub.replacement.is_empty() {
+            // found is set when drawing the matched text, which may contain captures
+            let (s, style, found) = if is_rejected(self.file_range(sub)) {
+                if selected != Some(i) {
+                    // rejected, draw the text as if it didn't match
+                    push_base(range.clone(), &mut text);
+                    last_end = range.end;
+                    continue;
+                }
+                (&self.text[range.clone()], theme.base, false)
+            } else if original || sub.replacement.is_empty() {
                 // no replacement text, draw the existing text
-                push_lines(&self.text[range.clone()], &mut text, theme.find);
+                (&self.text[range.clone()], theme.find, true)
+            } else {
+                (sub.replacement.as_str(), theme.replace, false)
+            };
+            let style = if selected == Some(i) {
+                style.patch(theme.selected)
+            } else {
+                style
+            };
+            if s.is_empty() {
+                push_lines(ZERO_WIDTH_MARKER, &mut text, style);
+            } else if found {
+                self.push_captures(range.clone(), &mut text, style, theme.capture);
             } else {
-                push_lines(&sub.replacement, &mut text, theme.replace);
+                push_lines(s, &mut text, style);
             }
 
             last_end = range.end;
@@ -183,7 +723,7 @@ impl TextSubstitution {
 
         // Add remaining text after the last match
         if last_end < self.text.len() {
-            push_lines(&self.text[last_end..], &mut text, theme.base);
+            push_base(last_end..self.text.len(), &mut text);
         }
 
         text
@@ -196,14 +736,16 @@ fn test_line_substitution_to_text_find() {
     assert_eq!(
         TextSubstitution {
             start_line: 1,
-            line_count: 1,
+            offset: 0,
+            syntax: Default::default(),
             text: "foo bar baz".into(),
             matches: vec![Substitution {
                 range: 4..7,
                 replacement: "".to_string(),
             }],
+            captures: vec![],
         }
-        .to_text(&theme),
+        .to_text(&theme, None, |_| false, None),
         Text::from(Line::from(vec![
             Span::styled("foo ", theme.base),
             Span::styled("bar", theme.find),
@@ -212,20 +754,58 @@ fn test_line_substitution_to_text_find() {
     );
 }
 
+#[test]
+fn test_line_substitution_to_text_captures() {
+    let theme = Theme::default();
+    let sub = |replacement: &str| TextSubstitution {
+        start_line: 1,
+        offset: 0,
+        syntax: Default::default(),
+        text: "thing(3, 5)".into(),
+        matches: vec![Substitution {
+            range: 0..11,
+            replacement: replacement.into(),
+        }],
+        captures: vec![0..5, 6..10],
+    };
+    let capture = theme.find.patch(theme.capture);
+    assert_eq!(
+        sub("").to_text(&theme, None, |_| false, None),
+        Text::from(Line::from(vec![
+            Span::styled("thing", capture),
+            Span::styled("(", theme.find),
+            Span::styled("3, 5", capture),
+            Span::styled(")", theme.find),
+        ]))
+    );
+    // the replacement is drawn as a whole, as captures are only known in the matched text
+    assert_eq!(
+        sub("f(3)").to_text(&theme, None, |_| false, None),
+        Text::from(Line::from(vec![Span::styled("f(3)", theme.replace)]))
+    );
+    let selected = capture.patch(theme.selected);
+    assert_eq!(
+        sub("").to_text(&theme, Some(0), |_| false, None).lines[0].spans[0],
+        Span::styled("thing", selected)
+    );
+}
+
 #[test]
 fn test_line_substitution_to_text_replace() {
     let theme = Theme::default();
     assert_eq!(
         TextSubstitution {
             start_line: 1,
-            line_count: 1,
+            offset: 0,
+            syntax: Default::default(),
             text: "foo bar baz".into(),
             matches: vec![Substitution {
                 range: 4..7,
                 replacement: "test".into()
             }],
+            captures: vec![],
         }
-        .to_text(&theme),
+        .to_text(&theme, None, |_| false, None),
         Text::from(Line::from(vec![
             Span::styled("foo ", theme.base),
             Span::styled("test", theme.replace),
@@ -234,6 +814,67 @@ fn test_line_substitution_to_text_replace() {
     );
 }
 
+#[test]
+fn test_line_substitution_to_text_overlapping() {
+    let theme = Theme::default();
+    let sub = |matches: &[(Range<usize>, &str)]| TextSubstitution {
+        start_line: 1,
+        offset: 0,
+        syntax: Default::default(),
+        text: "foo bar baz".into(),
+        matches: matches
+            .iter()
+            .map(|(range, replacement)| Substitution {
+                range: range.clone(),
+                replacement: replacement.to_string(),
+            })
+            .collect(),
+        captures: vec![],
+    };
+
+    // overlapping matches are clamped
+    assert_eq!(
+        sub(&[(4..7, ""), (5..9, "")]).to_text(&theme, None, |_| false, None),
+        Text::from(Line::from(vec![
+            Span::styled("foo ", theme.base),
+            Span::styled("bar", theme.find),
+            Span::styled(" b", theme.find),
+            Span::styled("az", theme.base),
+        ]))
+    );
+
+    // a match inside an earlier match is skipped
+    assert_eq!(
+        sub(&[(0..7, ""), (4..5, "x")]).to_text(&theme, None, |_| false, None),
+        Text::from(Line::from(vec![
+            Span::styled("foo bar", theme.find),
+            Span::styled(" baz", theme.base),
+        ]))
+    );
+
+    // out of order matches are drawn in order, keeping their selection index
+    assert_eq!(
+        sub(&[(8..11, ""), (0..3, "")]).to_text(&theme, Some(0), |_| false, None),
+        Text::from(Line::from(vec![
+            Span::styled("foo", theme.find),
+            Span::styled(" bar ", theme.base),
+            Span::styled("baz", theme.find.patch(theme.selected)),
+        ]))
+    );
+
+    // zero-width matches draw their replacement, or a marker without one
+    assert_eq!(
+        sub(&[(3..3, "X"), (7..7, "")]).to_text(&theme, None, |_| false, None),
+        Text::from(Line::from(vec![
+            Span::styled("foo", theme.base),
+            Span::styled("X", theme.replace),
+            Span::styled(" bar", theme.base),
+            Span::styled(ZERO_WIDTH_MARKER, theme.find),
+            Span::styled(" baz", theme.base),
+        ]))
+    );
+}
+
 #[test]
 fn test_line_substitution_to_text_multiline() {
     // to_text should return multiple lines, with the highlight spanning
@@ -242,14 +883,16 @@ fn test_line_substitution_to_text_multiline() {
     assert_eq!(
         TextSubstitution {
             start_line: 1,
-            line_count: 2,
+            offset: 0,
+            syntax: Default::default(),
             text: "foo bar baz\nbiz baz buz".into(),
             matches: vec![Substitution {
                 range: 8..15,
                 replacement: "".to_string()
             }],
+            captures: vec![],
         }
-        .to_text(&theme),
+        .to_text(&theme, None, |_| false, None),
         Text::from(vec![
             Line::from(vec![
                 Span::styled("foo bar ", theme.base),
@@ -270,7 +913,8 @@ fn test_line_substitution_to_text_multiline_split_on_newline() {
     assert_eq!(
         TextSubstitution {
             start_line: 1,
-            line_count: 2,
+            offset: 0,
+            syntax: Default::default(),
             text: "foo\nbar".into(),
             matches: vec![
                 Substitution {
@@ -282,20 +926,181 @@ fn test_line_substitution_to_text_multiline_split_on_newline() {
                     replacement: "".to_string()
                 }
             ],
+            captures: vec![],
         }
-        .to_text(&theme),
+        .to_text(&theme, None, |_| false, None),
         Text::from(vec![
-            Line::from(vec![
-                Span::styled("foo", theme.find),
-                Span::styled("", theme.base),
-            ]),
+            Line::from(vec![Span::styled("foo", theme.find)]),
             Line::from(vec![Span::styled("bar", theme.find),])
         ])
     );
 }
 
+#[test]
+fn test_line_substitution_to_text_match_ends_at_final_newline() {
+    let theme = Theme::default();
+    let sub = |replacement: &str| TextSubstitution {
+        start_line: 1,
+        offset: 0,
+        syntax: Default::default(),
+        text: "foo\r\nbar\n".into(),
+        matches: vec![Substitution {
+            range: 5..9,
+            replacement: replacement.into(),
+        }],
+        captures: vec![],
+    };
+    let found = sub("");
+    let mut text = found.to_text(&theme, None, |_| false, None);
+    assert_eq!(
+        text,
+        Text::from(vec![
+            Line::from(vec![Span::styled("foo", theme.base)]),
+            Line::from(vec![Span::styled("bar", theme.find)]),
+            Line::default(),
+        ])
+    );
+    // the line after the final newline isn't drawn
+    fit_text(&mut text, 4, None, 0);
+    assert_eq!(text.height(), 2);
+
+    // the replacement has no newline, so nothing follows it
+    let replaced = sub("baz");
+    let mut text = replaced.to_text(&theme, None, |_| false, None);
+    fit_text(&mut text, 4, None, 0);
+    assert_eq!(
+        text,
+        Text::from(vec![
+            Line::from(vec![Span::styled("foo", theme.base)]),
+            Line::from(vec![Span::styled("baz", theme.replace)]),
+        ])
+    );
+}
+
+#[test]
+fn test_line_substitution_to_text_match_starts_after_newline() {
+    let theme = Theme::default();
+    let sub = |text: &str, range| TextSubstitution {
+        start_line: 1,
+        offset: 0,
+        syntax: Default::default(),
+        text: text.into(),
+        matches: vec![Substitution {
+            range,
+            replacement: "".into(),
+        }],
+        captures: vec![],
+    };
+    let expected = Text::from(vec![
+        Line::from(vec![Span::styled("foo", theme.base)]),
+        Line::from(vec![Span::styled("bar", theme.find)]),
+    ]);
+    assert_eq!(
+        sub("foo\nbar", 4..7).to_text(&theme, None, |_| false, None),
+        expected
+    );
+    // the newline is part of the match, but there's nothing to underline on the first line
+    assert_eq!(
+        sub("foo\nbar", 3..7).to_text(&theme, None, |_| false, None),
+        expected
+    );
+    // the first line is empty
+    assert_eq!(
+        sub("\nbar", 1..4).to_text(&theme, None, |_| false, None),
+        Text::from(vec![
+            Line::default(),
+            Line::from(vec![Span::styled("bar", theme.find)]),
+        ])
+    );
+}
+
+#[test]
+fn test_line_substitution_to_text_rejected() {
+    // rejected matches draw as plain text, the selection is reversed
+    let theme = Theme::default();
+    assert_eq!(
+        TextSubstitution {
+            start_line: 1,
+            offset: 10,
+            syntax: Default::default(),
+            text: "foo bar baz".into(),
+            matches: vec![
+                Substitution {
+                    range: 0..3,
+                    replacement: "biz".into()
+                },
+                Substitution {
+                    range: 4..7,
+                    replacement: "buz".into()
+                }
+            ],
+            captures: vec![],
+        }
+        .to_text(&theme, Some(1), |r| r == (10..13), None),
+        Text::from(Line::from(vec![
+            Span::styled("foo", theme.base),
+            Span::styled(" ", theme.base),
+            Span::styled("buz", theme.replace.patch(theme.selected)),
+            Span::styled(" baz", theme.base),
+        ]))
+    );
+}
+
+#[test]
+fn test_line_substitution_to_text_highlight() {
+    let theme = Theme::default();
+    let highlighter = Highlighter::default();
+    let sub = TextSubstitution {
+        start_line: 1,
+        offset: 0,
+        syntax: Default::default(),
+        text: "let x = 5;".into(),
+        matches: vec![Substitution {
+            range: 4..5,
+            replacement: "y".into(),
+        }],
+        captures: vec![],
+    };
+    let text = sub.to_text(
+        &theme,
+        None,
+        |_| false,
+        Some((&highlighter, Path::new("foo.rs"), "")),
+    );
+    let spans = &text.lines[0].spans;
+    assert_eq!(
+        spans.iter().map(|s| s.content.as_ref()).collect::<String>(),
+        "let y = 5;"
+    );
+    // the replacement is drawn over the syntax highlighting
+    assert!(spans.contains(&Span::styled("y", theme.replace)));
+    // the surrounding text is highlighted
+    assert!(
+        spans
+            .iter()
+            .any(|s| s.content != "y" && s.style != theme.base)
+    );
+
+    // the highlighting is cached
+    let cached = sub.to_text(&theme, None, |_| false, None);
+    assert_eq!(&cached.lines[0].spans, spans);
+
+    // without a highlighter, the text is plain
+    let mut sub = sub;
+    sub.syntax.take();
+    let text = sub.to_text(&theme, None, |_| false, None);
+    assert!(
+        text.lines[0]
+            .spans
+            .iter()
+            .all(|s| s.style == theme.base || s.content == "y")
+    );
+}
+
 pub struct App {
     config: Config,
+    highlighter: Option<Highlighter>,
+    filetypes: Vec<FileType>, // settings for certain files, overriding regex_params
     search_params: SearchParams,
     regex_params: RegexParams,
     subs: Vec<FileSubstitution>,
@@ -303,27 +1108,110 @@ pub struct App {
     event_rx: Receiver<Event>,
     pattern_input: LineInput,
     replacement_input: LineInput,
+    filter_input: LineInput,
     editing_pattern: bool,
+    filtering: bool, // editing the filter rather than the pattern or replacement
+    unfiltered: Option<Vec<FileSubstitution>>, // all results, while subs is narrowed by a filter
     finder: Option<Finder>,
     scroll: usize,
+    hscroll: usize,  // columns to skip when drawing results
+    wrap: bool,      // wrap long result lines instead of scrolling
+    diff_view: bool, // show the original and replaced text side by side
+    sort: Sort,
+    results_width: usize, // width available for result text, as of the last draw
+    visible_files: usize, // files that fit entirely on screen, as of the last draw
+    click_targets: Vec<(Range<u16>, usize)>, // screen rows of results and the match each selects
+    match_count: usize,   // total matches in subs
+    selected: usize,      // index of the selected match, counting across all files
+    rejected: HashSet<(PathBuf, Range<usize>)>, // matches to skip on replace
+    confirming: bool,     // showing the prompt to confirm replacement
+    confirm_input: String, // typed so far, when replacing in many files needs "yes" typed
+    report: Option<ReplaceReport>, // what was replaced, shown until the app exits
+    output: RefCell<Option<String>>, // the replaced text when searching stdin, see take_output
+    pending_keys: Vec<KeyEvent>, // the start of a multi-key binding
+    key_seq_timeout: Receiver<Instant>, // fires when pending_keys should be handled alone
+    status: Option<(String, Instant)>, // message shown in the status bar, and when it was set
+    error: Option<String>, // why the pattern is invalid, shown below the results
+    ticker: Receiver<Instant>, // advances the spinner while searching
+    search_timer: Option<Receiver<Instant>>, // fires when typing pauses, while the pattern is unsearched
+    config_rx: Receiver<()>,                 // signals that the config file changed
+    config_watcher: Option<(PathBuf, notify::RecommendedWatcher)>,
+    spinner: usize,
+    clipboard: Option<arboard::Clipboard>,
 }
 
+#[derive(Debug, PartialEq)]
 enum State {
     Continue,
     Exit,
     Confirm,
+    Edit(PathBuf, u64), // open the file at the given line in an editor
 }
 
-impl App {
-    fn start_search(&mut self) {
-        let Some(finder) = &self.finder else {
-            debug!("No finder, not starting search");
-            return;
-        };
+// Set up the terminal for the app, undone by restore
+pub fn init() -> Result<DefaultTerminal> {
+    let terminal = ratatui::init();
+    crossterm::execute!(
+        std::io::stdout(),
+        crossterm::cursor::SetCursorStyle::BlinkingBar,
+        crossterm::event::EnableMouseCapture,
+        // pasted text arrives as one event, rather than keys that may trigger actions
+        crossterm::event::EnableBracketedPaste
+    )?;
+    Ok(terminal)
+}
+
+// Leave the terminal as it was before init
+pub fn restore() {
+    if let Err(err) = crossterm::execute!(
+        std::io::stdout(),
+        crossterm::event::DisableMouseCapture,
+        crossterm::event::DisableBracketedPaste
+    ) {
+        warn!("Failed to disable mouse capture and pasting: {err}");
+    }
+    ratatui::restore();
+}
+
+// The user's preferred editor command, which may include arguments
+fn editor() -> String {
+    std::env::var("EDITOR")
+        .or_else(|_| std::env::var("VISUAL"))
+        .unwrap_or_else(|_| "vi".into())
+}
+
+fn editor_command(editor: &str, path: &Path, line: u64) -> Option<Command> {
+    let mut args = editor.split_whitespace();
+    let mut cmd = Command::new(args.next()?);
+    cmd.args(args).arg(format!("+{line}")).arg(path);
+    Some(cmd)
+}
+
+#[test]
+fn test_editor_command() {
+    let cmd = editor_command("code --wait", Path::new("foo/bar.rs"), 12).unwrap();
+    assert_eq!(cmd.get_program(), "code");
+    assert_eq!(
+        cmd.get_args().collect::<Vec<_>>(),
+        ["--wait", "+12", "foo/bar.rs"]
+    );
+    assert!(editor_command(" ", Path::new("foo"), 1).is_none());
+}
+
+impl App {
+    fn start_search(&mut self) {
+        // dropping the receiver makes the stale search quit on its next send, so results for an
+        // old pattern are never received, even if there is no valid pattern to search for now
+        self.search_rx = None;
+        let Some(finder) = &self.finder else {
+            debug!("No finder, not starting search");
+            return;
+        };
         let finder = finder.clone();
         // blocking channel to pause the search when we aren't ready for more results
         let (tx, rx) = bounded(0);
-        self.search_rx.replace(rx);
+        self.search_rx = Some(rx);
+        self.search_params.stats = Default::default();
         let params = self.search_params.clone();
         std::thread::spawn(move || -> Result<()> {
             search::search(finder, params, tx).context("Search thread error")
@@ -331,120 +1219,577 @@ impl App {
     }
 
     pub fn new(
-        paths: Vec<PathBuf>,
-        types: ignore::types::Types,
+        search_params: SearchParams,
+        regex_params: RegexParams,
         config: Config,
         event_rx: Receiver<Event>,
-        ignore_case: bool,
-        multi_line: bool,
     ) -> Self {
-        let paths = if paths.is_empty() {
-            vec![".".into()]
-        } else {
-            paths
-        };
+        let filetypes = config.filetypes().unwrap_or_else(|err| {
+            error!("Ignoring filetypes: {err:#}");
+            vec![]
+        });
         Self {
-            search_params: SearchParams {
-                paths,
-                types,
-                threads: config.threads,
-            },
-            regex_params: RegexParams {
-                ignore_case,
-                multi_line,
-            },
+            highlighter: config.highlight.then(|| {
+                Highlighter::new(
+                    config.theme.syntax.as_deref(),
+                    config.syntax_dir.as_deref(),
+                    config.theme_dir.as_deref(),
+                    &filetypes,
+                )
+            }),
+            filetypes,
+            search_params,
+            regex_params,
             pattern_input: LineInput::new(config.auto_pairs),
             replacement_input: LineInput::new(config.auto_pairs),
+            filter_input: LineInput::new(false),
+            wrap: config.wrap,
+            diff_view: config.diff_view,
+            sort: config.sort,
             config,
             search_rx: None,
             event_rx,
             subs: vec![],
             editing_pattern: true,
+            filtering: false,
+            unfiltered: None,
             finder: None,
             scroll: 0,
+            hscroll: 0,
+            results_width: 0,
+            visible_files: 0,
+            click_targets: vec![],
+            match_count: 0,
+            selected: 0,
+            rejected: HashSet::new(),
+            confirming: false,
+            confirm_input: String::new(),
+            report: None,
+            output: RefCell::new(None),
+            pending_keys: vec![],
+            key_seq_timeout: never(),
+            status: None,
+            error: None,
+            ticker: tick(SPINNER_INTERVAL),
+            search_timer: None,
+            config_rx: never(),
+            config_watcher: None,
+            spinner: 0,
+            clipboard: None,
         }
     }
 
-    fn replace_all(&self) -> Result<()> {
+    // Replace all accepted matches, then show what changed until the next key exits
+    fn confirm_replace(&mut self) -> Result<()> {
+        let report = self.replace_all()?;
+        info!("{}", report.summary());
+        // replace_all drained any pending results
+        self.search_rx = None;
+        self.report = Some(report);
+        Ok(())
+    }
+
+    fn replace_all(&self) -> Result<ReplaceReport> {
+        let mut report = ReplaceReport::default();
         let Some(ref finder) = self.finder else {
             debug!("No finder");
-            return Ok(());
+            return Ok(report);
+        };
+        // write the file if it changed, noting it in the report
+        let record = |report: &mut ReplaceReport, path: &Path, text: &str, replaced| {
+            let (replaced, count): (String, usize) = replaced;
+            if replaced != text && self.write_replacement(path, &replaced)? {
+                report.files.push((path.into(), count));
+            }
+            anyhow::Ok(())
         };
 
         debug!("Replacing in cached results");
         for sub in &self.subs {
             let path = &sub.path;
             debug!("Replacing in {path:?}");
-            let text = std::fs::read_to_string(path)?;
-            let text = finder.replace(path, &text, self.replacement_input.pattern())?;
-            std::fs::write(path, text)?;
+            let Some(text) = self.read_text(path)? else {
+                continue;
+            };
+            let replaced = sub.replace(
+                &text,
+                finder,
+                self.replacement_input.pattern(),
+                &self.rejected,
+            )?;
+            record(&mut report, path, &text, replaced)?;
         }
 
         let Some(ref rx) = self.search_rx else {
             debug!("No pending search results, replacement complete");
-            return Ok(());
+            return Ok(report);
         };
 
+        // start_search replaces search_rx whenever finder changes, so these are its results
         debug!("Draining remaining results");
         for finding in rx {
+            let replacement = self.replacement_input.pattern();
+            if let Some(filter) = self.filter() {
+                // only replace what the filter would have shown
+                let Some(sub) =
+                    FileSubstitution::new(finding, finder, replacement)?.filtered(filter)
+                else {
+                    continue;
+                };
+                let Some(text) = self.read_text(&sub.path)? else {
+                    continue;
+                };
+                let replaced = sub.replace(&text, finder, replacement, &self.rejected)?;
+                record(&mut report, &sub.path, &text, replaced)?;
+                continue;
+            }
             let path = &finding.path;
             debug!("Replacing in {path:?}");
-            let text = std::fs::read_to_string(path)?;
-            let text = finder.replace(path, &text, self.replacement_input.pattern())?;
-            std::fs::write(path, text)?;
+            if finder.can_stream() && !self.is_stdin(path) && !finder::is_hardlinked(path)? {
+                if let Some((tmp, count)) = finder.replace_streaming(path, replacement)?
+                    && finder::backup(path, &self.config.backup)?
+                {
+                    finder::persist(tmp, path)?;
+                    report.files.push((path.clone(), count));
+                }
+                continue;
+            }
+            let Some(text) = self.read_text(path)? else {
+                continue;
+            };
+            let replaced = finder.replace_counted(path, &text, replacement)?;
+            record(&mut report, path, &text, replaced)?;
         }
 
         debug!("Replacement complete");
+        Ok(report)
+    }
+
+    // Replace in just the file containing the selected match, and drop it from the results
+    fn replace_selected_file(&mut self) -> Result<()> {
+        let Some(ref finder) = self.finder else {
+            debug!("No finder");
+            return Ok(());
+        };
+        let Some((file_idx, _, _)) = self.locate(self.selected) else {
+            debug!("No match selected");
+            return Ok(());
+        };
+
+        let sub = &self.subs[file_idx];
+        let path = &sub.path;
+        debug!("Replacing in {path:?}");
+        let Some(text) = self.read_text(path)? else {
+            self.set_status(format!("Skipped {}, not valid UTF-8", path.display()));
+            return Ok(());
+        };
+        let (replaced, _) = sub.replace(
+            &text,
+            finder,
+            self.replacement_input.pattern(),
+            &self.rejected,
+        )?;
+        if !self.write_replacement(path, &replaced)? {
+            self.set_status(format!("Skipped {}, backup exists", path.display()));
+            return Ok(());
+        }
+
+        let replaced = sub.accepted_count(&self.rejected);
+        let sub = self.remove_file(file_idx);
+        self.set_status(format!(
+            "Replaced {} in {}",
+            count(replaced, "match", "matches"),
+            sub.path.display()
+        ));
+        Ok(())
+    }
+
+    // Drop a file from the results, so it won't be replaced, keeping scroll and selection in range
+    fn remove_file(&mut self, file_idx: usize) -> FileSubstitution {
+        let sub = self.subs.remove(file_idx);
+        if let Some(all) = &mut self.unfiltered {
+            all.retain(|s| s.path != sub.path);
+        }
+        self.match_count -= sub.match_count();
+        self.rejected.retain(|(path, _)| *path != sub.path);
+        self.scroll = self.scroll.min(self.subs.len().saturating_sub(1));
+
+        // select the first match of the next file
+        let first = self.subs[..file_idx].iter().map(|s| s.match_count()).sum();
+        self.select(first);
+        sub
+    }
+
+    // Load pattern/replacement history saved by save_history
+    pub fn load_history(&mut self, dir: &Path) {
+        self.pattern_input
+            .set_history(read_history(&dir.join(SEARCH_HISTORY)));
+        self.replacement_input
+            .set_history(read_history(&dir.join(REPLACE_HISTORY)));
+    }
+
+    // Add the current pattern/replacement to the history, and save it to dir
+    pub fn save_history(&mut self, dir: &Path) -> Result<()> {
+        let max = self.config.history_size;
+        self.pattern_input.push_history(max);
+        self.replacement_input.push_history(max);
+        write_history(&dir.join(SEARCH_HISTORY), self.pattern_input.history())?;
+        write_history(&dir.join(REPLACE_HISTORY), self.replacement_input.history())
+    }
+
+    // Save the pattern, replacement, flags, and paths to dir, to restore with resume
+    pub fn save_session(&self, dir: &Path) -> Result<()> {
+        let params = &self.regex_params;
+        let session = Session {
+            pattern: self.pattern_input.pattern().into(),
+            replacement: self.replacement_input.pattern().into(),
+            ignore_case: params.ignore_case,
+            multi_line: params.multi_line,
+            whole_word: params.whole_word,
+            fixed_string: params.fixed_string,
+            paths: self
+                .search_params
+                .paths
+                .iter()
+                .map(std::path::absolute)
+                .collect::<std::io::Result<_>>()?,
+        };
+        let path = dir.join(SESSION);
+        std::fs::write(&path, toml::to_string(&session)?)
+            .with_context(|| format!("Writing session to {path:?}"))
+    }
+
+    // Restore the session saved by save_session, and start searching
+    pub fn resume(&mut self, dir: &Path) {
+        let path = dir.join(SESSION);
+        let session = std::fs::read_to_string(&path)
+            .context("No session to resume")
+            .and_then(|text| Ok(toml::from_str::<Session>(&text)?));
+        let session = match session {
+            Ok(session) => session,
+            Err(err) => {
+                warn!("Failed to resume from {path:?}: {err:#}");
+                self.set_status(format!("Failed to resume: {}", err.root_cause()));
+                return;
+            }
+        };
+        info!("Resuming session: {session:?}");
+        self.pattern_input.set_pattern(&session.pattern);
+        self.replacement_input.set_pattern(&session.replacement);
+        self.regex_params.ignore_case = session.ignore_case;
+        self.regex_params.multi_line = session.multi_line;
+        self.regex_params.whole_word = session.whole_word;
+        self.regex_params.fixed_string = session.fixed_string;
+        if !session.paths.is_empty() {
+            self.search_params.paths = session.paths;
+        }
+        if !session.pattern.is_empty() {
+            self.update_pattern();
+        }
+    }
+
+    // Whether path is the stand-in for text read from stdin, rather than a file
+    fn is_stdin(&self, path: &Path) -> bool {
+        self.search_params.stdin.is_some() && path == Path::new(STDIN_PATH)
+    }
+
+    // Read the file at path for replacement, see finder::read_text
+    fn read_text(&self, path: &Path) -> Result<Option<String>> {
+        match &self.search_params.stdin {
+            Some(text) if self.is_stdin(path) => Ok(Some(text.to_string())),
+            _ => finder::read_text(path),
+        }
+    }
+
+    // The replaced stdin, to print once the app exits, or None if it wasn't replaced
+    pub fn take_output(&mut self) -> Option<String> {
+        self.output.take()
+    }
+
+    // Write the replaced text to path, backing up the original first if configured
+    // Returns false if the file was left alone, see backup
+    fn write_replacement(&self, path: &Path, replaced: &str) -> Result<bool> {
+        if self.is_stdin(path) {
+            *self.output.borrow_mut() = Some(replaced.into());
+            return Ok(true);
+        }
+        if !finder::backup(path, &self.config.backup)? {
+            return Ok(false);
+        }
+        std::fs::write(path, replaced)?;
+        Ok(true)
+    }
+
+    // Reload the theme and keys whenever the config file at path changes
+    pub fn watch_config(&mut self, path: PathBuf) -> Result<()> {
+        // watch the directory, as editors often replace the file rather than writing to it
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir = dir.canonicalize()?;
+        let path = dir.join(path.file_name().context("Config path has no file name")?);
+
+        let (tx, rx) = bounded(1);
+        let watched = path.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+                match res {
+                    Ok(ev)
+                        if ev.paths.contains(&watched)
+                            && (ev.kind.is_modify() || ev.kind.is_create()) =>
+                    {
+                        // a reload is already pending if this fails
+                        _ = tx.try_send(());
+                    }
+                    Ok(_) => {}
+                    Err(err) => warn!("Error watching config: {err}"),
+                }
+            })?;
+        watcher.watch(&dir, notify::RecursiveMode::NonRecursive)?;
+        info!("Watching config at {path:?}");
+        self.config_rx = rx;
+        self.config_watcher = Some((path, watcher));
         Ok(())
     }
 
-    pub fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+    // Apply the theme and keys from the config file, keeping the current config if it is invalid
+    fn reload_config(&mut self) {
+        let Some((ref path, _)) = self.config_watcher else {
+            return;
+        };
+        info!("Reloading config from {path:?}");
+        match Config::load(path) {
+            Ok(config) => {
+                if self.highlighter.is_some() && config.theme.syntax != self.config.theme.syntax {
+                    self.highlighter = Some(Highlighter::new(
+                        config.theme.syntax.as_deref(),
+                        self.config.syntax_dir.as_deref(),
+                        self.config.theme_dir.as_deref(),
+                        &self.filetypes,
+                    ));
+                    // highlight again with the new theme
+                    for sub in self
+                        .subs
+                        .iter_mut()
+                        .chain(self.unfiltered.iter_mut().flatten())
+                    {
+                        for s in &mut sub.subs {
+                            s.syntax.take();
+                        }
+                    }
+                }
+                self.config.theme = config.theme;
+                self.config.keys = config.keys;
+                self.set_status("Reloaded config");
+            }
+            Err(err) => {
+                warn!("Failed to reload config: {err:#}");
+                self.set_status(format!("Failed to reload config: {}", err.root_cause()));
+            }
+        }
+    }
+
+    // events is held while reading terminal events, it is locked to stop reading while an editor runs
+    pub fn run(&mut self, terminal: &mut DefaultTerminal, events: &Mutex<()>) -> Result<()> {
         loop {
             let mut need_more = false;
             terminal.draw(|frame| need_more = self.draw(frame).unwrap())?;
             match self.handle_events(need_more)? {
                 State::Continue => {}
                 State::Exit => return Ok(()),
-                State::Confirm => return self.replace_all(),
+                State::Confirm => self.confirm_replace()?,
+                State::Edit(path, line) => {
+                    let _lock = events.lock();
+                    restore();
+                    let editor = editor();
+                    info!("Opening {path:?}:{line} with {editor}");
+                    match editor_command(&editor, &path, line).map(|mut cmd| cmd.status()) {
+                        Some(Ok(status)) if !status.success() => {
+                            warn!("{editor} exited with {status}")
+                        }
+                        Some(Ok(_)) => {}
+                        Some(Err(err)) => error!("Failed to run {editor}: {err}"),
+                        None => error!("No editor set"),
+                    }
+                    *terminal = init()?;
+                    // the file may have changed, so search again
+                    self.update_pattern();
+                }
+            }
+        }
+    }
+
+    // Title for the search input, showing a forced search mode
+    fn search_header(&self) -> String {
+        match self.regex_params.mode {
+            SearchMode::Auto => "Search".to_string(),
+            mode => format!("Search [{mode}]"),
+        }
+    }
+
+    // Show a message in the status bar until a key is pressed or it times out
+    fn set_status(&mut self, message: impl Into<String>) {
+        self.status = Some((message.into(), Instant::now()));
+    }
+
+    // The message in the status bar, if it hasn't timed out
+    fn status(&self) -> Option<&str> {
+        self.status
+            .as_ref()
+            .filter(|(_, at)| at.elapsed() < STATUS_TIMEOUT)
+            .map(|(message, _)| message.as_str())
+    }
+
+    // The summary of a finished search, with --stats
+    fn stats_message(&self) -> Option<String> {
+        (self.config.stats && self.finder.is_some() && self.search_rx.is_none())
+            .then(|| self.search_params.stats.summary().to_string())
+    }
+
+    // The right side of the status bar: the kind of pattern, its flags, and the results so far
+    fn status_summary(&self) -> String {
+        let mut flags = String::new();
+        for (on, flag) in [
+            (self.regex_params.ignore_case, 'i'),
+            (self.regex_params.multi_line, 'm'),
+            (self.regex_params.whole_word, 'w'),
+            (self.regex_params.fixed_string, 'F'),
+            (self.regex_params.invert_match, 'v'),
+            (self.search_params.no_ignore, 'u'),
+            (self.search_params.hidden, 'h'),
+        ] {
+            if on {
+                flags.push(flag);
             }
         }
+        // the kind of the current pattern, which auto mode may search either way
+        let mut summary = match &self.finder {
+            Some(f) if f.is_ast() => "ast".to_string(),
+            Some(_) => "regex".to_string(),
+            None => self.regex_params.mode.to_string(),
+        };
+        if !flags.is_empty() {
+            summary = format!("{summary} ({flags})");
+        }
+        if self.finder.is_none() {
+            return summary;
+        }
+
+        let mut matches = count(self.match_count, "match", "matches");
+        if self.match_count > 0 {
+            matches = format!("{}/{matches}", self.selected + 1);
+        }
+        summary = format!(
+            "{summary} │ {matches} in {}",
+            count(self.subs.len(), "file", "files")
+        );
+        if self.search_rx.is_some() {
+            // more results may be coming
+            summary.push(' ');
+            summary.push(SPINNER[self.spinner % SPINNER.len()]);
+        }
+        summary
+    }
+
+    // Why some files weren't searched for an ast-grep pattern, if any
+    fn skipped_message(&self) -> Option<String> {
+        let skipped = self.finder.as_ref()?.ast_skipped();
+        let no_lang = count(skipped.no_lang, "file", "files") + " with no ast-grep language";
+        let invalid = "where the pattern is invalid for the language";
+        match (skipped.no_lang, skipped.invalid_pattern) {
+            (0, 0) => None,
+            (0, n) => Some(format!("Skipped {} {invalid}", count(n, "file", "files"))),
+            (_, 0) => Some(format!("Skipped {no_lang}")),
+            (_, n) => Some(format!("Skipped {no_lang}, and {n} {invalid}")),
+        }
     }
 
     // returns true if more results are needed
     fn draw(&mut self, frame: &mut Frame) -> Result<bool> {
         trace!("Drawing");
+        if let Some(report) = &self.report {
+            self.draw_report(frame, report);
+            return Ok(false);
+        }
         let theme = &self.config.theme;
+        let message = self
+            .status()
+            .map(str::to_string)
+            .or_else(|| self.skipped_message())
+            .or_else(|| self.stats_message());
 
-        let [input_area, search_area] = Layout::default()
+        let input_height = self
+            .pattern_input
+            .height()
+            .max(self.replacement_input.height());
+        let [input_area, search_area, error_area, status_area] = Layout::default()
             .direction(Direction::Vertical)
-            .constraints(vec![Constraint::Length(3), Constraint::Fill(1)])
+            .constraints(vec![
+                Constraint::Length(input_height),
+                Constraint::Fill(1),
+                Constraint::Length(self.error.as_ref().map_or(0, |e| e.lines().count() as u16)),
+                Constraint::Length(1),
+            ])
             .margin(1) // to account for the border we draw around everything
             .areas(frame.area());
 
+        if let Some(error) = &self.error {
+            let style = theme.base.patch(theme.error);
+            frame.render_widget(Paragraph::new(error.as_str()).style(style), error_area);
+        }
+        // the message takes priority if there isn't room for both
+        let mut summary = self.status_summary();
+        let message_width = message.as_ref().map_or(0, |m| m.width() + 1);
+        if message_width + summary.width() > status_area.width as usize {
+            summary.clear();
+        }
+        let [message_area, summary_area] = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(vec![
+                Constraint::Fill(1),
+                Constraint::Length(summary.width() as u16),
+            ])
+            .spacing(1)
+            .areas(status_area);
+        if let Some(message) = message {
+            frame.render_widget(Paragraph::new(message).style(theme.base), message_area);
+        }
+        frame.render_widget(
+            Paragraph::new(summary).style(theme.base.add_modifier(Modifier::DIM)),
+            summary_area,
+        );
+
+        let search_header = self.search_header();
+        // +2 for borders
+        let header_width = search_header.width() as u16 + 2;
         let [pattern_area, tab_area, replace_area] = Layout::default()
             .direction(Direction::Horizontal)
             .constraints(vec![
-                Constraint::Length(self.pattern_input.size().max(16)),
+                Constraint::Length(self.pattern_input.size().max(header_width).max(16)),
                 Constraint::Length(9),
                 Constraint::Length(self.replacement_input.size().max(16)),
             ])
             .areas(input_area);
 
-        let mut flags = String::new();
-        if self.regex_params.ignore_case {
-            flags += "i";
-        }
-        if self.regex_params.multi_line {
-            flags += "m";
-        }
-        let mut search_header = "Search".to_string();
-        if !flags.is_empty() {
-            search_header = format!("{search_header} ({flags})");
+        self.pattern_input.draw(
+            frame,
+            pattern_area,
+            &search_header,
+            theme.base,
+            match self.error {
+                Some(_) => theme.base.patch(theme.error),
+                None => theme.base,
+            },
+        );
+        if self.regex_params.invert_match {
+            let disabled = theme.base.add_modifier(Modifier::DIM);
+            self.replacement_input
+                .draw(frame, replace_area, "Replace (off)", disabled, disabled);
+        } else {
+            self.replacement_input
+                .draw(frame, replace_area, "Replace", theme.base, theme.base);
         }
-        self.pattern_input
-            .draw(frame, pattern_area, &search_header, theme.base);
-        self.replacement_input
-            .draw(frame, replace_area, "Replace", theme.base);
 
         if let Some(swap_key) = self
             .config
@@ -465,99 +1810,607 @@ impl App {
         frame.set_cursor_position(if self.editing_pattern {
             Position::new(
                 pattern_area.x + self.pattern_input.cursor_pos() + 1,
-                pattern_area.y + 1,
+                pattern_area.y + self.pattern_input.cursor_line() + 1,
             )
         } else {
             Position::new(
                 replace_area.x + self.replacement_input.cursor_pos() + 1,
-                replace_area.y + 1,
+                replace_area.y + self.replacement_input.cursor_line() + 1,
             )
         });
 
-        let mut size_left = search_area.height;
-        let constraints: Vec<_> = self
-            .subs
-            .iter()
-            .skip(self.scroll)
-            .map(|s| (s.line_count() + 2)) // +2 for top/bottom border
-            .take_while(|s| {
-                let ret = size_left > 0;
-                size_left = size_left.saturating_sub(*s);
-                ret
-            })
-            .map(Constraint::Length)
-            .collect();
+        // the filter sits above the results while in use
+        let [filter_area, search_area] = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![
+                Constraint::Length(if self.filtering || self.unfiltered.is_some() {
+                    self.filter_input.height()
+                } else {
+                    0
+                }),
+                Constraint::Fill(1),
+            ])
+            .areas(search_area);
+        if filter_area.height > 0 {
+            self.filter_input
+                .draw(frame, filter_area, "Filter", theme.base, theme.base);
+        }
+        if self.filtering {
+            frame.set_cursor_position(Position::new(
+                filter_area.x + self.filter_input.cursor_pos() + 1,
+                filter_area.y + 1,
+            ));
+        }
+        if self.pattern_input.pattern().is_empty() {
+            let hint = Paragraph::new("Type a pattern to search")
+                .style(theme.base.add_modifier(Modifier::DIM));
+            frame.render_widget(hint, search_area);
+        }
 
-        let search_areas = Layout::vertical(constraints.as_slice()).split(search_area);
-        let subs = self.subs.iter().skip(self.scroll);
-        for (area, sub) in search_areas.iter().zip(subs) {
+        let number_width = if self.config.column {
+            LINE_COLUMN_WIDTH
+        } else {
+            LINE_NUMBER_WIDTH
+        };
+        // borders, line numbers, and column spacing
+        self.results_width = search_area.width.saturating_sub(2 + number_width + 1) as usize;
+        if self.diff_view {
+            // split between the original and replaced text
+            self.results_width = self.results_width.saturating_sub(1) / 2;
+        }
+        let wrap_width = self.wrap.then_some(self.results_width);
+        let text_columns = if self.diff_view { 2 } else { 1 };
+        let selected = self.locate(self.selected);
+        let mut size_left = search_area.height;
+        let mut heights = vec![];
+        let mut tables = vec![];
+        for (file_idx, sub) in self.subs.iter().enumerate().skip(self.scroll) {
+            if size_left == 0 {
+                break;
+            }
+            let mut height = 2; // top/bottom border
+            let mut rows = vec![]; // height of each row, and the matches in the file before it
+            let mut matches_before = 0;
             let table = Table::new(
-                sub.subs.iter().map(|s| {
-                    Row::new(vec![Text::raw(s.start_line.to_string()), s.to_text(theme)])
-                        .height(s.line_count)
+                sub.subs.iter().enumerate().map(|(line_idx, s)| {
+                    let selected = match selected {
+                        Some((f, l, m)) if (f, l) == (file_idx, line_idx) => Some(m),
+                        _ => None,
+                    };
+                    let highlight = self
+                        .highlighter
+                        .as_ref()
+                        .map(|h| (h, sub.path.as_path(), sub.first_line.as_str()));
+                    let is_rejected = |range| self.rejected.contains(&(sub.path.clone(), range));
+                    let mut texts = vec![s.to_text(theme, selected, is_rejected, highlight)];
+                    if self.diff_view {
+                        let original = s.to_original_text(theme, selected, is_rejected, highlight);
+                        texts.insert(0, original);
+                    }
+                    for text in &mut texts {
+                        fit_text(text, self.config.tab_width, wrap_width, self.hscroll);
+                    }
+                    let row_height = texts.iter().map(Text::height).max().unwrap_or(1) as u16;
+                    height += row_height;
+                    rows.push((row_height, matches_before));
+                    matches_before += s.matches.len();
+                    let line_number = match s.matches.first() {
+                        // a tab is one column here, as editors count them for line:column
+                        Some(m) if self.config.column => {
+                            Text::raw(format!("{}:{}", s.start_line, s.columns(m, 1).start + 1))
+                        }
+                        _ => Text::raw(s.start_line.to_string()),
+                    };
+                    let line_number = if selected.is_some() {
+                        line_number.style(theme.selected)
+                    } else {
+                        line_number
+                    };
+                    let row = Row::new([line_number].into_iter().chain(texts)).height(row_height);
+                    if s.matches.is_empty() {
+                        // context line
+                        row.style(theme.base.add_modifier(Modifier::DIM))
+                    } else {
+                        row
+                    }
                 }),
-                &[Constraint::Max(6), Constraint::Fill(1)],
+                [Constraint::Max(number_width)]
+                    .into_iter()
+                    .chain(std::iter::repeat_n(Constraint::Fill(1), text_columns)),
             )
-            .style(theme.base)
-            .block(Block::bordered().title_top(sub.path.to_string_lossy()));
+            .style(theme.base);
+            size_left = size_left.saturating_sub(height);
+            heights.push(height);
+            let title = match sub.lang {
+                Some(lang) => format!("{} — AST: {lang}", sub.path.display()),
+                None => sub.path.display().to_string(),
+            };
+            let matches = sub.match_count();
+            // inverted searches find lines without matches, so there's nothing to count
+            let count = match matches {
+                0 => None,
+                n if sub.truncated => Some(format!("showing first {n} of many")),
+                n => Some(count(n, "match", "matches")),
+            };
+            tables.push(((title, count), table, rows, matches_before));
+        }
+
+        // If some files don't fit, keep the last row to say so
+        let fits = |height: u16| {
+            let mut size_left = height;
+            heights
+                .iter()
+                .take_while(|&&h| {
+                    let fit = h <= size_left;
+                    size_left = size_left.saturating_sub(h);
+                    fit
+                })
+                .count()
+        };
+        let mut results_area = search_area;
+        let truncated = fits(results_area.height) < self.subs.len().saturating_sub(self.scroll);
+        if truncated && results_area.height > 1 {
+            results_area.height -= 1;
+        }
+        self.visible_files = fits(results_area.height);
+
+        // Files are drawn at full height, only the last one may be cut off
+        let mut y = results_area.y;
+        let mut drawn_files = 0;
+        let mut cut_lines = 0;
+        let mut first_match: usize = self
+            .subs
+            .iter()
+            .take(self.scroll)
+            .map(|s| s.match_count())
+            .sum();
+        let mut click_targets = vec![];
+        for (height, ((title, count), table, rows, match_count)) in heights.into_iter().zip(tables)
+        {
+            if y >= results_area.bottom() {
+                break;
+            }
+            let area = Rect {
+                y,
+                height: height.min(results_area.bottom() - y),
+                ..results_area
+            };
+            // leave the bottom open so it's clear the file continues
+            let borders = if area.height < height {
+                cut_lines = height - area.height - 1;
+                Borders::ALL - Borders::BOTTOM
+            } else {
+                Borders::ALL
+            };
+            // the count is dropped rather than hide part of the path
+            let fits = |count: &String| title.width() + count.width() + 3 <= area.width as usize;
+            let count = count.filter(fits);
+            let mut block = Block::new().borders(borders).title_top(title);
+            if let Some(count) = count {
+                block = block.title_top(Line::from(count).right_aligned());
+            }
+            let table = table.block(block);
             let mut table_state = TableState::default();
-            frame.render_stateful_widget(table, *area, &mut table_state);
+            frame.render_stateful_widget(table, area, &mut table_state);
+
+            // clicking the title selects the first match, clicking a line selects the next match
+            let last_match = first_match + match_count.saturating_sub(1);
+            click_targets.push((y..y + 1, first_match));
+            let mut row_y = y + 1;
+            for (row_height, matches_before) in rows {
+                let end = (row_y + row_height).min(area.bottom());
+                if row_y >= end {
+                    break;
+                }
+                click_targets.push((row_y..end, (first_match + matches_before).min(last_match)));
+                row_y = end;
+            }
+            first_match += match_count;
+
+            y += area.height;
+            drawn_files += 1;
+        }
+        self.click_targets = click_targets;
+
+        if truncated && results_area.height < search_area.height {
+            let more_files = self.subs.len().saturating_sub(self.scroll + drawn_files);
+            let text = if more_files > 0 {
+                let more = if self.search_rx.is_some() { "+" } else { "" };
+                count(more_files, "file", "files").replacen(' ', &format!("{more} more "), 1)
+            } else {
+                count(cut_lines.into(), "line", "lines").replacen(' ', " more ", 1)
+            };
+            frame.render_widget(
+                Line::from(format!("… {text}")).style(theme.base),
+                Rect {
+                    y: results_area.bottom(),
+                    height: 1,
+                    ..results_area
+                },
+            );
+        }
+
+        if self.confirming {
+            self.draw_confirm(frame, search_area);
         }
 
         trace!("Draw complete");
         // Pause searching once we're showing all the results we can on the screen,
         // Plus a few buffered results (so scrolling is instant)
-        Ok(self.subs.len() < search_areas.len() + SEARCH_BUFFER + self.scroll)
+        Ok(self.subs.len() < drawn_files + SEARCH_BUFFER + self.scroll)
     }
 
-    fn on_finding(&mut self, finding: FileMatch) -> Result<()> {
-        let Some(ref finder) = self.finder else {
-            warn!("Got substitution, but no regex set");
-            return Ok(());
+    // The files changed by replacing, shown in place of everything else
+    fn draw_report(&self, frame: &mut Frame, report: &ReplaceReport) {
+        let theme = &self.config.theme;
+        let text = if report.files.is_empty() {
+            Text::from("No files changed")
+        } else {
+            report.file_lines().map(Line::from).collect()
         };
-        let sub = FileSubstitution::new(finding, finder, self.replacement_input.pattern())?;
-        debug!("Pushing item: {sub:?}");
-        self.subs.push(sub);
-        debug!("Total items: {}", self.subs.len());
-        Ok(())
+        frame.render_widget(
+            Paragraph::new(text).style(theme.base).block(
+                Block::bordered()
+                    .title(report.summary())
+                    .title_bottom("Press any key to exit"),
+            ),
+            frame.area(),
+        );
     }
 
-    fn update_pattern(&mut self) {
-        let pattern = self.pattern_input.pattern();
-        self.finder = Finder::new(pattern, &self.regex_params);
-        info!("New pattern: {pattern}");
-        self.start_search();
-        self.subs.clear();
+    // The number of accepted matches, and the number of files containing them
+    fn accepted_counts(&self) -> (usize, usize) {
+        self.subs
+            .iter()
+            .map(|s| s.accepted_count(&self.rejected))
+            .filter(|&n| n > 0)
+            .fold((0, 0), |(m, f), n| (m + n, f + 1))
     }
 
-    fn update_replacement(&mut self) {
-        let replacement = self.replacement_input.pattern();
-        let Some(finder) = &self.finder else { return };
-        for sub in &mut self.subs {
-            sub.update_replacement(finder, replacement);
+    // Whether to confirm by typing "yes" rather than pressing y, as too many files would change.
+    // A search in progress may find any number more, so it counts as too many.
+    fn confirm_typed(&self) -> bool {
+        let threshold = self.config.confirm_threshold;
+        !self.confirm_input.is_empty()
+            || threshold > 0 && (self.search_rx.is_some() || self.accepted_counts().1 > threshold)
+    }
+
+    // Prompt over the results to confirm replacing all accepted matches
+    fn draw_confirm(&self, frame: &mut Frame, area: Rect) {
+        let (matches, files) = self.accepted_counts();
+        let question = format!(
+            "Replace {} across {}{}?",
+            count(matches, "match", "matches"),
+            count(files, "file", "files"),
+            if self.search_rx.is_some() {
+                " so far"
+            } else {
+                ""
+            },
+        );
+        let mut lines = vec![];
+        if self.confirm_typed() {
+            lines.push(question);
+            lines.push(format!("Type yes to confirm: {}", self.confirm_input));
+        } else {
+            lines.push(format!("{question} [y/N]"));
         }
+        if self.search_rx.is_some() {
+            lines.push("Files not searched yet will also be replaced".into());
+        }
+        let width = lines.iter().map(|l| l.width()).max().unwrap_or(0);
+        let height = lines.len();
+        let text: Text = lines.into_iter().map(Line::from).collect();
+
+        let theme = &self.config.theme;
+        let [area] = Layout::horizontal([Constraint::Length(width as u16 + 4)])
+            .flex(Flex::Center)
+            .areas(area);
+        let [area] = Layout::vertical([Constraint::Length(height as u16 + 2)])
+            .flex(Flex::Center)
+            .areas(area);
+        frame.render_widget(Clear, area);
+        frame.render_widget(
+            Paragraph::new(text)
+                .centered()
+                .style(theme.base)
+                .block(Block::bordered().title("Confirm")),
+            area,
+        );
     }
 
-    /// updates the application's state based on user input
-    fn handle_events(&mut self, need_more: bool) -> Result<State> {
-        trace!("Awaiting event");
+    // Find the (file, line, match) indices of the nth match across all files
+    fn locate(&self, mut n: usize) -> Option<(usize, usize, usize)> {
+        for (file_idx, file) in self.subs.iter().enumerate() {
+            for (line_idx, line) in file.subs.iter().enumerate() {
+                if n < line.matches.len() {
+                    return Some((file_idx, line_idx, n));
+                }
+                n -= line.matches.len();
+            }
+        }
+        None
+    }
 
-        let search_rx = match self.search_rx {
-            Some(ref rx) if need_more => rx,
-            _ => &never(),
+    // Select the nth match, scrolling just enough to keep it visible
+    fn select(&mut self, n: usize) {
+        self.selected = n.min(self.match_count.saturating_sub(1));
+        if let Some((file_idx, _, _)) = self.locate(self.selected) {
+            let visible = self.visible_files.max(1);
+            if file_idx < self.scroll {
+                self.scroll = file_idx;
+            } else if file_idx >= self.scroll + visible {
+                self.scroll = file_idx + 1 - visible;
+            }
+        }
+        info!("Selected match: {}", self.selected);
+    }
+
+    fn toggle_selected(&mut self) {
+        let Some((file_idx, line_idx, match_idx)) = self.locate(self.selected) else {
+            debug!("No match selected");
+            return;
         };
+        let file = &self.subs[file_idx];
+        let line = &file.subs[line_idx];
+        let key = (file.path.clone(), line.file_range(&line.matches[match_idx]));
+        if !self.rejected.remove(&key) {
+            info!("Rejecting match: {key:?}");
+            self.rejected.insert(key);
+        } else {
+            info!("Accepting match: {key:?}");
+        }
+    }
 
-        // Bias for events, as they may invalidate search results
-        select_biased! {
-            recv(self.event_rx) -> ev => {
-                debug!("Handling terminal event: {ev:?}");
-                match ev? {
-                    Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
+    fn on_finding(&mut self, finding: FileMatch) -> Result<()> {
+        let Some(ref finder) = self.finder else {
+            warn!("Got substitution, but no regex set");
+            return Ok(());
+        };
+        let sub = FileSubstitution::new(finding, finder, self.replacement_input.pattern())?;
+        let sub = match &mut self.unfiltered {
+            Some(all) => {
+                let filtered = sub.filtered(self.filter_input.pattern());
+                let idx = all.partition_point(|s| s.compare(&sub, self.sort).is_le());
+                all.insert(idx, sub);
+                let Some(sub) = filtered else {
+                    debug!("Filtered out new result");
+                    return Ok(());
+                };
+                sub
+            }
+            None => sub,
+        };
+        debug!("Inserting item: {sub:?}");
+        if self.subs.is_empty() {
+            self.scroll_to_first_match(&sub);
+        }
+        // Insert in sorted order as results stream in, rather than only sorting on request,
+        // so the configured sort applies from the start. The search pauses once the screen
+        // is full, so the order only covers the files found so far.
+        let idx = self
+            .subs
+            .partition_point(|s| s.compare(&sub, self.sort).is_le());
+        // keep the view and selection on the same files
+        if idx < self.scroll {
+            self.scroll += 1;
+        }
+        if self.locate(self.selected).is_some_and(|(f, _, _)| idx <= f) {
+            self.selected += sub.match_count();
+        }
+        self.match_count += sub.match_count();
+        self.subs.insert(idx, sub);
+        debug!("Total items: {}", self.subs.len());
+        Ok(())
+    }
+
+    // Re-sort the results, keeping the same match selected
+    fn sort_results(&mut self) {
+        let selected = self.locate(self.selected).map(|(file_idx, _, _)| {
+            let before: usize = self.subs[..file_idx].iter().map(|s| s.match_count()).sum();
+            (self.subs[file_idx].path.clone(), self.selected - before)
+        });
+        let sort = self.sort;
+        self.subs.sort_by(|a, b| a.compare(b, sort));
+        if let Some(all) = &mut self.unfiltered {
+            all.sort_by(|a, b| a.compare(b, sort));
+        }
+        info!("Sorted by {sort}");
+
+        let Some((path, offset)) = selected else {
+            return;
+        };
+        let before: usize = self
+            .subs
+            .iter()
+            .take_while(|s| s.path != path)
+            .map(|s| s.match_count())
+            .sum();
+        self.select(before + offset);
+    }
+
+    // Scroll horizontally if the first match of a new search would be off-screen
+    fn scroll_to_first_match(&mut self, sub: &FileSubstitution) {
+        let tab_width = self.config.tab_width;
+        let Some(cols) = sub
+            .subs
+            .iter()
+            .find_map(|s| s.matches.first().map(|m| s.columns(m, tab_width)))
+        else {
+            return;
+        };
+        if self.results_width > 0 && cols.end > self.results_width {
+            self.hscroll = cols.start.saturating_sub(HSCROLL_STEP);
+            info!("Scrolled horizontally to: {}", self.hscroll);
+        }
+    }
+
+    // Search for the edited pattern once typing pauses
+    fn pattern_changed(&mut self) {
+        let debounce = Duration::from_millis(self.config.debounce);
+        // clearing the results is instant, so there's no need to wait
+        if debounce.is_zero() || self.pattern_input.pattern().is_empty() {
+            return self.update_pattern();
+        }
+        // dropping the receiver makes the stale search quit on its next send
+        self.search_rx = None;
+        self.search_timer = Some(after(debounce));
+    }
+
+    // Search for the pattern now if typing hasn't paused yet
+    fn flush_pattern(&mut self) {
+        if self.search_timer.is_some() {
+            self.update_pattern();
+        }
+    }
+
+    fn update_pattern(&mut self) {
+        self.search_timer = None;
+        let pattern = self.pattern_input.pattern();
+        (self.finder, self.error) = if pattern.is_empty() {
+            // an empty regex matches everywhere, so wait for a pattern instead
+            (None, None)
+        } else {
+            match Finder::with_filetypes(pattern, &self.regex_params, &self.filetypes) {
+                Ok(finder) => (Some(finder), None),
+                Err(err) => {
+                    debug!("Invalid pattern {pattern}: {err}");
+                    (None, Some(err.root_cause().to_string()))
+                }
+            }
+        };
+        info!("New pattern: {pattern}");
+        let params = &self.regex_params;
+        if self.finder.as_ref().is_some_and(Finder::is_ast)
+            && (params.ignore_case || params.whole_word)
+        {
+            self.set_status("Ignore case and whole word don't apply to ast-grep patterns");
+        }
+        self.start_search();
+        self.subs.clear();
+        self.unfiltered = self.filter().map(|_| vec![]);
+        self.match_count = 0;
+        self.selected = 0;
+        self.scroll = 0;
+        self.hscroll = 0;
+        self.rejected.clear();
+    }
+
+    fn update_replacement(&mut self) {
+        let replacement = self.replacement_input.pattern();
+        let Some(finder) = &self.finder else { return };
+        for sub in self
+            .subs
+            .iter_mut()
+            .chain(self.unfiltered.iter_mut().flatten())
+        {
+            sub.update_replacement(finder, replacement);
+        }
+    }
+
+    // The text results are being narrowed to, if any
+    fn filter(&self) -> Option<&str> {
+        Some(self.filter_input.pattern()).filter(|f| !f.is_empty())
+    }
+
+    // Narrow the results to those containing the filter, without searching again
+    fn apply_filter(&mut self) {
+        let all = self
+            .unfiltered
+            .take()
+            .unwrap_or_else(|| std::mem::take(&mut self.subs));
+        self.subs = match self.filter() {
+            Some(filter) => {
+                let subs = all.iter().filter_map(|s| s.filtered(filter)).collect();
+                self.unfiltered = Some(all);
+                subs
+            }
+            None => all,
+        };
+        info!("Filtered results: {}", self.filter_input.pattern());
+        self.match_count = self.subs.iter().map(|s| s.match_count()).sum();
+        self.scroll = 0;
+        self.select(0);
+    }
+
+    // The path and matched text of the selected match
+    fn selected_match(&self) -> Option<(&Path, &str)> {
+        let (file_idx, line_idx, match_idx) = self.locate(self.selected)?;
+        let file = &self.subs[file_idx];
+        let line = &file.subs[line_idx];
+        Some((
+            &file.path,
+            &line.text[line.matches[match_idx].range.clone()],
+        ))
+    }
+
+    // The clipboard is kept open, as on some platforms copied text is lost when it is closed
+    fn clipboard(&mut self) -> Result<&mut arboard::Clipboard, arboard::Error> {
+        if self.clipboard.is_none() {
+            self.clipboard = Some(arboard::Clipboard::new()?);
+        }
+        Ok(self.clipboard.as_mut().unwrap())
+    }
+
+    fn copy(&mut self, text: String) {
+        match self.clipboard().and_then(|c| c.set_text(text.as_str())) {
+            Ok(()) => self.set_status(format!("Copied {text}")),
+            Err(err) => warn!("Failed to write clipboard: {err}"),
+        }
+    }
+
+    // Insert text into the focused input
+    fn paste(&mut self, text: &str) {
+        if self.filtering {
+            if self.filter_input.insert(text, false).is_some() {
+                self.apply_filter();
+            }
+        } else if self.editing_pattern {
+            if self.pattern_input.insert(text, false).is_some() {
+                self.update_pattern();
+            }
+        } else if self.replacement_input.insert(text, true).is_some() {
+            self.update_replacement();
+        }
+    }
+
+    /// updates the application's state based on user input
+    fn handle_events(&mut self, need_more: bool) -> Result<State> {
+        trace!("Awaiting event");
+
+        let search_rx = match self.search_rx {
+            Some(ref rx) if need_more => rx,
+            _ => &never(),
+        };
+        let ticker = match self.search_rx {
+            Some(_) => &self.ticker,
+            None => &never(),
+        };
+        let key_seq_timeout = self.key_seq_timeout.clone();
+        let search_timer = self.search_timer.clone().unwrap_or_else(never);
+        let status_timeout = match &self.status {
+            Some((_, at)) => after(STATUS_TIMEOUT.saturating_sub(at.elapsed())),
+            None => never(),
+        };
+        let config_rx = self.config_rx.clone();
+
+        // Bias for events, as they may invalidate search results
+        select_biased! {
+            recv(self.event_rx) -> ev => {
+                debug!("Handling terminal event: {ev:?}");
+                match ev? {
+                    Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
                         return self.handle_key_event(key_event);
                     }
+                    Event::Mouse(mouse_event) => self.handle_mouse_event(mouse_event),
+                    Event::Paste(text) if !self.confirming => self.paste(&text),
                     _ => {}
                 };
             }
+            recv(search_timer) -> _ => {
+                debug!("Typing paused");
+                self.update_pattern();
+            }
             recv(search_rx) -> sub => {
                 match sub {
                     Ok(sub) => self.on_finding(sub)?,
@@ -567,17 +2420,129 @@ impl App {
                     }
                 }
             }
+            recv(ticker) -> _ => {
+                self.spinner += 1;
+            }
+            recv(config_rx) -> _ => {
+                self.reload_config();
+            }
+            recv(key_seq_timeout) -> _ => {
+                debug!("Timed out waiting for key sequence");
+                return self.flush_keys();
+            }
+            recv(status_timeout) -> _ => {
+                self.status = None;
+            }
         }
         Ok(State::Continue)
     }
 
+    fn handle_mouse_event(&mut self, mouse_event: MouseEvent) {
+        if self.confirming || self.report.is_some() {
+            return;
+        }
+        match mouse_event.kind {
+            MouseEventKind::ScrollDown => {
+                if self.scroll + 1 < self.subs.len() {
+                    self.scroll += 1;
+                    info!("Scrolled to: {}", self.scroll);
+                }
+            }
+            MouseEventKind::ScrollUp => {
+                self.scroll = self.scroll.saturating_sub(1);
+                info!("Scrolled to: {}", self.scroll);
+            }
+            MouseEventKind::Down(MouseButton::Left) => {
+                let row = mouse_event.row;
+                if let Some(&(_, n)) = self.click_targets.iter().find(|(r, _)| r.contains(&row)) {
+                    self.select(n);
+                }
+            }
+            _ => {}
+        }
+    }
+
     fn handle_key_event(&mut self, key_event: KeyEvent) -> Result<State> {
-        if let Some(action) = self.config.keys.get(&key_event.into()) {
+        if self.report.is_some() {
+            return Ok(State::Exit);
+        }
+        self.status = None;
+        if self.confirming {
+            if self.confirm_typed() {
+                match key_event.code {
+                    KeyCode::Char(c) => {
+                        self.confirm_input.push(c);
+                        return Ok(State::Continue);
+                    }
+                    KeyCode::Backspace => {
+                        self.confirm_input.pop();
+                        return Ok(State::Continue);
+                    }
+                    _ => {}
+                }
+            }
+            // any key but y, or enter after typing yes, cancels
+            self.confirming = false;
+            let typed = std::mem::take(&mut self.confirm_input);
+            match key_event.code {
+                KeyCode::Char('y' | 'Y') => return Ok(State::Confirm),
+                KeyCode::Enter if typed.eq_ignore_ascii_case("yes") => return Ok(State::Confirm),
+                _ => {}
+            }
+            info!("Replacement cancelled");
+            return Ok(State::Continue);
+        }
+
+        self.pending_keys.push(key_event);
+        let keys = KeySeq::from(self.pending_keys.as_slice());
+        if self
+            .config
+            .keys
+            .keys()
+            .any(|k| k.len() > keys.len() && k.starts_with(&keys))
+        {
+            debug!("Awaiting the rest of key sequence: {keys}");
+            self.key_seq_timeout = after(KEY_SEQ_TIMEOUT);
+            return Ok(State::Continue);
+        }
+        self.flush_keys()
+    }
+
+    // Handle pending keys as a multi-key binding if they form one, otherwise one at a time
+    fn flush_keys(&mut self) -> Result<State> {
+        self.key_seq_timeout = never();
+        let keys = std::mem::take(&mut self.pending_keys);
+        if let [.., last] = keys[..]
+            && keys.len() > 1
+            && let Some(&action) = self.config.keys.get(&KeySeq::from(keys.as_slice()))
+        {
+            return self.handle_key(last, Some(action));
+        }
+        for key_event in keys {
+            let action = self.config.keys.get(&key_event.into()).copied();
+            match self.handle_key(key_event, action)? {
+                State::Continue => {}
+                state => return Ok(state),
+            }
+        }
+        Ok(State::Continue)
+    }
+
+    fn handle_key(&mut self, key_event: KeyEvent, action: Option<Action>) -> Result<State> {
+        if let Some(action) = action {
             match action {
                 Action::Exit => {
                     debug!("Exit requested");
                     return Ok(State::Exit);
                 }
+                Action::ToggleSearchReplace if self.filtering => {
+                    self.filtering = false;
+                    return Ok(State::Continue);
+                }
+                Action::ToggleSearchReplace if self.regex_params.invert_match => {
+                    self.set_status(INVERT_NO_REPLACE);
+                    return Ok(State::Continue);
+                }
                 Action::ToggleSearchReplace => {
                     self.editing_pattern = !self.editing_pattern;
                     info!(
@@ -586,8 +2551,37 @@ impl App {
                     );
                     return Ok(State::Continue);
                 }
+                Action::Confirm | Action::ReplaceFile if self.regex_params.invert_match => {
+                    self.set_status(INVERT_NO_REPLACE);
+                    return Ok(State::Continue);
+                }
                 Action::Confirm => {
-                    return Ok(State::Confirm);
+                    // replace the results of the pattern as typed
+                    self.flush_pattern();
+                    self.confirming = true;
+                    return Ok(State::Continue);
+                }
+                Action::ReplaceFile => {
+                    self.replace_selected_file()?;
+                    return Ok(State::Continue);
+                }
+                Action::CancelSearch => {
+                    // dropping the receiver makes the search threads quit on their next send
+                    if self.search_rx.take().is_some() {
+                        info!("Search cancelled");
+                        self.set_status("Search cancelled");
+                    }
+                    return Ok(State::Continue);
+                }
+                Action::SkipFile => {
+                    match self.locate(self.selected) {
+                        Some((file_idx, _, _)) => {
+                            let sub = self.remove_file(file_idx);
+                            self.set_status(format!("Skipped {}", sub.path.display()));
+                        }
+                        None => debug!("No match selected"),
+                    }
+                    return Ok(State::Continue);
                 }
                 Action::ToggleIgnoreCase => {
                     self.regex_params.ignore_case = !self.regex_params.ignore_case;
@@ -599,8 +2593,41 @@ impl App {
                     self.update_pattern();
                     return Ok(State::Continue);
                 }
+                Action::ToggleWholeWord => {
+                    self.regex_params.whole_word = !self.regex_params.whole_word;
+                    self.update_pattern();
+                    return Ok(State::Continue);
+                }
+                Action::ToggleFixedString => {
+                    self.regex_params.fixed_string = !self.regex_params.fixed_string;
+                    self.update_pattern();
+                    return Ok(State::Continue);
+                }
+                Action::ToggleInvertMatch => {
+                    self.regex_params.invert_match = !self.regex_params.invert_match;
+                    // the replacement is disabled while inverted
+                    self.editing_pattern |= self.regex_params.invert_match;
+                    self.update_pattern();
+                    return Ok(State::Continue);
+                }
+                Action::ToggleSearchMode => {
+                    self.regex_params.mode = self.regex_params.mode.next();
+                    self.set_status(format!("Search mode: {}", self.regex_params.mode));
+                    self.update_pattern();
+                    return Ok(State::Continue);
+                }
+                Action::ToggleRespectIgnore => {
+                    self.search_params.no_ignore = !self.search_params.no_ignore;
+                    self.update_pattern();
+                    return Ok(State::Continue);
+                }
+                Action::ToggleHidden => {
+                    self.search_params.hidden = !self.search_params.hidden;
+                    self.update_pattern();
+                    return Ok(State::Continue);
+                }
                 Action::ScrollDown => {
-                    if self.scroll < self.subs.len() - 1 {
+                    if self.scroll + 1 < self.subs.len() {
                         self.scroll += 1;
                         info!("Scrolled to: {}", self.scroll);
                     }
@@ -616,24 +2643,117 @@ impl App {
                     info!("Scrolled to: {}", self.scroll);
                     return Ok(State::Continue);
                 }
+                Action::ScrollBottom => {
+                    self.scroll = self.subs.len().saturating_sub(1);
+                    info!("Scrolled to: {}", self.scroll);
+                    return Ok(State::Continue);
+                }
+                Action::PageDown => {
+                    let last = self.subs.len().saturating_sub(1);
+                    self.scroll = (self.scroll + self.visible_files.max(1)).min(last);
+                    info!("Scrolled to: {}", self.scroll);
+                    return Ok(State::Continue);
+                }
+                Action::PageUp => {
+                    self.scroll = self.scroll.saturating_sub(self.visible_files.max(1));
+                    info!("Scrolled to: {}", self.scroll);
+                    return Ok(State::Continue);
+                }
+                Action::CycleSort => {
+                    self.sort = self.sort.next();
+                    self.sort_results();
+                    self.set_status(format!("Sorted by {}", self.sort));
+                    return Ok(State::Continue);
+                }
+                Action::FilterResults => {
+                    self.filtering = !self.filtering;
+                    info!("Toggled filtering: {}", self.filtering);
+                    return Ok(State::Continue);
+                }
+                Action::ToggleWrap => {
+                    self.wrap = !self.wrap;
+                    info!("Toggled wrap: {}", self.wrap);
+                    return Ok(State::Continue);
+                }
+                Action::ToggleDiffView => {
+                    self.diff_view = !self.diff_view;
+                    info!("Toggled diff view: {}", self.diff_view);
+                    return Ok(State::Continue);
+                }
+                Action::ScrollLeft => {
+                    self.hscroll = self.hscroll.saturating_sub(HSCROLL_STEP);
+                    info!("Scrolled horizontally to: {}", self.hscroll);
+                    return Ok(State::Continue);
+                }
+                Action::ScrollRight => {
+                    self.hscroll += HSCROLL_STEP;
+                    info!("Scrolled horizontally to: {}", self.hscroll);
+                    return Ok(State::Continue);
+                }
+                Action::NextMatch => {
+                    self.select(self.selected + 1);
+                    return Ok(State::Continue);
+                }
+                Action::PrevMatch => {
+                    self.select(self.selected.saturating_sub(1));
+                    return Ok(State::Continue);
+                }
+                Action::ToggleMatch => {
+                    self.toggle_selected();
+                    return Ok(State::Continue);
+                }
+                Action::OpenInEditor => {
+                    let Some((file_idx, line_idx, _)) = self.locate(self.selected) else {
+                        debug!("No match selected");
+                        return Ok(State::Continue);
+                    };
+                    let file = &self.subs[file_idx];
+                    if self.is_stdin(&file.path) {
+                        self.set_status("Can't open stdin in an editor");
+                        return Ok(State::Continue);
+                    }
+                    return Ok(State::Edit(
+                        file.path.clone(),
+                        file.subs[line_idx].start_line,
+                    ));
+                }
+                Action::YankPath => {
+                    if let Some((path, _)) = self.selected_match() {
+                        self.copy(path.to_string_lossy().into_owned());
+                    }
+                    return Ok(State::Continue);
+                }
+                Action::YankMatch => {
+                    if let Some((_, text)) = self.selected_match() {
+                        self.copy(text.to_string());
+                    }
+                    return Ok(State::Continue);
+                }
+                Action::Paste => {
+                    match self.clipboard().and_then(|c| c.get_text()) {
+                        Ok(text) => self.paste(&text),
+                        Err(err) => warn!("Failed to read clipboard: {err}"),
+                    }
+                    return Ok(State::Continue);
+                }
                 _ => {}
             }
         }
 
-        if self.editing_pattern {
-            let Some(_) = self
-                .pattern_input
-                .handle_key_event(key_event, &self.config.keys)
-            else {
+        if self.filtering {
+            let Some(_) = self.filter_input.handle_key(key_event, action) else {
+                debug!("Filter unchanged");
+                return Ok(State::Continue);
+            };
+            self.apply_filter();
+        } else if self.editing_pattern {
+            let Some(_) = self.pattern_input.handle_key(key_event, action) else {
                 debug!("Pattern unchanged");
                 return Ok(State::Continue);
             };
-            self.update_pattern();
+            self.pattern_changed();
         } else {
-            let Some(_) = self
-                .replacement_input
-                .handle_key_event(key_event, &self.config.keys)
-            else {
+            let Some(_) = self.replacement_input.handle_key(key_event, action) else {
                 debug!("Replacement unchanged");
                 return Ok(State::Continue);
             };
@@ -647,13 +2767,20 @@ impl App {
 
 #[cfg(test)]
 mod tests {
-    use std::{fmt::Display, path::Path};
+    use std::{
+        fmt::Display,
+        path::Path,
+        time::{Duration, Instant, SystemTime},
+    };
 
-    use crate::config::Config;
+    use crate::config::{Action, Backup, Config, Sort};
+    use crate::finder::{RegexParams, STDIN_PATH, SearchParams};
 
-    use super::App;
-    use crossbeam::channel::{Sender, bounded};
-    use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+    use super::{App, STATUS_TIMEOUT, State};
+    use crossbeam::channel::{Sender, bounded, never, tick};
+    use crossterm::event::{
+        Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    };
     use insta::assert_snapshot;
     use pretty_assertions::assert_eq;
     use ratatui::{Terminal, backend::TestBackend};
@@ -669,24 +2796,33 @@ mod tests {
         }
 
         fn with_dir(path: &Path) -> Self {
+            Self::with_config(path, Config::default())
+        }
+
+        fn with_config(path: &Path, config: Config) -> Self {
             let (event_tx, event_rx) = bounded(1);
-            Test {
+            let mut test = Test {
                 app: App::new(
-                    vec![path.into()],
-                    ignore::types::TypesBuilder::new()
-                        .add_defaults()
-                        .build()
-                        .unwrap(),
-                    Config {
+                    SearchParams {
+                        paths: vec![path.into()],
+                        types: ignore::types::TypesBuilder::new()
+                            .add_defaults()
+                            .build()
+                            .unwrap(),
                         threads: 1,
                         ..Default::default()
                     },
+                    RegexParams::default(),
+                    config,
                     event_rx,
-                    false,
-                    false,
                 ),
                 event_tx,
-            }
+            };
+            // spinner ticks would make event handling order unpredictable
+            test.app.ticker = never();
+            // search on every key, so tests needn't wait for typing to pause
+            test.app.config.debounce = 0;
+            test
         }
 
         fn input(&mut self, s: &str) {
@@ -735,6 +2871,82 @@ mod tests {
         assert_snapshot!(terminal.backend());
     }
 
+    #[test]
+    fn test_empty_pattern() {
+        let mut test = Test::new();
+        test.app.config.debounce = 1000;
+        test.input("line");
+        test.app.flush_pattern();
+        test.app.handle_events(true).unwrap();
+        assert!(!test.app.subs.is_empty());
+
+        // cleared right away, without waiting for typing to pause
+        for _ in 0..4 {
+            test.app
+                .handle_key_event(KeyCode::Backspace.into())
+                .unwrap();
+        }
+        assert!(test.app.subs.is_empty());
+        assert_eq!(test.app.match_count, 0);
+        assert!(test.app.finder.is_none());
+        assert!(test.app.error.is_none());
+        assert!(test.app.search_rx.is_none());
+        assert!(test.app.search_timer.is_none());
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_column() {
+        let mut test = Test::with_config(
+            Path::new("testdata"),
+            Config {
+                column: true,
+                ..Default::default()
+            },
+        );
+        test.input("(one|two|Line)");
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+
+        let mut terminal = Terminal::new(TestBackend::new(40, 12)).unwrap();
+        terminal
+            .draw(|frame| _ = test.app.draw(frame).unwrap())
+            .unwrap();
+        assert_snapshot!(terminal.backend());
+    }
+
+    #[test]
+    fn test_search_tabs() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("Makefile"), "all:\n\tgo build\n\t\tbuild\n").unwrap();
+        let mut test = Test::with_config(
+            tmp.path(),
+            Config {
+                column: true,
+                tab_width: 4,
+                ..Default::default()
+            },
+        );
+        test.input("build");
+        test.app.handle_events(true).unwrap();
+
+        let mut terminal = Terminal::new(TestBackend::new(40, 10)).unwrap();
+        terminal
+            .draw(|frame| _ = test.app.draw(frame).unwrap())
+            .unwrap();
+        assert_snapshot!(scrub_tmp(&tmp, terminal.backend()));
+
+        // the highlight covers the unselected match, not the columns it would have without tabs
+        let buffer = terminal.backend().buffer();
+        let find = test.app.config.theme.find;
+        let styled: String = (0..40)
+            .map(|x| &buffer[(x, 6)])
+            .filter(|c| c.style().add_modifier == find.add_modifier && c.fg == find.fg.unwrap())
+            .map(|c| c.symbol())
+            .collect();
+        assert_eq!(styled, "build");
+    }
+
     #[test]
     #[tracing_test::traced_test]
     fn test_search() {
@@ -756,148 +2968,1502 @@ mod tests {
 
     #[test]
     #[tracing_test::traced_test]
-    fn test_search_ignore_case() {
-        let mut test = Test::new();
-        test.input("the");
-
+    fn test_hscroll() {
+        let tmp = tempfile::tempdir().unwrap();
+        let text = format!("{}foo bar\n", "x".repeat(40));
+        std::fs::write(tmp.path().join("long.txt"), text).unwrap();
+        let mut test = Test::with_dir(tmp.path());
+        let mut terminal = Terminal::new(TestBackend::new(40, 9)).unwrap();
+        let mut draw = |app: &mut App| {
+            terminal.draw(|frame| _ = app.draw(frame).unwrap()).unwrap();
+            scrub_tmp(&tmp, terminal.backend())
+        };
+        draw(&mut test.app);
+
+        // the first match is off-screen, so scroll to it
+        test.input("bar");
+        test.app.handle_events(true).unwrap();
+        assert_eq!(test.app.hscroll, 36);
+        assert_snapshot!(draw(&mut test.app));
+
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Left, KeyModifiers::ALT))
+            .unwrap();
+        assert_eq!(test.app.hscroll, 28);
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Right, KeyModifiers::ALT))
+            .unwrap();
+        assert_eq!(test.app.hscroll, 36);
+
+        // a new pattern resets the scroll
+        test.input("|x");
+        assert_eq!(test.app.hscroll, 0);
+        test.app.handle_events(true).unwrap();
+        assert_eq!(test.app.hscroll, 0);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_wrap() {
+        let tmp = tempfile::tempdir().unwrap();
+        let text = format!("{}foo bar\nshort bar\n", "x".repeat(40));
+        std::fs::write(tmp.path().join("long.txt"), text).unwrap();
+        let mut test = Test::with_config(
+            tmp.path(),
+            Config {
+                wrap: true,
+                ..Default::default()
+            },
+        );
+        test.input("bar");
+        test.app.handle_events(true).unwrap();
+
+        let mut terminal = Terminal::new(TestBackend::new(40, 12)).unwrap();
+        terminal
+            .draw(|frame| _ = test.app.draw(frame).unwrap())
+            .unwrap();
+        assert_snapshot!(scrub_tmp(&tmp, terminal.backend()));
+
+        // toggle wrap off with alt-z
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('z'), KeyModifiers::ALT))
+            .unwrap();
+        assert!(!test.app.wrap);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_open_in_editor() {
+        let mut test = Test::new();
+        let open = |test: &mut Test| {
+            test.app
+                .handle_key_event(KeyEvent::new(KeyCode::Char('o'), KeyModifiers::CONTROL))
+                .unwrap()
+        };
+        assert_eq!(open(&mut test), State::Continue, "nothing selected");
+
+        test.input("line");
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+        assert_eq!(
+            open(&mut test),
+            State::Edit("testdata/dir1/file2.txt".into(), 1)
+        );
+
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('n'), KeyModifiers::ALT))
+            .unwrap();
+        assert_eq!(
+            open(&mut test),
+            State::Edit("testdata/dir1/file2.txt".into(), 2)
+        );
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_confirm() {
+        let mut test = Test::new();
+        test.input("line");
+        // a search in progress would need typed confirmation
+        while test.app.search_rx.is_some() {
+            test.app.handle_events(true).unwrap();
+        }
+
+        // reject the first match, it should not be counted
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('r'), KeyModifiers::ALT))
+            .unwrap();
+
+        let mut key = |code: KeyCode| test.app.handle_key_event(code.into()).unwrap();
+        assert_eq!(key(KeyCode::Enter), State::Continue);
+        assert_eq!(key(KeyCode::Esc), State::Continue, "cancelled");
+        assert_eq!(key(KeyCode::Enter), State::Continue);
+        assert_eq!(key(KeyCode::Char('n')), State::Continue, "cancelled");
+        assert_eq!(key(KeyCode::Enter), State::Continue);
+
+        let mut terminal = Terminal::new(TestBackend::new(50, 20)).unwrap();
+        terminal
+            .draw(|frame| {
+                test.app.draw(frame).unwrap();
+            })
+            .unwrap();
+        assert_snapshot!(terminal.backend());
+
+        assert_eq!(
+            test.app
+                .handle_key_event(KeyCode::Char('y').into())
+                .unwrap(),
+            State::Confirm
+        );
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_confirm_typed() {
+        let mut test = Test::with_config(
+            Path::new("testdata"),
+            Config {
+                confirm_threshold: 1,
+                ..Default::default()
+            },
+        );
+        test.input("line");
+        while test.app.search_rx.is_some() {
+            test.app.handle_events(true).unwrap();
+        }
+
+        let mut key = |code: KeyCode| test.app.handle_key_event(code.into()).unwrap();
+        assert_eq!(key(KeyCode::Enter), State::Continue);
+        assert_eq!(key(KeyCode::Char('y')), State::Continue, "y is not enough");
+        assert_eq!(key(KeyCode::Enter), State::Continue, "cancelled");
+        assert!(!test.app.confirming);
+        assert_eq!(test.app.pattern_input.pattern(), "line");
+
+        let mut key = |code: KeyCode| test.app.handle_key_event(code.into()).unwrap();
+        assert_eq!(key(KeyCode::Enter), State::Continue);
+        for c in "yex".chars() {
+            assert_eq!(key(KeyCode::Char(c)), State::Continue);
+        }
+        assert_eq!(key(KeyCode::Backspace), State::Continue);
+        assert_eq!(key(KeyCode::Char('s')), State::Continue);
+
+        let mut terminal = Terminal::new(TestBackend::new(50, 12)).unwrap();
+        terminal
+            .draw(|frame| _ = test.app.draw(frame).unwrap())
+            .unwrap();
+        assert_snapshot!(terminal.backend());
+
+        let state = test.app.handle_key_event(KeyCode::Enter.into());
+        assert_eq!(state.unwrap(), State::Confirm);
+        assert!(test.app.confirm_input.is_empty());
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_confirm_pending() {
+        let mut test = Test::new();
+        test.input("line");
+        test.app.handle_events(true).unwrap();
+        assert!(test.app.search_rx.is_some());
+        test.app.handle_key_event(KeyCode::Enter.into()).unwrap();
+
+        let mut terminal = Terminal::new(TestBackend::new(60, 12)).unwrap();
+        terminal
+            .draw(|frame| _ = test.app.draw(frame).unwrap())
+            .unwrap();
+        assert_snapshot!(terminal.backend());
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_select_scroll() {
+        let mut test = Test::new();
+        test.input("line");
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+
+        // only room to show one file at a time
+        let mut terminal = Terminal::new(TestBackend::new(40, 10)).unwrap();
+        let mut draw = |app: &mut App| {
+            terminal.draw(|frame| _ = app.draw(frame).unwrap()).unwrap();
+        };
+        let next = KeyEvent::new(KeyCode::Char('n'), KeyModifiers::ALT);
+        let prev = KeyEvent::new(KeyCode::Char('p'), KeyModifiers::ALT);
+
+        draw(&mut test.app);
+        for _ in 0..2 {
+            test.app.handle_key_event(next).unwrap();
+            draw(&mut test.app);
+            assert_eq!(test.app.scroll, 0);
+        }
+        test.app.handle_key_event(next).unwrap();
+        draw(&mut test.app);
+        assert_eq!(test.app.scroll, 1, "selection moved to the next file");
+        assert_snapshot!(terminal.backend());
+
+        test.app.handle_key_event(prev).unwrap();
+        assert_eq!(test.app.scroll, 0);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_scroll_empty() {
+        let mut test = Test::new();
+        for key in [
+            KeyEvent::new(KeyCode::Char('n'), KeyModifiers::CONTROL),
+            KeyCode::PageDown.into(),
+            KeyEvent::new(KeyCode::Char('g'), KeyModifiers::ALT),
+        ] {
+            test.app.handle_key_event(key).unwrap();
+            assert_eq!(test.app.scroll, 0);
+        }
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_scroll_new_pattern() {
+        let mut test = Test::new();
+        test.input("line");
+        while test.app.search_rx.is_some() {
+            test.app.handle_events(true).unwrap();
+        }
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('n'), KeyModifiers::CONTROL))
+            .unwrap();
+        assert_eq!(test.app.scroll, 1);
+
+        // the old results are gone, so the scroll must not outlive them
+        test.input("s");
+        assert_eq!(test.app.scroll, 0);
+        let mut terminal = Terminal::new(TestBackend::new(80, 20)).unwrap();
+        terminal
+            .draw(|frame| _ = test.app.draw(frame).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_page_scroll() {
+        let tmp = tempfile::tempdir().unwrap();
+        for i in 0..7 {
+            std::fs::write(tmp.path().join(format!("{i}.txt")), "foo\n").unwrap();
+        }
+        let mut test = Test::with_dir(tmp.path());
+        test.input("foo");
+        while test.app.search_rx.is_some() {
+            test.app.handle_events(true).unwrap();
+        }
+        assert_eq!(test.app.subs.len(), 7);
+
+        // room to show two files at a time
+        let mut terminal = Terminal::new(TestBackend::new(40, 13)).unwrap();
+        let mut press = |app: &mut App, key: KeyEvent| {
+            terminal.draw(|frame| _ = app.draw(frame).unwrap()).unwrap();
+            app.handle_key_event(key).unwrap();
+            app.scroll
+        };
+        let page_down = KeyCode::PageDown.into();
+        let page_up = KeyCode::PageUp.into();
+        assert_eq!(press(&mut test.app, page_down), 2);
+        assert_eq!(press(&mut test.app, page_down), 4);
+        assert_eq!(press(&mut test.app, page_down), 6);
+        assert_eq!(
+            press(&mut test.app, page_down),
+            6,
+            "clamped to the last file"
+        );
+        // only the last file is visible, so the page is one file
+        assert_eq!(press(&mut test.app, page_up), 5);
+        assert_eq!(press(&mut test.app, page_up), 3);
+        assert_eq!(press(&mut test.app, page_up), 1);
+        assert_eq!(press(&mut test.app, page_up), 0);
+
+        let bottom = KeyEvent::new(KeyCode::Char('g'), KeyModifiers::ALT);
+        assert_eq!(press(&mut test.app, bottom), 6);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_yank() {
+        let mut test = Test::new();
+        assert_eq!(test.app.selected_match(), None);
+
+        test.input("l\\w+");
+        test.app.handle_events(true).unwrap();
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('n'), KeyModifiers::ALT))
+            .unwrap();
+        assert_eq!(
+            test.app.selected_match(),
+            Some((Path::new("testdata/dir1/file2.txt"), "line"))
+        );
+
+        // there is no clipboard in tests, so this should only log
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('y'), KeyModifiers::ALT))
+            .unwrap();
+        assert!(test.app.status().is_none());
+
+        test.app.set_status("Copied testdata/dir1/file2.txt");
+        let mut terminal = Terminal::new(TestBackend::new(40, 12)).unwrap();
+        terminal
+            .draw(|frame| _ = test.app.draw(frame).unwrap())
+            .unwrap();
+        assert_snapshot!(terminal.backend());
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_spinner() {
+        let mut test = Test::new();
+        test.app.ticker = tick(Duration::from_millis(1));
+        test.input("line");
+        assert!(test.app.status_summary().starts_with("regex │"));
+
+        // not requesting more results, so only the ticker can wake us
+        test.app.handle_events(false).unwrap();
+        assert_eq!(test.app.status_summary(), "regex │ 0 matches in 0 files ⠙");
+
+        // the spinner stops once the search completes
+        while test.app.search_rx.is_some() {
+            test.app.handle_events(true).unwrap();
+        }
+        assert_eq!(test.app.status_summary(), "regex │ 1/6 matches in 2 files");
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_invalid_pattern() {
+        let mut test = Test::new();
+        test.input("line)");
+        assert!(test.app.finder.is_none());
+        assert!(test.app.error.as_ref().unwrap().contains("unopened group"));
+
+        let mut terminal = Terminal::new(TestBackend::new(40, 12)).unwrap();
+        terminal
+            .draw(|frame| _ = test.app.draw(frame).unwrap())
+            .unwrap();
+        assert_snapshot!(terminal.backend());
+
+        test.app
+            .handle_key_event(KeyCode::Backspace.into())
+            .unwrap();
+        assert!(test.app.finder.is_some());
+        assert_eq!(test.app.error, None);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_status_summary() {
+        let mut test = Test::new();
+        assert_eq!(test.app.search_header(), "Search");
+        assert_eq!(test.app.status_summary(), "auto");
+
+        test.input("line");
+        assert!(test.app.status_summary().starts_with("regex │"));
+
+        test.app.handle_events(true).unwrap();
+        assert_eq!(test.app.status_summary(), "regex │ 1/3 matches in 1 file ⠋");
+
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+        assert_eq!(test.app.status_summary(), "regex │ 1/6 matches in 2 files");
+    }
+
+    #[test]
+    fn test_status_timeout() {
+        let mut test = Test::new();
+        test.app.set_status("Copied");
+        assert_eq!(test.app.status(), Some("Copied"));
+
+        test.app.status = Some(("Copied".into(), Instant::now() - STATUS_TIMEOUT));
+        assert_eq!(test.app.status(), None);
+        // nothing else is happening, so only the timeout can wake us
+        test.app.handle_events(false).unwrap();
+        assert!(test.app.status.is_none());
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_toggle_hidden() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join(".github")).unwrap();
+        std::fs::write(tmp.path().join(".github").join("ci.yml"), "foo\n").unwrap();
+        std::fs::write(tmp.path().join("visible.txt"), "foo\n").unwrap();
+
+        let mut test = Test::with_dir(tmp.path());
+        test.input("foo");
+        while test.app.search_rx.is_some() {
+            test.app.handle_events(true).unwrap();
+        }
+        assert_eq!(test.app.match_count, 1);
+
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('h'), KeyModifiers::ALT))
+            .unwrap();
+        while test.app.search_rx.is_some() {
+            test.app.handle_events(true).unwrap();
+        }
+        assert_eq!(test.app.match_count, 2);
+        assert!(test.app.status_summary().starts_with("regex (h)"));
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_whole_word() {
+        let mut test = Test::new();
+        test.input("line|th");
+
+        // Send alt-w to toggle whole word
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('w'), KeyModifiers::ALT))
+            .unwrap();
+        assert_eq!(
+            test.app.status_summary(),
+            "regex (w) │ 0 matches in 0 files ⠋"
+        );
+
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+        // "th" only matches within words, so only "line" is found
+        assert_eq!(
+            test.app.status_summary(),
+            "regex (w) │ 1/6 matches in 2 files"
+        );
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_ignore_case() {
+        let mut test = Test::new();
+        test.input("the");
+
         // Send ctrl-s to toggle case-insensitive
         test.app
             .handle_key_event(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL))
             .unwrap();
 
-        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
-        test.input("One");
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("One");
+
+        // await results from 2 files
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+
+        let mut terminal = Terminal::new(TestBackend::new(40, 20)).unwrap();
+        terminal
+            .draw(|frame| {
+                assert!(test.app.draw(frame).unwrap(), "Should need more results");
+            })
+            .unwrap();
+        assert_snapshot!(terminal.backend());
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_ast() {
+        let mut test = Test::new();
+        test.input("$FN($$$ARGS)");
+
+        // await results from 2 files
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+
+        let mut terminal = Terminal::new(TestBackend::new(40, 20)).unwrap();
+        terminal
+            .draw(|frame| {
+                assert!(test.app.draw(frame).unwrap(), "Should need more results");
+            })
+            .unwrap();
+        assert_snapshot!(terminal.backend());
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_ast_ignore_case() {
+        let mut test = Test::new();
+        test.input("thing");
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL))
+            .unwrap();
+        assert_eq!(test.app.status(), None);
+
+        test.input("($$$A)");
+        assert_eq!(
+            test.app.status(),
+            Some("Ignore case and whole word don't apply to ast-grep patterns")
+        );
+    }
+
+    #[test]
+    fn test_bracketed_paste() {
+        let mut test = Test::new();
+        // enter and tab would confirm or switch inputs if typed
+        test.event_tx.send(Event::Paste("a\tb\nc".into())).unwrap();
+        test.app.handle_events(false).unwrap();
+        assert_eq!(test.app.pattern_input.pattern(), "a\tb c");
+        assert!(test.app.editing_pattern);
+        assert!(!test.app.confirming);
+        assert!(test.app.search_rx.is_some(), "search started");
+
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        // the replacement keeps newlines
+        test.event_tx.send(Event::Paste("$1\n$2".into())).unwrap();
+        test.app.handle_events(false).unwrap();
+        assert_eq!(test.app.replacement_input.pattern(), "$1\n$2");
+    }
+
+    #[test]
+    fn test_resume() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut test = Test::new();
+        test.app.resume(tmp.path());
+        assert_eq!(
+            test.app.status(),
+            Some("Failed to resume: No such file or directory (os error 2)")
+        );
+
+        test.input("line (t");
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("x");
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL))
+            .unwrap();
+        test.app.save_session(tmp.path()).unwrap();
+
+        let mut test = Test::new();
+        test.app.resume(tmp.path());
+        assert_eq!(test.app.status(), None);
+        assert_eq!(test.app.pattern_input.pattern(), "line (t)");
+        assert_eq!(test.app.replacement_input.pattern(), "x");
+        assert!(test.app.regex_params.ignore_case);
+        assert!(!test.app.regex_params.multi_line);
+        assert_eq!(
+            test.app.search_params.paths,
+            [std::path::absolute("testdata").unwrap()]
+        );
+        assert!(test.app.search_rx.is_some(), "search started");
+    }
+
+    #[test]
+    fn test_search_stats() {
+        let mut test = Test::with_config(
+            Path::new("testdata"),
+            Config {
+                stats: true,
+                ..Default::default()
+            },
+        );
+        test.input("line");
+        while test.app.search_rx.is_some() {
+            test.app.handle_events(true).unwrap();
+        }
+        let status = test.app.stats_message().unwrap();
+        assert!(
+            status.starts_with("6 matches in 2 files, 9 files searched"),
+            "{status}"
+        );
+    }
+
+    #[test]
+    fn test_max_matches_per_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("many.txt"), "foo foo\nfoo\nfoo\n").unwrap();
+
+        let mut test = Test::with_dir(tmp.path());
+        test.app.regex_params.max_matches = Some(2);
+        test.input("foo");
+        test.app.handle_events(true).unwrap();
+        assert_eq!(test.app.match_count, 2);
+
+        let mut terminal = Terminal::new(TestBackend::new(60, 10)).unwrap();
+        terminal.draw(|frame| _ = test.app.draw(frame)).unwrap();
+        assert_snapshot!(scrub_tmp(&tmp, terminal.backend()));
+    }
+
+    #[test]
+    fn test_debounce() {
+        let mut test = Test::new();
+        test.app.config.debounce = 10;
+        test.input("This");
+        assert!(test.app.finder.is_none());
+        assert!(test.app.search_timer.is_some());
+
+        // typing paused
+        test.app.handle_events(false).unwrap();
+        assert!(test.app.search_timer.is_none());
+        assert!(test.app.finder.is_some());
+        while test.app.search_rx.is_some() {
+            test.app.handle_events(true).unwrap();
+        }
+        assert!(!test.app.subs.is_empty());
+
+        // typing cancels the search for the previous pattern, keeping its results until the next
+        test.input("d");
+        test.app.search_rx = Some(never());
+        test.input("e");
+        assert!(test.app.search_rx.is_none());
+        assert!(!test.app.subs.is_empty());
+
+        // confirming searches without waiting
+        test.app.handle_key_event(KeyCode::Enter.into()).unwrap();
+        assert!(test.app.search_timer.is_none());
+        assert!(test.app.search_rx.is_some());
+        assert!(test.app.subs.is_empty());
+        assert!(test.app.confirming);
+    }
+
+    #[test]
+    fn test_invert_match() {
+        let mut test = Test::new();
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        assert!(!test.app.editing_pattern);
+
+        let alt_v = KeyEvent::new(KeyCode::Char('v'), KeyModifiers::ALT);
+        test.app.handle_key_event(alt_v).unwrap();
+        assert!(test.app.editing_pattern, "focus moves to the pattern");
+        test.input("line");
+        assert!(test.app.status_summary().starts_with("regex (v) │"));
+        while test.app.search_rx.is_some() {
+            test.app.handle_events(true).unwrap();
+        }
+        assert!(!test.app.subs.is_empty());
+        assert!(
+            test.app
+                .subs
+                .iter()
+                .flat_map(|s| &s.subs)
+                .all(|s| s.matches.is_empty() && !s.text.contains("line"))
+        );
+
+        let msg = Some("Replacement is disabled while inverting matches");
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        assert_eq!(test.app.status(), msg);
+        assert!(test.app.editing_pattern);
+        test.app.handle_key_event(KeyCode::Enter.into()).unwrap();
+        assert_eq!(test.app.status(), msg);
+        assert!(!test.app.confirming);
+
+        test.app.handle_key_event(alt_v).unwrap();
+        assert!(test.app.status_summary().starts_with("regex │"));
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        assert!(!test.app.editing_pattern);
+    }
+
+    #[test]
+    fn test_toggle_search_mode() {
+        let mut test = Test::new();
+        test.input("$A");
+        assert!(test.app.finder.as_ref().unwrap().is_ast());
+        assert!(test.app.status_summary().starts_with("ast │"));
+
+        let alt_m = KeyEvent::new(KeyCode::Char('m'), KeyModifiers::ALT);
+        test.app.handle_key_event(alt_m).unwrap();
+        assert!(!test.app.finder.as_ref().unwrap().is_ast());
+        assert_eq!(test.app.status(), Some("Search mode: regex"));
+        assert_eq!(test.app.search_header(), "Search [regex]");
+        assert!(test.app.status_summary().starts_with("regex │"));
+
+        test.app.handle_key_event(alt_m).unwrap();
+        assert_eq!(test.app.status(), Some("Search mode: ast"));
+        assert!(test.app.finder.as_ref().unwrap().is_ast());
+
+        test.app.handle_key_event(alt_m).unwrap();
+        assert_eq!(test.app.status(), Some("Search mode: auto"));
+        assert_eq!(test.app.search_header(), "Search");
+        assert!(test.app.status_summary().starts_with("ast │"));
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_multiline() {
+        let mut test = Test::new();
+        test.input("\\w+\\n\\w+");
+
+        // Send ctrl-l to toggle multiline
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('l'), KeyModifiers::CONTROL))
+            .unwrap();
+
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("One");
+
+        // await results from 2 files
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+
+        let mut terminal = Terminal::new(TestBackend::new(40, 20)).unwrap();
+        terminal
+            .draw(|frame| {
+                assert!(test.app.draw(frame).unwrap(), "Should need more results");
+            })
+            .unwrap();
+        assert_snapshot!(terminal.backend());
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_results_full() {
+        let mut test = Test::new();
+        test.input("aaa");
+        // Use a smaller y size, so the results fill the page
+        let mut terminal = Terminal::new(TestBackend::new(40, 12)).unwrap();
+
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+
+        terminal
+            .draw(|frame| {
+                assert!(test.app.draw(frame).unwrap(), "Should need more results");
+            })
+            .unwrap();
+
+        test.app.handle_events(true).unwrap();
+
+        terminal
+            .draw(|frame| {
+                assert!(
+                    !test.app.draw(frame).unwrap(),
+                    "Should not need more results"
+                );
+            })
+            .unwrap();
+        assert_snapshot!(terminal.backend());
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_search_results_truncated() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "foo\nfoo\n").unwrap();
+        std::fs::write(tmp.path().join("b.txt"), "foo\nfoo\nfoo\nfoo\nfoo\n").unwrap();
+
+        let mut test = Test::with_dir(tmp.path());
+        test.input("foo");
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+
+        // the first file is shown in full, the second is cut off
+        let mut terminal = Terminal::new(TestBackend::new(40, 14)).unwrap();
+        terminal
+            .draw(|frame| {
+                test.app.draw(frame).unwrap();
+            })
+            .unwrap();
+        assert_snapshot!(scrub_tmp(&tmp, terminal.backend()));
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace_non_utf8() {
+        let tmp = tempfile::tempdir().unwrap();
+        // the matched line is valid UTF-8, but the rest of the file is latin-1
+        std::fs::write(tmp.path().join("a.txt"), b"foo\ncaf\xe9\n").unwrap();
+        std::fs::write(tmp.path().join("b.txt"), "foo bar\n").unwrap();
+
+        let mut test = Test::with_dir(tmp.path());
+        test.input("foo");
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("baz");
+        test.app.replace_all().unwrap();
+
+        assert_eq!(
+            std::fs::read(tmp.path().join("a.txt")).unwrap(),
+            b"foo\ncaf\xe9\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("b.txt")).unwrap(),
+            "baz bar\n"
+        );
+        assert!(logs_contain("not valid UTF-8"));
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace_crlf() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("crlf.txt");
+        std::fs::write(&path, "foo one\r\nbar\r\nfoo two\r\n").unwrap();
+
+        let mut test = Test::with_dir(tmp.path());
+        test.input("foo.*");
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("baz");
+        test.app.handle_events(true).unwrap();
+        assert_eq!(test.app.match_count, 2);
+
+        test.app.replace_all().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "baz\r\nbar\r\nbaz\r\n"
+        );
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace_zero_width() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("file.txt");
+        for (pattern, replacement, count, expected) in [
+            ("^", "> ", 2, "> foo bar\n> baz\n"),
+            ("$", ";", 2, "foo bar;\nbaz;\n"),
+            (r"\b", "|", 6, "|foo| |bar|\n|baz|\n"),
+        ] {
+            std::fs::write(&path, "foo bar\nbaz\n").unwrap();
+            let mut test = Test::with_dir(tmp.path());
+            test.input(pattern);
+            test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+            test.input(replacement);
+            test.app.handle_events(true).unwrap();
+            assert_eq!(test.app.match_count, count, "{pattern}");
+
+            test.app.replace_all().unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace() {
+        let tmp = stage_files();
+
+        let mut test = Test::with_dir(tmp.path());
+        test.input("line");
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("replacement");
+
+        // await results from 2 files
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+
+        let mut terminal = Terminal::new(TestBackend::new(40, 20)).unwrap();
+        terminal
+            .draw(|frame| {
+                assert!(test.app.draw(frame).unwrap(), "Should need more results");
+            })
+            .unwrap();
+        assert_snapshot!(scrub_tmp(&tmp, terminal.backend()));
+
+        test.app.replace_all().unwrap();
+
+        let content = std::fs::read_to_string(tmp.path().join("file1.txt")).unwrap();
+        assert_eq!(
+            content,
+            "\
+This is replacement one.
+This is replacement two.
+This is replacement three.
+Line four.
+"
+        );
+
+        let content = std::fs::read_to_string(tmp.path().join("dir1").join("file2.txt")).unwrap();
+        assert_eq!(
+            content,
+            "\
+The first replacement.
+The second replacement.
+The third replacement.
+"
+        );
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_diff_view() {
+        let mut test = Test::new();
+        test.input("line");
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("row");
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('d'), KeyModifiers::ALT))
+            .unwrap();
+
+        let mut terminal = Terminal::new(TestBackend::new(50, 11)).unwrap();
+        terminal
+            .draw(|frame| _ = test.app.draw(frame).unwrap())
+            .unwrap();
+        assert_snapshot!(terminal.backend());
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace_stdin() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("a.txt");
+        std::fs::write(&path, "foo\n").unwrap();
+
+        let mut test = Test::with_dir(tmp.path());
+        test.app.search_params.stdin = Some(std::sync::Arc::new("foo one\nbar\nfoo two\n".into()));
+        test.input("foo");
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("baz");
+        while test.app.search_rx.is_some() {
+            test.app.handle_events(true).unwrap();
+        }
+        assert_eq!(test.app.subs.len(), 1);
+        assert_eq!(test.app.subs[0].path, Path::new(STDIN_PATH));
+        assert_eq!(test.app.match_count, 2);
+
+        let state = test
+            .app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('o'), KeyModifiers::CONTROL));
+        assert_eq!(state.unwrap(), State::Continue);
+        assert_eq!(test.app.status(), Some("Can't open stdin in an editor"));
+
+        test.app.confirm_replace().unwrap();
+        assert_eq!(
+            test.app.report.as_ref().unwrap().files,
+            [(STDIN_PATH.into(), 2)]
+        );
+        assert_eq!(
+            test.app.take_output().as_deref(),
+            Some("baz one\nbar\nbaz two\n")
+        );
+        // neither the searched paths nor a file named for stdin are written
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "foo\n");
+        assert!(!Path::new(STDIN_PATH).exists());
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace_report() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "foo foo\n").unwrap();
+        std::fs::write(tmp.path().join("b.txt"), "bar\n").unwrap();
+        std::fs::write(tmp.path().join("c.txt"), "foo\n").unwrap();
+
+        let mut test = Test::with_dir(tmp.path());
+        test.input("foo");
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("baz");
+        // one file is in the results, the other is still pending
+        test.app.handle_events(true).unwrap();
+        assert_eq!(test.app.subs.len(), 1);
+        test.app.confirm_replace().unwrap();
+        assert!(test.app.search_rx.is_none());
+        assert_eq!(
+            test.app.report.as_ref().unwrap().files,
+            [(tmp.path().join("a.txt"), 2), (tmp.path().join("c.txt"), 1)]
+        );
+
+        let mut terminal = Terminal::new(TestBackend::new(50, 6)).unwrap();
+        terminal
+            .draw(|frame| _ = test.app.draw(frame).unwrap())
+            .unwrap();
+        assert_snapshot!(scrub_tmp(&tmp, terminal.backend()));
+
+        let state = test.app.handle_key_event(KeyCode::Char('x').into());
+        assert_eq!(state.unwrap(), State::Exit);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace_after_pattern_change() {
+        let tmp = tempfile::tempdir().unwrap();
+        for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+            std::fs::write(tmp.path().join(name), "foo bar\n").unwrap();
+        }
+        let read = |name| std::fs::read_to_string(tmp.path().join(name)).unwrap();
+
+        let mut test = Test::with_dir(tmp.path());
+        test.input("foo");
+        test.app.handle_events(true).unwrap();
+        assert_eq!(test.app.subs.len(), 1);
+
+        // an invalid pattern stops the search for the last one
+        test.input("\\");
+        assert!(test.app.finder.is_none());
+        assert!(test.app.search_rx.is_none());
+        test.app.confirm_replace().unwrap();
+        assert_eq!(test.app.report.take().unwrap().files, []);
+        assert_eq!(read("a.txt"), "foo bar\n");
+
+        // change the pattern while results for the old one are still streaming
+        test.app
+            .handle_key_event(KeyCode::Backspace.into())
+            .unwrap();
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+        for _ in 0..3 {
+            test.app
+                .handle_key_event(KeyCode::Backspace.into())
+                .unwrap();
+        }
+        test.input("bar");
+        test.app.handle_events(true).unwrap();
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("baz");
+        test.app.confirm_replace().unwrap();
+
+        assert_eq!(test.app.report.unwrap().files.len(), 4);
+        for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+            assert_eq!(read(name), "foo baz\n");
+        }
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace_capture() {
+        let tmp = stage_files();
+
+        let mut test = Test::with_dir(tmp.path());
+        test.input("This is");
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("${0}n't");
+
+        // await results from 2 files
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+
+        let mut terminal = Terminal::new(TestBackend::new(40, 20)).unwrap();
+        terminal
+            .draw(|frame| {
+                assert!(test.app.draw(frame).unwrap(), "Should need more results");
+            })
+            .unwrap();
+        assert_snapshot!(scrub_tmp(&tmp, terminal.backend()));
+
+        test.app.replace_all().unwrap();
+
+        let content = std::fs::read_to_string(tmp.path().join("file1.txt")).unwrap();
+        assert_eq!(
+            content,
+            "\
+This isn't line one.
+This isn't line two.
+This isn't line three.
+Line four.
+"
+        );
+
+        let content = std::fs::read_to_string(tmp.path().join("dir1").join("file2.txt")).unwrap();
+        assert_eq!(
+            content,
+            "\
+The first line.
+The second line.
+The third line.
+"
+        );
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace_rejected() {
+        let tmp = stage_files();
+
+        let mut test = Test::with_dir(tmp.path());
+        test.input("line");
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("replacement");
+
+        // await results from 2 files
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+
+        let toggle = KeyEvent::new(KeyCode::Char('r'), KeyModifiers::ALT);
+        let next = KeyEvent::new(KeyCode::Char('n'), KeyModifiers::ALT);
+
+        // reject the first match in each file
+        test.app.handle_key_event(toggle).unwrap();
+        for _ in 0..3 {
+            test.app.handle_key_event(next).unwrap();
+        }
+        test.app.handle_key_event(toggle).unwrap();
+
+        // toggling twice accepts the match again
+        test.app.handle_key_event(next).unwrap();
+        test.app.handle_key_event(toggle).unwrap();
+        test.app.handle_key_event(toggle).unwrap();
+
+        test.app.replace_all().unwrap();
+
+        let content = std::fs::read_to_string(tmp.path().join("file1.txt")).unwrap();
+        assert_eq!(
+            content,
+            "\
+This is line one.
+This is replacement two.
+This is replacement three.
+Line four.
+"
+        );
+
+        let content = std::fs::read_to_string(tmp.path().join("dir1").join("file2.txt")).unwrap();
+        assert_eq!(
+            content,
+            "\
+The first line.
+The second replacement.
+The third replacement.
+"
+        );
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace_file() {
+        let tmp = stage_files();
+
+        let mut test = Test::with_dir(tmp.path());
+        test.input("line");
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("replacement");
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+        assert_eq!(test.app.match_count, 6);
+
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Enter, KeyModifiers::ALT))
+            .unwrap();
+        assert_eq!(test.app.match_count, 3);
+        assert_eq!(test.app.subs.len(), 1);
+
+        let mut terminal = Terminal::new(TestBackend::new(50, 12)).unwrap();
+        terminal
+            .draw(|frame| {
+                test.app.draw(frame).unwrap();
+            })
+            .unwrap();
+        assert_snapshot!(scrub_tmp(&tmp, terminal.backend()));
+
+        let read = |p: &[&str]| {
+            let path = p.iter().fold(tmp.path().to_path_buf(), |p, s| p.join(s));
+            std::fs::read_to_string(path).unwrap()
+        };
+        assert_eq!(
+            read(&["dir1", "file2.txt"]),
+            "\
+The first replacement.
+The second replacement.
+The third replacement.
+"
+        );
+        assert_eq!(
+            read(&["file1.txt"]),
+            "\
+This is line one.
+This is line two.
+This is line three.
+Line four.
+"
+        );
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace_file_backup_exists() {
+        let tmp = stage_files();
+        let path = tmp.path().join("dir1").join("file2.txt");
+        std::fs::write(tmp.path().join("dir1").join("file2.txt.orig"), "existing").unwrap();
 
-        // await results from 2 files
+        let mut test = Test::with_config(
+            tmp.path(),
+            Config {
+                backup: Backup::Suffix(".orig".into()),
+                ..Default::default()
+            },
+        );
+        test.input("line");
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("replacement");
         test.app.handle_events(true).unwrap();
         test.app.handle_events(true).unwrap();
 
-        let mut terminal = Terminal::new(TestBackend::new(40, 20)).unwrap();
-        terminal
-            .draw(|frame| {
-                assert!(test.app.draw(frame).unwrap(), "Should need more results");
-            })
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Enter, KeyModifiers::ALT))
             .unwrap();
-        assert_snapshot!(terminal.backend());
+        // the file is left alone, and kept in the results
+        assert_eq!(
+            test.app.status(),
+            Some(format!("Skipped {}, backup exists", path.display()).as_str())
+        );
+        assert_eq!(test.app.match_count, 6);
+        assert_eq!(test.app.subs.len(), 2);
+        assert!(std::fs::read_to_string(&path).unwrap().contains("line"));
     }
 
     #[test]
     #[tracing_test::traced_test]
-    fn test_search_ast() {
+    fn test_cancel_search() {
         let mut test = Test::new();
-        test.input("$FN($$$ARGS)");
-
-        // await results from 2 files
-        test.app.handle_events(true).unwrap();
+        test.input("line");
         test.app.handle_events(true).unwrap();
+        assert_eq!(test.app.subs.len(), 1);
 
-        let mut terminal = Terminal::new(TestBackend::new(40, 20)).unwrap();
-        terminal
-            .draw(|frame| {
-                assert!(test.app.draw(frame).unwrap(), "Should need more results");
-            })
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::CONTROL))
             .unwrap();
-        assert_snapshot!(terminal.backend());
+        assert!(test.app.search_rx.is_none());
+        assert_eq!(test.app.status(), Some("Search cancelled"));
+        assert_eq!(test.app.status_summary(), "regex │ 1/3 matches in 1 file");
+        assert_eq!(test.app.subs.len(), 1, "results so far are kept");
     }
 
     #[test]
     #[tracing_test::traced_test]
-    fn test_search_multiline() {
-        let mut test = Test::new();
-        test.input("\\w+\\n\\w+");
+    fn test_cycle_sort() {
+        let tmp = tempfile::tempdir().unwrap();
+        let now = SystemTime::now();
+        for (name, text, age) in [
+            ("a.txt", "x\n", 2),
+            ("b.txt", "x\nx\nx\n", 3),
+            ("c.txt", "x\nx\n", 1),
+        ] {
+            let path = tmp.path().join(name);
+            std::fs::write(&path, text).unwrap();
+            let file = std::fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(now - Duration::from_secs(age * 60))
+                .unwrap();
+        }
+        let names = |app: &App| {
+            app.subs
+                .iter()
+                .map(|s| s.path.file_name().unwrap().to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
 
-        // Send ctrl-l to toggle multiline
-        test.app
-            .handle_key_event(KeyEvent::new(KeyCode::Char('l'), KeyModifiers::CONTROL))
-            .unwrap();
+        let mut test = Test::with_dir(tmp.path());
+        test.input("x");
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+        assert_eq!(names(&test.app), ["a.txt", "b.txt", "c.txt"]);
 
-        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
-        test.input("One");
+        // select the second match in c.txt
+        test.app.select(5);
+        let sort = KeyEvent::new(KeyCode::Char('s'), KeyModifiers::ALT);
 
-        // await results from 2 files
+        test.app.handle_key_event(sort).unwrap();
+        assert_eq!(names(&test.app), ["c.txt", "a.txt", "b.txt"]);
+        assert_eq!(test.app.selected, 1);
+        assert_eq!(test.app.status(), Some("Sorted by modified time"));
+
+        test.app.handle_key_event(sort).unwrap();
+        assert_eq!(names(&test.app), ["b.txt", "c.txt", "a.txt"]);
+        assert_eq!(test.app.selected, 4);
+
+        test.app.handle_key_event(sort).unwrap();
+        assert_eq!(names(&test.app), ["a.txt", "b.txt", "c.txt"]);
+        assert_eq!(test.app.selected, 5);
+
+        // results are sorted as they arrive
+        let config = Config {
+            sort: Sort::Matches,
+            ..Default::default()
+        };
+        let mut test = Test::with_config(tmp.path(), config);
+        test.input("x");
         test.app.handle_events(true).unwrap();
         test.app.handle_events(true).unwrap();
-
-        let mut terminal = Terminal::new(TestBackend::new(40, 20)).unwrap();
-        terminal
-            .draw(|frame| {
-                assert!(test.app.draw(frame).unwrap(), "Should need more results");
-            })
-            .unwrap();
-        assert_snapshot!(terminal.backend());
+        test.app.handle_events(true).unwrap();
+        assert_eq!(names(&test.app), ["b.txt", "c.txt", "a.txt"]);
     }
 
     #[test]
     #[tracing_test::traced_test]
-    // BUG: weird how these collapse, would expect full results until last one
-    // TODO: Show when results are truncated
-    fn test_search_results_full() {
-        let mut test = Test::new();
-        test.input("aaa");
-        // Use a smaller y size, so the results fill the page
-        let mut terminal = Terminal::new(TestBackend::new(40, 12)).unwrap();
+    fn test_filter_results() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "x foo\nx bar\nx foo\n").unwrap();
+        std::fs::write(tmp.path().join("b.txt"), "x bar\n").unwrap();
+        std::fs::write(tmp.path().join("foo.txt"), "x\n").unwrap();
 
+        let mut test = Test::with_dir(tmp.path());
+        test.input("x");
         test.app.handle_events(true).unwrap();
         test.app.handle_events(true).unwrap();
         test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+        assert_eq!(test.app.match_count, 5);
+        assert!(test.app.search_rx.is_none());
+
+        let filter = KeyEvent::new(KeyCode::Char('/'), KeyModifiers::ALT);
+        test.app.handle_key_event(filter).unwrap();
+        test.input("foo");
+        assert_eq!(
+            test.app.pattern_input.pattern(),
+            "x",
+            "pattern is unchanged"
+        );
+        assert_eq!(test.app.status_summary(), "regex │ 1/3 matches in 2 files");
 
+        let mut terminal = Terminal::new(TestBackend::new(40, 16)).unwrap();
         terminal
             .draw(|frame| {
-                assert!(test.app.draw(frame).unwrap(), "Should need more results");
+                test.app.draw(frame).unwrap();
             })
             .unwrap();
+        assert_snapshot!(scrub_tmp(&tmp, terminal.backend()));
+
+        // new results are filtered too
+        test.app.handle_key_event(filter).unwrap();
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL))
+            .unwrap();
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
+        assert_eq!(
+            test.app.status_summary(),
+            "regex (i) │ 1/3 matches in 2 files"
+        );
+
+        // clearing the filter restores everything
+        test.app.handle_key_event(filter).unwrap();
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('u'), KeyModifiers::CONTROL))
+            .unwrap();
+        assert_eq!(
+            test.app.status_summary(),
+            "regex (i) │ 1/5 matches in 3 files"
+        );
+        test.input("fo");
+        assert_eq!(
+            test.app.status_summary(),
+            "regex (i) │ 1/3 matches in 2 files"
+        );
+
+        // only the filtered, accepted matches are replaced
+        test.app.select(0);
+        test.app.toggle_selected();
+        test.app.handle_key_event(filter).unwrap();
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE))
+            .unwrap();
+        test.input("y");
+        test.app.replace_all().unwrap();
+        let read = |name| std::fs::read_to_string(tmp.path().join(name)).unwrap();
+        assert_eq!(read("a.txt"), "x foo\nx bar\ny foo\n");
+        assert_eq!(read("b.txt"), "x bar\n");
+        assert_eq!(read("foo.txt"), "y\n");
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_mouse() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "x\nfoo\nx\n").unwrap();
+        std::fs::write(tmp.path().join("b.txt"), "x\n").unwrap();
 
+        let mut test = Test::with_dir(tmp.path());
+        test.input("x");
+        test.app.handle_events(true).unwrap();
+        test.app.handle_events(true).unwrap();
         test.app.handle_events(true).unwrap();
 
+        let mut terminal = Terminal::new(TestBackend::new(40, 12)).unwrap();
         terminal
             .draw(|frame| {
-                assert!(
-                    !test.app.draw(frame).unwrap(),
-                    "Should not need more results"
-                );
+                test.app.draw(frame).unwrap();
             })
             .unwrap();
-        assert_snapshot!(terminal.backend());
+
+        let mouse = |kind, row| MouseEvent {
+            kind,
+            column: 10,
+            row,
+            modifiers: KeyModifiers::empty(),
+        };
+        let click = |row| mouse(MouseEventKind::Down(MouseButton::Left), row);
+
+        // the second line of a.txt
+        test.app.handle_mouse_event(click(6));
+        assert_eq!(test.app.selected, 1);
+        // the title of b.txt
+        test.app.handle_mouse_event(click(8));
+        assert_eq!(test.app.selected, 2);
+        // the search input
+        test.app.handle_mouse_event(click(2));
+        assert_eq!(test.app.selected, 2);
+
+        test.app
+            .handle_mouse_event(mouse(MouseEventKind::ScrollDown, 5));
+        assert_eq!(test.app.scroll, 1);
+        test.app
+            .handle_mouse_event(mouse(MouseEventKind::ScrollDown, 5));
+        assert_eq!(test.app.scroll, 1);
+        test.app
+            .handle_mouse_event(mouse(MouseEventKind::ScrollUp, 5));
+        assert_eq!(test.app.scroll, 0);
     }
 
     #[test]
     #[tracing_test::traced_test]
-    fn test_replace() {
+    fn test_skip_file() {
         let tmp = stage_files();
 
         let mut test = Test::with_dir(tmp.path());
         test.input("line");
         test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
         test.input("replacement");
-
-        // await results from 2 files
         test.app.handle_events(true).unwrap();
         test.app.handle_events(true).unwrap();
 
-        let mut terminal = Terminal::new(TestBackend::new(40, 20)).unwrap();
-        terminal
-            .draw(|frame| {
-                assert!(test.app.draw(frame).unwrap(), "Should need more results");
-            })
+        // select the last match, in the last file
+        test.app.select(5);
+        test.app.scroll = 1;
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('k'), KeyModifiers::ALT))
             .unwrap();
-        assert_snapshot!(scrub_tmp(&tmp, terminal.backend()));
+        assert_eq!(test.app.subs.len(), 1);
+        assert_eq!(test.app.match_count, 3);
+        assert_eq!(test.app.selected, 2);
+        assert_eq!(test.app.scroll, 0);
 
         test.app.replace_all().unwrap();
-
-        let content = std::fs::read_to_string(tmp.path().join("file1.txt")).unwrap();
+        let read = |p: &[&str]| {
+            let path = p.iter().fold(tmp.path().to_path_buf(), |p, s| p.join(s));
+            std::fs::read_to_string(path).unwrap()
+        };
         assert_eq!(
-            content,
+            read(&["dir1", "file2.txt"]),
             "\
-This is replacement one.
-This is replacement two.
-This is replacement three.
+The first replacement.
+The second replacement.
+The third replacement.
+"
+        );
+        assert_eq!(
+            read(&["file1.txt"]),
+            "\
+This is line one.
+This is line two.
+This is line three.
 Line four.
 "
         );
+    }
 
-        let content = std::fs::read_to_string(tmp.path().join("dir1").join("file2.txt")).unwrap();
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace_backup() {
+        let tmp = stage_files();
+        // an existing backup should never be overwritten
+        std::fs::write(tmp.path().join("file1.txt.orig"), "existing").unwrap();
+
+        let mut test = Test::with_config(
+            tmp.path(),
+            Config {
+                backup: Backup::Suffix(".orig".into()),
+                ..Default::default()
+            },
+        );
+        test.input("line");
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("replacement");
+        test.app.replace_all().unwrap();
+
+        let read = |p: &[&str]| {
+            let path = p.iter().fold(tmp.path().to_path_buf(), |p, s| p.join(s));
+            std::fs::read_to_string(path).unwrap()
+        };
+        assert_eq!(read(&["file1.txt.orig"]), "existing");
         assert_eq!(
-            content,
+            read(&["file1.txt"]),
+            "\
+This is line one.
+This is line two.
+This is line three.
+Line four.
+"
+        );
+        assert_eq!(
+            read(&["dir1", "file2.txt.orig"]),
+            "\
+The first line.
+The second line.
+The third line.
+"
+        );
+        assert_eq!(
+            read(&["dir1", "file2.txt"]),
             "\
 The first replacement.
 The second replacement.
@@ -908,46 +4474,183 @@ The third replacement.
 
     #[test]
     #[tracing_test::traced_test]
-    fn test_replace_capture() {
-        let tmp = stage_files();
+    fn test_history() {
+        let tmp = tempfile::tempdir().unwrap();
 
-        let mut test = Test::with_dir(tmp.path());
-        test.input("This is");
+        let mut test = Test::new();
+        test.app.load_history(tmp.path());
+        test.input("foo");
         test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
-        test.input("${0}n't");
+        test.input("bar");
+        test.app.save_history(tmp.path()).unwrap();
 
-        // await results from 2 files
+        let mut test = Test::new();
+        test.app.load_history(tmp.path());
+        test.input("baz");
+        test.app.save_history(tmp.path()).unwrap();
+
+        let read = |p| std::fs::read_to_string(tmp.path().join(p)).unwrap();
+        assert_eq!(read("search_history.txt"), "foo\nbaz\n");
+        assert_eq!(read("replace_history.txt"), "bar\n");
+
+        let mut test = Test::new();
+        test.app.load_history(tmp.path());
+        test.app.handle_key_event(KeyCode::Up.into()).unwrap();
+        assert_eq!(test.app.pattern_input.pattern(), "baz");
+        test.app.handle_key_event(KeyCode::Up.into()).unwrap();
+        assert_eq!(test.app.pattern_input.pattern(), "foo");
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.app.handle_key_event(KeyCode::Up.into()).unwrap();
+        assert_eq!(test.app.replacement_input.pattern(), "bar");
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_key_seq() {
+        let mut config = Config::default();
+        let mut bind = |keys: &str, action| {
+            config
+                .keys
+                .insert(keys.to_string().try_into().unwrap(), action)
+        };
+        bind("g g", Action::ScrollTop);
+        bind("c-x c-k", Action::DeleteLine);
+
+        let tmp = stage_files();
+        let mut test = Test::with_config(tmp.path(), config);
+        test.input("line");
         test.app.handle_events(true).unwrap();
         test.app.handle_events(true).unwrap();
+        assert_eq!(test.app.subs.len(), 2);
 
-        let mut terminal = Terminal::new(TestBackend::new(40, 20)).unwrap();
+        test.app.scroll = 1;
+        test.input("gg");
+        assert_eq!(test.app.scroll, 0);
+        assert_eq!(test.app.pattern_input.pattern(), "line");
+
+        // keys that don't complete a sequence are handled one at a time
+        test.input("gx");
+        assert_eq!(test.app.pattern_input.pattern(), "linegx");
+
+        // a pending key is handled alone once the sequence times out
+        test.input("g");
+        assert_eq!(test.app.pattern_input.pattern(), "linegx");
+        test.app.flush_keys().unwrap();
+        assert_eq!(test.app.pattern_input.pattern(), "linegxg");
+
+        // a single key binding can begin a sequence
+        let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+        test.app.handle_key_event(ctrl('x')).unwrap();
+        test.app.handle_key_event(ctrl('k')).unwrap();
+        assert_eq!(test.app.pattern_input.pattern(), "");
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_reload_config() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("lasr.toml");
+        std::fs::write(&path, "[keys]\nc-t = \"exit\"\n").unwrap();
+        let key = |s: &str| s.to_string().try_into().unwrap();
+
+        let mut test = Test::new();
+        test.app.watch_config(path.clone()).unwrap();
+        std::fs::write(
+            &path,
+            "[theme]\nbase.fg = \"6\"\n[keys]\nc-t = \"scroll_top\"\n",
+        )
+        .unwrap();
+        test.app
+            .config_rx
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap();
+        test.app.reload_config();
+        assert_eq!(test.app.status(), Some("Reloaded config"));
+        assert_eq!(
+            test.app.config.keys.get(&key("c-t")),
+            Some(&Action::ScrollTop)
+        );
+        // defaults are merged in
+        assert_eq!(test.app.config.keys.get(&key("esc")), Some(&Action::Exit));
+        assert_eq!(
+            test.app.config.theme.base.fg,
+            Some(ratatui::style::Color::Indexed(6))
+        );
+
+        // an invalid config is ignored
+        std::fs::write(&path, "[keys\n").unwrap();
+        test.app.reload_config();
+        assert!(
+            test.app
+                .status()
+                .is_some_and(|s| s.starts_with("Failed to reload config")),
+            "{:?}",
+            test.app.status()
+        );
+        assert_eq!(
+            test.app.config.keys.get(&key("c-t")),
+            Some(&Action::ScrollTop)
+        );
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_history_newline() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let mut test = Test::new();
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("foo");
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('j'), KeyModifiers::CONTROL))
+            .unwrap();
+        test.input("bar");
+        test.app.save_history(tmp.path()).unwrap();
+
+        let read = |p| std::fs::read_to_string(tmp.path().join(p)).unwrap();
+        assert_eq!(read("replace_history.txt"), "foo\u{2424}bar\n");
+
+        let mut test = Test::new();
+        test.app.load_history(tmp.path());
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.app.handle_key_event(KeyCode::Up.into()).unwrap();
+        assert_eq!(test.app.replacement_input.pattern(), "foo\nbar");
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_replace_newline() {
+        let tmp = stage_files();
+
+        let mut test = Test::with_dir(tmp.path());
+        test.input("line (t)");
+        test.app.handle_key_event(KeyCode::Tab.into()).unwrap();
+        test.input("line");
+        test.app
+            .handle_key_event(KeyEvent::new(KeyCode::Char('j'), KeyModifiers::CONTROL))
+            .unwrap();
+        test.input("$1");
+        test.app.handle_events(true).unwrap();
+
+        let mut terminal = Terminal::new(TestBackend::new(40, 14)).unwrap();
         terminal
             .draw(|frame| {
-                assert!(test.app.draw(frame).unwrap(), "Should need more results");
+                test.app.draw(frame).unwrap();
             })
             .unwrap();
         assert_snapshot!(scrub_tmp(&tmp, terminal.backend()));
 
         test.app.replace_all().unwrap();
-
         let content = std::fs::read_to_string(tmp.path().join("file1.txt")).unwrap();
         assert_eq!(
             content,
             "\
-This isn't line one.
-This isn't line two.
-This isn't line three.
+This is line one.
+This is line
+two.
+This is line
+three.
 Line four.
-"
-        );
-
-        let content = std::fs::read_to_string(tmp.path().join("dir1").join("file2.txt")).unwrap();
-        assert_eq!(
-            content,
-            "\
-The first line.
-The second line.
-The third line.
 "
         );
     }